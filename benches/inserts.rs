@@ -1,6 +1,6 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 
-use hashseq::HashSeq;
+use hashseq::{EncodableOp, HashSeq};
 
 fn prepend(n: usize) {
     let mut seq = HashSeq::default();
@@ -33,6 +33,17 @@ fn insert_random(n: usize) {
     }
 }
 
+/// A peer typing just before the last of `n` chars, all but which we removed
+/// in the meantime, so everything before their inserts is tombstones.
+fn inserts_after_tombstones(n: usize) -> (HashSeq, Vec<EncodableOp>) {
+    let mut seq = HashSeq::default();
+    seq.insert_batch(0, std::iter::repeat_n('a', n));
+    let mut peer = seq.clone();
+    let ops = (0..100).flat_map(|_| peer.insert(n - 1, 'b')).collect();
+    seq.remove_batch(0, n - 1);
+    (seq, ops)
+}
+
 fn append_growth(c: &mut Criterion) {
     for n in [1, 10, 100, 1000] {
         c.bench_function(&format!("index-append-{n}"), |b| {
@@ -65,11 +76,30 @@ fn insert_random_growth(c: &mut Criterion) {
     }
 }
 
+fn apply_after_tombstones_growth(c: &mut Criterion) {
+    for n in [100, 1000, 10000] {
+        let (seq, ops) = inserts_after_tombstones(n);
+        c.bench_function(&format!("apply-after-tombstones {n}"), |b| {
+            b.iter_batched(
+                || (seq.clone(), ops.clone()),
+                |(mut seq, ops)| {
+                    for op in ops {
+                        seq.apply_op(black_box(op));
+                    }
+                    seq
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+}
+
 criterion_group!(
     benches,
     append_growth,
     prepend_growth,
     insert_middle_growth,
-    insert_random_growth
+    insert_random_growth,
+    apply_after_tombstones_growth
 );
 criterion_main!(benches);
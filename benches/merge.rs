@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::Path;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use flate2::read::GzDecoder;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+
+use hashseq::HashSeq;
+
+const TRACES_DIR: &str = "../editing-traces/sequential_traces";
+const TRACES: [&str; 3] = ["automerge-paper", "rustcode", "seph-blog1"];
+
+/// (position, delete_count, insert_content)
+#[derive(Deserialize)]
+struct Patch(usize, usize, String);
+
+#[derive(Deserialize)]
+struct Txn {
+    patches: Vec<Patch>,
+}
+
+#[derive(Deserialize)]
+struct Trace {
+    txns: Vec<Txn>,
+}
+
+fn load_trace(name: &str) -> Option<Vec<Patch>> {
+    let file = std::fs::File::open(Path::new(TRACES_DIR).join(format!("{name}.json.gz"))).ok()?;
+    let mut json = Vec::new();
+    GzDecoder::new(file).read_to_end(&mut json).ok()?;
+    let trace: Trace = serde_json::from_slice(&json).ok()?;
+    Some(trace.txns.into_iter().flat_map(|txn| txn.patches).collect())
+}
+
+/// Someone typing: mostly appending words at the cursor, sometimes moving it
+/// or deleting a few characters. Used when the editing traces aren't checked out.
+fn synthetic_trace(n: usize) -> Vec<Patch> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(n as u64);
+    let mut len = 0;
+    let mut cursor = 0;
+    let mut patches = Vec::new();
+    while len < n {
+        if rng.gen_bool(0.1) {
+            cursor = rng.gen_range(0..=len);
+        }
+        if cursor > 0 && rng.gen_bool(0.1) {
+            let del = rng.gen_range(1..=cursor.min(5));
+            cursor -= del;
+            len -= del;
+            patches.push(Patch(cursor, del, String::new()));
+        } else {
+            let word: String = (0..rng.gen_range(1..10)).map(|_| rng.gen_range('a'..='z')).collect();
+            patches.push(Patch(cursor, 0, format!("{word} ")));
+            cursor += word.len() + 1;
+            len += word.len() + 1;
+        }
+    }
+    patches
+}
+
+fn replay(seq: &mut HashSeq, patches: &[Patch]) {
+    for Patch(pos, del, ins) in patches {
+        seq.remove_batch(*pos, *del);
+        seq.insert_batch(*pos, ins.chars());
+    }
+}
+
+fn traces() -> Vec<(String, Vec<Patch>)> {
+    let mut traces: Vec<(String, Vec<Patch>)> = TRACES
+        .iter()
+        .filter_map(|name| Some((name.to_string(), load_trace(name)?)))
+        .collect();
+    if traces.is_empty() {
+        traces.push(("synthetic-100k".to_string(), synthetic_trace(100_000)));
+    }
+    traces
+}
+
+/// Merging a whole replica into an empty one, compared with applying its ops,
+/// which rehashes every character.
+fn merge_into_empty(c: &mut Criterion) {
+    for (name, patches) in traces() {
+        let mut seq = HashSeq::default();
+        replay(&mut seq, &patches);
+
+        c.bench_function(&format!("merge-into-empty {name}"), |b| {
+            b.iter(|| {
+                let mut merged = HashSeq::default();
+                merged.merge(black_box(&seq));
+                merged
+            });
+        });
+
+        let ops = seq.ops_since(&BTreeSet::new());
+        c.bench_function(&format!("apply-ops-into-empty {name}"), |b| {
+            b.iter_batched(
+                || ops.clone(),
+                |ops| {
+                    let mut applied = HashSeq::default();
+                    for op in black_box(ops) {
+                        applied.apply_op(op);
+                    }
+                    applied
+                },
+                BatchSize::LargeInput,
+            );
+        });
+
+        c.bench_function(&format!("verify-ids {name}"), |b| {
+            b.iter(|| black_box(&seq).verify_ids());
+        });
+    }
+}
+
+/// Two replicas that share the first half of the trace and each replay half
+/// of the rest: most of the merged runs are suffixes of runs we have.
+fn merge_diverged(c: &mut Criterion) {
+    for (name, patches) in traces() {
+        let (shared, rest) = patches.split_at(patches.len() / 2);
+        let (ours, theirs) = rest.split_at(rest.len() / 2);
+        let mut base = HashSeq::default();
+        replay(&mut base, shared);
+        let mut seq_a = base.clone();
+        replay(&mut seq_a, ours);
+        let mut seq_b = base;
+        // Both halves start from the shared text, clamp positions to stay in bounds.
+        for Patch(pos, del, ins) in theirs {
+            let pos = (*pos).min(seq_b.len());
+            seq_b.remove_batch(pos, *del);
+            seq_b.insert_batch(pos, ins.chars());
+        }
+
+        c.bench_function(&format!("merge-diverged {name}"), |b| {
+            b.iter_batched(
+                || seq_a.clone(),
+                |mut merged| {
+                    merged.merge(black_box(&seq_b));
+                    merged
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+}
+
+criterion_group!(benches, merge_into_empty, merge_diverged);
+criterion_main!(benches);
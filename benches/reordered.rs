@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use hashseq::{EncodableOp, HashNode, HashSeq};
+
+/// Every node of a random editing session, in causal order.
+fn trace(n: usize) -> Vec<HashNode> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(n as u64);
+    let mut seq = HashSeq::default();
+    let mut ops = Vec::new();
+    for _ in 0..n {
+        if seq.is_empty() || rng.gen_bool(0.8) {
+            let p = rng.gen_range(0..=seq.len());
+            ops.extend(seq.insert(p, 'a'));
+        } else {
+            let p = rng.gen_range(0..seq.len());
+            ops.extend(seq.remove(p));
+        }
+    }
+    ops.into_iter()
+        .flat_map(|op| match op {
+            EncodableOp::Run(run) => run.decompress(),
+            EncodableOp::Node(node) => vec![node],
+        })
+        .collect()
+}
+
+fn apply_all(nodes: Vec<HashNode>) -> HashSeq {
+    let mut seq = HashSeq::default();
+    for node in nodes {
+        seq.apply(node);
+    }
+    seq
+}
+
+fn in_order(c: &mut Criterion) {
+    for n in [100, 1000, 10000] {
+        let nodes = trace(n);
+        c.bench_function(&format!("deliver-in-order {n}"), |b| {
+            b.iter_batched(
+                || nodes.clone(),
+                |nodes| apply_all(black_box(nodes)),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+/// Worst case for the orphan buffer: everything but the first node is
+/// orphaned until the very last delivery, then applied in one cascade.
+fn reversed(c: &mut Criterion) {
+    for n in [100, 1000, 10000] {
+        let mut nodes = trace(n);
+        nodes.reverse();
+        c.bench_function(&format!("deliver-reversed {n}"), |b| {
+            b.iter_batched(
+                || nodes.clone(),
+                |nodes| apply_all(black_box(nodes)),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+fn shuffled(c: &mut Criterion) {
+    for n in [100, 1000, 10000] {
+        let mut nodes = trace(n);
+        nodes.shuffle(&mut rand::rngs::StdRng::seed_from_u64(0));
+        c.bench_function(&format!("deliver-shuffled {n}"), |b| {
+            b.iter_batched(
+                || nodes.clone(),
+                |nodes| apply_all(black_box(nodes)),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+criterion_group!(benches, in_order, reversed, shuffled);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use hashseq::HashSeq;
+
+fn doc(n: usize) -> HashSeq {
+    let mut seq = HashSeq::default();
+    seq.insert_batch(0, std::iter::repeat_n('a', n));
+    seq
+}
+
+// A transaction journals its edits to roll them back, so a single edit made
+// through one should cost about what the same edit made directly does.
+fn single_edit_growth(c: &mut Criterion) {
+    for n in [100, 1000, 10000, 100000] {
+        let seq = doc(n);
+        c.bench_function(&format!("direct-insert {n}"), |b| {
+            b.iter_batched(
+                || seq.clone(),
+                |mut seq| {
+                    seq.insert(black_box(n / 2), 'b');
+                    seq
+                },
+                BatchSize::LargeInput,
+            );
+        });
+        c.bench_function(&format!("transaction-insert {n}"), |b| {
+            b.iter_batched(
+                || seq.clone(),
+                |mut seq| {
+                    let _ = seq.transaction(|tx| {
+                        tx.insert(black_box(n / 2), 'b');
+                        Ok::<_, ()>(())
+                    });
+                    seq
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+}
+
+criterion_group!(benches, single_edit_growth);
+criterion_main!(benches);
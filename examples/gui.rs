@@ -67,7 +67,7 @@ impl Example {
                 // User A creates a document
                 type_at(&mut seq_a, 0, "function() { return x; }");
                 // Sync so B has the same
-                seq_b.merge(seq_a.clone());
+                seq_b.merge(&seq_a);
                 // User A adds parameter
                 type_at(&mut seq_a, 9, "name");
                 // User B inserts code inside the function body (between { and return)
@@ -76,7 +76,7 @@ impl Example {
             Example::ForkAndMerge => {
                 // Shared base: a todo list
                 type_at(&mut seq_a, 0, "TODO:\n- Buy groceries\n");
-                seq_b.merge(seq_a.clone());
+                seq_b.merge(&seq_a);
 
                 // User A adds items
                 let pos_a = seq_a.len();
@@ -169,15 +169,15 @@ impl Application for Demo {
                 // Handled by global tick
             }
             Message::MergeAtoB => {
-                self.seq_b.merge(self.seq_a.clone());
+                self.seq_b.merge(&self.seq_a);
             }
             Message::MergeBtoA => {
-                self.seq_a.merge(self.seq_b.clone());
+                self.seq_a.merge(&self.seq_b);
             }
             Message::Sync => {
                 let seq_a = self.seq_a.clone();
-                self.seq_a.merge(self.seq_b.clone());
-                self.seq_b.merge(seq_a);
+                self.seq_a.merge(&self.seq_b);
+                self.seq_b.merge(&seq_a);
             }
             Message::ShowDependencies(v) => {
                 self.show_dependencies = v;
@@ -72,8 +72,12 @@ fn main() {
         }
 
         match event {
-            Trace::Insert(idx, c) => seq.insert(*idx, *c),
-            Trace::Delete(idx) => seq.remove(*idx),
+            Trace::Insert(idx, c) => {
+                seq.insert(*idx, *c);
+            }
+            Trace::Delete(idx) => {
+                seq.remove(*idx);
+            }
         }
     }
 
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Cursor, Element, Gravity, HashSeq};
+
+/// Whether an annotated range grows to take in text inserted right at its edges.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Expand {
+    /// Text inserted at either edge stays outside, like a comment.
+    #[default]
+    None,
+    /// Text inserted at the start is taken in.
+    Start,
+    /// Text inserted at the end is taken in, like bold while typing.
+    End,
+    /// Text inserted at either edge is taken in.
+    Both,
+}
+
+impl Expand {
+    /// The gravities of the start and end cursors of a range that expands like this.
+    pub(crate) fn gravities(self) -> (Gravity, Gravity) {
+        match self {
+            Expand::None => (Gravity::Right, Gravity::Left),
+            Expand::Start => (Gravity::Left, Gravity::Left),
+            Expand::End => (Gravity::Right, Gravity::Right),
+            Expand::Both => (Gravity::Left, Gravity::Right),
+        }
+    }
+}
+
+/// A payload attached to a range of a `HashSeq`. The edges are cursors, so
+/// the range follows the text through local edits and merges, and shrinks
+/// to nothing at the right spot if all of its text is removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation<P> {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub payload: P,
+}
+
+impl<P> Annotation<P> {
+    /// Annotate the elements in `range` as they are in `seq` now.
+    pub fn new<T: Element>(
+        seq: &HashSeq<T>,
+        range: Range<usize>,
+        expand: Expand,
+        payload: P,
+    ) -> Self {
+        let (start, end) = expand.gravities();
+        Self {
+            start: seq.cursor_at(range.start, start),
+            end: seq.cursor_at(range.end, end),
+            payload,
+        }
+    }
+
+    /// The elements the annotation covers in `seq` now. `None` if `seq` hasn't
+    /// seen the nodes the edges are anchored on.
+    pub fn range<T: Element>(&self, seq: &HashSeq<T>) -> Option<Range<usize>> {
+        let start = seq.cursor_index(&self.start)?;
+        let end = seq.cursor_index(&self.end)?;
+        // Text inserted between the edges of an empty range that doesn't
+        // expand isn't taken in.
+        Some(start..end.max(start))
+    }
+}
+
+/// Annotations on a single `HashSeq`, keyed by whatever the application
+/// identifies them with. The store only holds ids, so the same annotations
+/// resolve on every replica that has seen the text they're anchored on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotations<K: Ord, P> {
+    annotations: BTreeMap<K, Annotation<P>>,
+}
+
+impl<K: Ord, P> Default for Annotations<K, P> {
+    fn default() -> Self {
+        Self {
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, P> Annotations<K, P> {
+    pub fn len(&self) -> usize {
+        self.annotations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.annotations.is_empty()
+    }
+
+    /// Annotate `range` of `seq`, replacing any annotation under `key`.
+    pub fn annotate<T: Element>(
+        &mut self,
+        seq: &HashSeq<T>,
+        key: K,
+        range: Range<usize>,
+        expand: Expand,
+        payload: P,
+    ) -> Option<Annotation<P>> {
+        self.insert(key, Annotation::new(seq, range, expand, payload))
+    }
+
+    /// Add an annotation made elsewhere, e.g. received from a peer.
+    pub fn insert(&mut self, key: K, annotation: Annotation<P>) -> Option<Annotation<P>> {
+        self.annotations.insert(key, annotation)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<Annotation<P>> {
+        self.annotations.remove(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Annotation<P>> {
+        self.annotations.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Annotation<P>)> + '_ {
+        self.annotations.iter()
+    }
+
+    /// The current range of the annotation under `key`.
+    pub fn range<T: Element>(&self, seq: &HashSeq<T>, key: &K) -> Option<Range<usize>> {
+        self.annotations.get(key)?.range(seq)
+    }
+
+    /// The annotations covering the element at `idx`, in key order.
+    /// Resolves every annotation, so it's O(annotations * log n).
+    pub fn covering<'a, T: Element>(
+        &'a self,
+        seq: &'a HashSeq<T>,
+        idx: usize,
+    ) -> impl Iterator<Item = (&'a K, &'a Annotation<P>)> + 'a {
+        self.annotations
+            .iter()
+            .filter(move |(_, annotation)| annotation.range(seq).is_some_and(|r| r.contains(&idx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[test]
+    fn test_edges() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "one two three".chars());
+        let mut notes = Annotations::default();
+        for (key, expand) in [Expand::None, Expand::Start, Expand::End, Expand::Both]
+            .into_iter()
+            .enumerate()
+        {
+            notes.annotate(&seq, key, 4..7, expand, "two");
+        }
+
+        seq.insert(7, ']');
+        seq.insert(4, '[');
+        assert_eq!(seq.to_string(), "one [two] three");
+        let text = |key| seq.slice(notes.range(&seq, &key).unwrap());
+        assert_eq!(text(0), "two");
+        assert_eq!(text(1), "[two");
+        assert_eq!(text(2), "two]");
+        assert_eq!(text(3), "[two]");
+
+        assert_eq!(
+            notes.covering(&seq, 4).map(|(k, _)| *k).collect::<Vec<_>>(),
+            [1, 3]
+        );
+        assert_eq!(notes.covering(&seq, 5).count(), 4);
+        assert_eq!(notes.covering(&seq, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_removing_the_edges() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "one two three".chars());
+        let mut notes = Annotations::default();
+        notes.annotate(&seq, "note", 4..7, Expand::None, ());
+
+        seq.remove_batch(2, 3);
+        assert_eq!(seq.to_string(), "onwo three");
+        assert_eq!(seq.slice(notes.range(&seq, &"note").unwrap()), "wo");
+        seq.remove_batch(3, 2);
+        assert_eq!(seq.to_string(), "onwthree");
+        assert_eq!(seq.slice(notes.range(&seq, &"note").unwrap()), "w");
+
+        seq.remove(2);
+        assert_eq!(notes.range(&seq, &"note"), Some(2..2));
+        seq.insert_batch(2, "__".chars());
+        assert_eq!(notes.range(&seq, &"note"), Some(2..2));
+        assert_eq!(notes.covering(&seq, 2).count(), 0);
+    }
+
+    #[test]
+    fn test_unknown_anchors() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        let mut notes = Annotations::default();
+        notes.annotate(&seq, 0, 1..2, Expand::None, ());
+        let fresh: HashSeq = HashSeq::default();
+        assert_eq!(notes.range(&fresh, &0), None);
+        assert_eq!(notes.range(&seq, &1), None);
+    }
+
+    #[quickcheck]
+    fn prop_annotations_converge(
+        base: String,
+        range: (u8, u8),
+        edits_a: Vec<(bool, u8, char)>,
+        edits_b: Vec<(bool, u8, char)>,
+    ) -> bool {
+        fn edit(seq: &mut HashSeq, edits: &[(bool, u8, char)]) {
+            for &(insert_or_remove, idx, ch) in edits {
+                let idx = idx as usize;
+                if insert_or_remove {
+                    seq.insert(idx.min(seq.len()), ch);
+                } else if !seq.is_empty() {
+                    seq.remove(idx.min(seq.len() - 1));
+                }
+            }
+        }
+
+        let mut a = HashSeq::default();
+        a.insert_batch(0, base.chars());
+        let mut b = a.clone();
+        let (start, end) = (range.0 as usize, range.1 as usize);
+        let (start, end) = (start.min(end).min(a.len()), start.max(end).min(a.len()));
+        let mut notes = Annotations::default();
+        notes.annotate(&a, (), start..end, Expand::End, ());
+        let covered: Vec<_> = a
+            .iter_ids()
+            .skip(start)
+            .take(end - start)
+            .copied()
+            .collect();
+
+        edit(&mut a, &edits_a);
+        edit(&mut b, &edits_b);
+        a.merge(&b);
+        b.merge(&a);
+
+        // Both replicas agree, and whatever survived of the text is still covered.
+        let range = notes.range(&a, &()).unwrap();
+        range == notes.range(&b, &()).unwrap()
+            && covered
+                .iter()
+                .filter_map(|id| a.iter_ids().position(|x| x == id))
+                .all(|idx| range.contains(&idx))
+    }
+}
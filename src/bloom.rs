@@ -0,0 +1,192 @@
+use crate::{Element, HashSeq, Id};
+
+/// A Bloom filter over node ids, used as a compact "what I have" summary.
+///
+/// Ids are already uniform BLAKE3 hashes, so the bit positions are derived
+/// straight from the id bytes by double hashing instead of rehashing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Most hash functions a filter uses. Past this, `contains` costs more
+    /// than the lower false positive rate is worth.
+    pub const MAX_HASHES: u32 = 32;
+
+    /// An empty filter sized for `capacity` ids at the given false positive rate.
+    pub fn with_capacity(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let capacity = capacity.max(1) as f64;
+        let num_bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0);
+        let num_hashes = (num_bits / capacity * ln2)
+            .round()
+            .clamp(1.0, Self::MAX_HASHES as f64);
+        Self {
+            bits: vec![0; (num_bits as usize).div_ceil(64)],
+            num_hashes: num_hashes as u32,
+        }
+    }
+
+    /// Rebuild a filter from its raw parts, e.g. when decoding.
+    pub fn from_parts(bits: Vec<u64>, num_hashes: u32) -> Self {
+        assert!(!bits.is_empty(), "a bloom filter needs at least one word");
+        assert!(
+            (1..=Self::MAX_HASHES).contains(&num_hashes),
+            "a bloom filter needs 1 to {} hashes",
+            Self::MAX_HASHES
+        );
+        Self { bits, num_hashes }
+    }
+
+    /// A filter over every node id in `seq` (run elements, roots, befores and removes).
+    pub fn from_seq<T: Element>(seq: &HashSeq<T>, false_positive_rate: f64) -> Self {
+        let ids: Vec<&Id> = seq.node_ids().collect();
+        let mut filter = Self::with_capacity(ids.len(), false_positive_rate);
+        for id in ids {
+            filter.insert(id);
+        }
+        filter
+    }
+
+    pub fn bits(&self) -> &[u64] {
+        &self.bits
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    pub fn insert(&mut self, id: &Id) {
+        for bit in bit_positions(id, self.bits.len(), self.num_hashes) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, id: &Id) -> bool {
+        bit_positions(id, self.bits.len(), self.num_hashes)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+fn bit_positions(id: &Id, num_words: usize, num_hashes: u32) -> impl Iterator<Item = usize> {
+    let h1 = u64::from_le_bytes(id.0[0..8].try_into().unwrap());
+    let h2 = u64::from_le_bytes(id.0[8..16].try_into().unwrap()) | 1;
+    let num_bits = num_words as u64 * 64;
+    (0..num_hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodableOp;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeSet;
+
+    fn edit(seq: &mut HashSeq, ops: &[(bool, u8, char)]) {
+        for &(insert_or_remove, idx, elem) in ops {
+            let idx = idx as usize;
+            if insert_or_remove {
+                seq.insert(idx.min(seq.len()), elem);
+            } else if !seq.is_empty() {
+                seq.remove(idx.min(seq.len() - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, (0..1000).map(|i| char::from(b'a' + (i % 26) as u8)));
+
+        let filter = BloomFilter::from_seq(&seq, 0.01);
+        assert!(seq.node_ids().all(|id| filter.contains(id)));
+
+        let mut other = HashSeq::default();
+        other.insert_batch(0, (0..1000).map(|i| char::from(b'A' + (i % 26) as u8)));
+        let false_positives = other.node_ids().filter(|id| filter.contains(id)).count();
+        assert!(false_positives < 30, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn test_false_positives_surface_as_orphans() {
+        let mut a = HashSeq::default();
+        a.insert_batch(0, "hello".chars());
+        let mut b = a.clone();
+        let ops = a.insert_batch(5, " world".chars());
+        let EncodableOp::Run(run) = &ops[0] else {
+            panic!("expected a run, got {:?}", ops[0]);
+        };
+
+        // Force a false positive on the first new char.
+        let mut filter = BloomFilter::from_seq(&b, 0.001);
+        filter.insert(&run.elements[0]);
+
+        // Everything after it is still sent, but can't be applied without it.
+        for op in a.ops_missing_from(&filter) {
+            b.apply_op(op);
+        }
+        let missing = b.missing_dependencies();
+        assert_eq!(missing, BTreeSet::from([run.elements[0]]));
+
+        for op in a.ops_for_ids(&missing) {
+            b.apply_op(op);
+        }
+        assert!(b.orphans().is_empty());
+        assert_eq!(b.iter().collect::<String>(), "hello world");
+    }
+
+    #[quickcheck]
+    fn prop_missing_ops_converge(
+        base: Vec<(bool, u8, char)>,
+        ops_a: Vec<(bool, u8, char)>,
+        ops_b: Vec<(bool, u8, char)>,
+    ) {
+        let mut a = HashSeq::default();
+        edit(&mut a, &base);
+        let mut b = a.clone();
+        edit(&mut a, &ops_a);
+        edit(&mut b, &ops_b);
+
+        // With no false positives a single round in each direction is enough.
+        let filter_a = BloomFilter::from_seq(&a, 1e-9);
+        let filter_b = BloomFilter::from_seq(&b, 1e-9);
+        let to_b = a.ops_missing_from(&filter_b);
+        let to_a = b.ops_missing_from(&filter_a);
+        for op in to_a {
+            a.apply_op(op);
+        }
+        for op in to_b {
+            b.apply_op(op);
+        }
+
+        assert!(a.orphans().is_empty());
+        assert!(b.orphans().is_empty());
+        assert_eq!(a, b);
+        assert_eq!(a.iter().collect::<String>(), b.iter().collect::<String>());
+    }
+
+    #[quickcheck]
+    fn prop_missing_ops_are_a_subset_of_the_difference(
+        base: Vec<(bool, u8, char)>,
+        ops_a: Vec<(bool, u8, char)>,
+    ) {
+        let mut a = HashSeq::default();
+        edit(&mut a, &base);
+        let b = a.clone();
+        edit(&mut a, &ops_a);
+
+        // A terrible filter still never makes us resend what `b` has.
+        let filter = BloomFilter::from_seq(&b, 0.5);
+        for op in a.ops_missing_from(&filter) {
+            let created = match op {
+                EncodableOp::Run(run) => run.elements,
+                EncodableOp::Node(node) => vec![node.id()],
+            };
+            assert!(created.iter().all(|id| !b.contains_node(id)));
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::Element;
+use crate::element::RunStorage;
+
+/// A splice of the visible sequence: at `index`, `removed_len` elements were
+/// removed and then `inserted` was inserted. Indices are in chars and refer to
+/// the sequence as it was after all the previous changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<T: Element = char> {
+    pub index: usize,
+    pub removed_len: usize,
+    pub inserted: T::Run,
+    /// `index` in UTF-16 code units, for editors that count that way.
+    pub utf16_index: usize,
+    /// `removed_len` in UTF-16 code units.
+    pub utf16_removed_len: usize,
+}
+
+impl<T: Element> Change<T> {
+    /// Apply this change to a model of the visible sequence.
+    pub fn apply_to(&self, text: &mut Vec<T>) {
+        text.splice(
+            self.index..self.index + self.removed_len,
+            self.inserted.elems(),
+        );
+    }
+}
+
+/// Changes recorded since they were last taken. Adjacent single element
+/// inserts and removes are merged, so a run landing shows up as one change.
+#[derive(Debug, Clone)]
+pub(crate) struct ChangeLog<T: Element> {
+    changes: Vec<Change<T>>,
+    // chars in the last change's `inserted`, to avoid recounting on every push.
+    last_inserted_len: usize,
+}
+
+/// A point in a `ChangeLog` to rewind it to.
+#[derive(Debug)]
+pub(crate) struct ChangeLogMark<T: Element> {
+    len: usize,
+    last: Option<Change<T>>,
+    last_inserted_len: usize,
+}
+
+impl<T: Element> Default for ChangeLog<T> {
+    fn default() -> Self {
+        Self {
+            changes: Vec::new(),
+            last_inserted_len: 0,
+        }
+    }
+}
+
+impl<T: Element> ChangeLog<T> {
+    pub(crate) fn inserted(&mut self, index: usize, utf16_index: usize, ch: T) {
+        if let Some(last) = self.changes.last_mut()
+            && index == last.index + self.last_inserted_len
+        {
+            last.inserted.push(ch);
+            self.last_inserted_len += 1;
+            return;
+        }
+        self.changes.push(Change {
+            index,
+            removed_len: 0,
+            inserted: std::iter::once(ch).collect(),
+            utf16_index,
+            utf16_removed_len: 0,
+        });
+        self.last_inserted_len = 1;
+    }
+
+    pub(crate) fn removed(&mut self, index: usize, utf16_index: usize, utf16_len: usize) {
+        if let Some(last) = self.changes.last_mut()
+            && self.last_inserted_len == 0
+        {
+            if index == last.index {
+                last.removed_len += 1;
+                last.utf16_removed_len += utf16_len;
+                return;
+            }
+            if index + 1 == last.index {
+                last.index = index;
+                last.removed_len += 1;
+                last.utf16_index = utf16_index;
+                last.utf16_removed_len += utf16_len;
+                return;
+            }
+        }
+        self.changes.push(Change {
+            index,
+            removed_len: 1,
+            inserted: T::Run::default(),
+            utf16_index,
+            utf16_removed_len: utf16_len,
+        });
+        self.last_inserted_len = 0;
+    }
+
+    /// Where the log stands, to `rewind` it to later. Only the last change
+    /// can still be merged into, so it's the only one copied.
+    pub(crate) fn mark(&self) -> ChangeLogMark<T> {
+        ChangeLogMark {
+            len: self.changes.len(),
+            last: self.changes.last().cloned(),
+            last_inserted_len: self.last_inserted_len,
+        }
+    }
+
+    /// Forget every change recorded since `mark` was taken.
+    pub(crate) fn rewind(&mut self, mark: ChangeLogMark<T>) {
+        self.changes.truncate(mark.len);
+        if let (Some(last), Some(change)) = (self.changes.last_mut(), mark.last) {
+            *last = change;
+        }
+        self.last_inserted_len = mark.last_inserted_len;
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<Change<T>> {
+        self.last_inserted_len = 0;
+        std::mem::take(&mut self.changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncodableOp, HashSeq};
+    use quickcheck_macros::quickcheck;
+
+    fn edit(seq: &mut HashSeq, ops: &[(bool, u8, char)]) -> Vec<EncodableOp> {
+        let mut created = Vec::new();
+        for &(insert_or_remove, idx, elem) in ops {
+            let idx = idx as usize;
+            if insert_or_remove {
+                created.extend(seq.insert(idx.min(seq.len()), elem));
+            } else if !seq.is_empty() {
+                created.extend(seq.remove(idx.min(seq.len() - 1)));
+            }
+        }
+        created
+    }
+
+    #[test]
+    fn test_coalescing() {
+        let mut log = ChangeLog::default();
+        for (i, ch) in "abc".chars().enumerate() {
+            log.inserted(2 + i, 2 + i, ch);
+        }
+        log.removed(7, 7, 1);
+        log.removed(7, 7, 2);
+        log.removed(6, 6, 1);
+        log.inserted(6, 6, 'x');
+        log.removed(0, 0, 1);
+        assert_eq!(
+            log.take(),
+            vec![
+                Change {
+                    index: 2,
+                    removed_len: 0,
+                    inserted: "abc".into(),
+                    utf16_index: 2,
+                    utf16_removed_len: 0,
+                },
+                Change {
+                    index: 6,
+                    removed_len: 3,
+                    inserted: "x".into(),
+                    utf16_index: 6,
+                    utf16_removed_len: 4,
+                },
+                Change {
+                    index: 0,
+                    removed_len: 1,
+                    inserted: String::new(),
+                    utf16_index: 0,
+                    utf16_removed_len: 1,
+                },
+            ]
+        );
+        assert!(log.take().is_empty());
+    }
+
+    #[test]
+    fn test_run_lands_as_one_change() {
+        let mut a = HashSeq::default();
+        let base = a.insert_batch(0, "hello".chars());
+        let mut b = HashSeq::default();
+        for op in base {
+            b.apply_op(op);
+        }
+
+        let ops = a.insert_batch(5, " world".chars());
+        b.set_record_changes(true);
+        for op in ops {
+            b.apply_op(op);
+        }
+        assert_eq!(
+            b.take_changes(),
+            vec![Change {
+                index: 5,
+                removed_len: 0,
+                inserted: " world".into(),
+                utf16_index: 5,
+                utf16_removed_len: 0,
+            }]
+        );
+
+        let ops = a.remove_batch(2, 6);
+        for op in ops {
+            b.apply_op(op);
+        }
+        assert_eq!(
+            b.take_changes(),
+            vec![Change {
+                index: 2,
+                removed_len: 6,
+                inserted: String::new(),
+                utf16_index: 2,
+                utf16_removed_len: 6,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_orphans_report_changes_when_applied() {
+        let mut a = HashSeq::default();
+        let mut ops = a.insert_batch(0, "abc".chars());
+        ops.extend(a.insert_batch(1, "xy".chars()));
+
+        let mut b = HashSeq::default();
+        b.set_record_changes(true);
+        for op in ops.into_iter().rev() {
+            b.apply_op(op);
+        }
+        let mut text = Vec::new();
+        for change in b.take_changes() {
+            change.apply_to(&mut text);
+        }
+        assert_eq!(text.into_iter().collect::<String>(), "axybc");
+    }
+
+    #[quickcheck]
+    fn prop_changes_replay_to_the_same_text(
+        base: Vec<(bool, u8, char)>,
+        ops_a: Vec<(bool, u8, char)>,
+        ops_b: Vec<(bool, u8, char)>,
+    ) {
+        let mut a = HashSeq::default();
+        edit(&mut a, &base);
+        let mut b = a.clone();
+        edit(&mut a, &ops_a);
+
+        b.set_record_changes(true);
+        let mut text: Vec<char> = b.iter().collect();
+        let mut utf16: Vec<u16> = b.iter().collect::<String>().encode_utf16().collect();
+        edit(&mut b, &ops_b);
+        b.merge(&a);
+        for change in b.take_changes() {
+            change.apply_to(&mut text);
+            let inserted: Vec<u16> = change.inserted.encode_utf16().collect();
+            utf16.splice(
+                change.utf16_index..change.utf16_index + change.utf16_removed_len,
+                inserted,
+            );
+        }
+        assert_eq!(text, b.iter().collect::<Vec<_>>());
+        assert_eq!(
+            String::from_utf16(&utf16).unwrap(),
+            b.iter().collect::<String>()
+        );
+    }
+}
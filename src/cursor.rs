@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Id;
+
+/// Which neighbour a `Cursor` sticks to when text is inserted right at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Gravity {
+    /// Stick to the element on the left: inserts at the cursor go after it.
+    Left,
+    /// Stick to the element on the right: inserts at the cursor go before it.
+    Right,
+}
+
+/// A position between two elements that follows the text around as it is
+/// edited, locally or by peers. Create one with `HashSeq::cursor_at` and find
+/// where it is now with `HashSeq::cursor_index`.
+///
+/// Cursors only hold an `Id`, so they can be sent to peers (see
+/// `encode_cursor`) and resolved against their replicas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Cursor {
+    /// The element the cursor sticks to, `None` for the start (left gravity)
+    /// or the end (right gravity) of the sequence.
+    pub anchor: Option<Id>,
+    pub gravity: Gravity,
+}
+
+impl Cursor {
+    /// The very start of the sequence, whatever gets inserted there.
+    pub const START: Cursor = Cursor {
+        anchor: None,
+        gravity: Gravity::Left,
+    };
+
+    /// The very end of the sequence, whatever gets inserted there.
+    pub const END: Cursor = Cursor {
+        anchor: None,
+        gravity: Gravity::Right,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecodeError, HashSeq, decode_cursor, encode_cursor};
+    use quickcheck_macros::quickcheck;
+
+    #[test]
+    fn test_gravity() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "ac".chars());
+        let left = seq.cursor_at(1, Gravity::Left);
+        let right = seq.cursor_at(1, Gravity::Right);
+
+        seq.insert(1, 'b');
+        assert_eq!(seq.cursor_index(&left), Some(1));
+        assert_eq!(seq.cursor_index(&right), Some(2));
+
+        seq.insert(0, '>');
+        assert_eq!(seq.cursor_index(&left), Some(2));
+        assert_eq!(seq.cursor_index(&right), Some(3));
+        assert_eq!(seq.cursor_index(&Cursor::START), Some(0));
+        assert_eq!(seq.cursor_index(&Cursor::END), Some(4));
+        assert_eq!(seq.cursor_at(0, Gravity::Left), Cursor::START);
+        assert_eq!(seq.cursor_at(4, Gravity::Right), Cursor::END);
+    }
+
+    #[test]
+    fn test_removed_anchor_falls_back_to_its_neighbours() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abcdef".chars());
+        let after_c = seq.cursor_at(3, Gravity::Left);
+        let before_d = seq.cursor_at(3, Gravity::Right);
+
+        seq.remove_batch(1, 4);
+        assert_eq!(seq.to_string(), "af");
+        assert_eq!(seq.cursor_index(&after_c), Some(1));
+        assert_eq!(seq.cursor_index(&before_d), Some(1));
+
+        // It still moves with the text around it.
+        seq.insert_batch(0, "xy".chars());
+        seq.insert(3, 'z');
+        assert_eq!(seq.to_string(), "xyazf");
+        assert_eq!(seq.cursor_index(&after_c), Some(3));
+    }
+
+    #[test]
+    fn test_cursors_survive_merges() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "hello world".chars());
+        let mut bob = alice.clone();
+        let caret = alice.cursor_at(5, Gravity::Left);
+        let bytes = {
+            let mut buf = Vec::new();
+            encode_cursor(&caret, &mut buf);
+            buf
+        };
+        assert_eq!(bytes.len(), 33);
+
+        bob.insert_batch(0, "oh, ".chars());
+        bob.remove_batch(9, 6);
+        alice.merge(&bob);
+        assert_eq!(alice.to_string(), "oh, hello");
+
+        let (shared, size) = decode_cursor(&bytes).unwrap();
+        assert_eq!(size, bytes.len());
+        assert_eq!(alice.cursor_index(&shared), Some(9));
+
+        // A peer that hasn't seen the anchor yet can't place the cursor.
+        assert_eq!(HashSeq::<char>::default().cursor_index(&shared), None);
+
+        assert_eq!(decode_cursor(&[0x01]), Ok((Cursor::END, 1)));
+        assert_eq!(decode_cursor(&bytes[..20]), Err(DecodeError::UnexpectedEof));
+        assert_eq!(
+            decode_cursor(&[0x04]),
+            Err(DecodeError::InvalidCursorFlags(0x04))
+        );
+    }
+
+    #[quickcheck]
+    fn prop_cursors_track_edits(base: Vec<char>, idx: u8, edits: Vec<(bool, u8, char)>) -> bool {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, base.iter().copied());
+        let idx = idx as usize % (base.len() + 1);
+        let left = seq.cursor_at(idx, Gravity::Left);
+        let right = seq.cursor_at(idx, Gravity::Right);
+
+        // Follow the cursors through a model of the edits, for as long as
+        // their anchors are around to pin them down.
+        let mut left_idx = Some(idx);
+        let mut right_idx = Some(idx);
+        let right_is_end = idx == base.len();
+        for (insert_or_remove, pos, ch) in edits {
+            let pos = pos as usize;
+            if insert_or_remove {
+                let pos = pos.min(seq.len());
+                seq.insert(pos, ch);
+                left_idx = left_idx.map(|i| i + usize::from(pos < i));
+                right_idx = right_idx.map(|i| i + usize::from(pos <= i));
+            } else if !seq.is_empty() {
+                let pos = pos.min(seq.len() - 1);
+                seq.remove(pos);
+                left_idx = left_idx
+                    .filter(|&i| pos + 1 != i)
+                    .map(|i| i - usize::from(pos < i));
+                right_idx = right_idx
+                    .filter(|&i| right_is_end || pos != i)
+                    .map(|i| i - usize::from(pos < i));
+            }
+        }
+
+        let resolves = |cursor: &Cursor, model: Option<usize>| match seq.cursor_index(cursor) {
+            Some(i) => model.is_none_or(|m| m == i) && i <= seq.len(),
+            None => false,
+        };
+        resolves(&left, left_idx) && resolves(&right, right_idx)
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt::Debug;
+use std::ops::Range;
+
+use crate::Weight;
+
+/// Something that can be stored in a `HashSeq`.
+pub trait Element: Clone + Eq + Debug {
+    /// How a run stores its elements: `String` for `char`, usually `Vec<Self>` otherwise.
+    type Run: RunStorage<Self>;
+
+    /// Feed the element into the hash of the node inserting it. Distinct
+    /// elements must feed distinct bytes, or their nodes would share an id.
+    fn hash_update(&self, hasher: &mut blake3::Hasher);
+
+    /// How long the element is when the sequence is indexed by UTF-16 or UTF-8
+    /// offsets. Anything that isn't text counts as one unit.
+    fn weight(&self) -> Weight {
+        Weight::UNIT
+    }
+}
+
+/// The elements of a run, in order.
+pub trait RunStorage<T>: Clone + Default + Debug + Eq + FromIterator<T> {
+    fn push(&mut self, elem: T);
+
+    fn elem(&self, position: usize) -> Option<T>;
+
+    fn elems(&self) -> impl Iterator<Item = T> + '_;
+
+    /// Split at `position`, keeping the elements before it.
+    fn split_off(&mut self, position: usize) -> Self;
+
+    fn slice(&self, range: Range<usize>) -> Self;
+}
+
+impl RunStorage<char> for String {
+    fn push(&mut self, ch: char) {
+        String::push(self, ch);
+    }
+
+    fn elem(&self, position: usize) -> Option<char> {
+        self.chars().nth(position)
+    }
+
+    fn elems(&self) -> impl Iterator<Item = char> + '_ {
+        self.chars()
+    }
+
+    fn split_off(&mut self, position: usize) -> Self {
+        let byte_pos = self
+            .char_indices()
+            .nth(position)
+            .map_or(self.len(), |(i, _)| i);
+        String::split_off(self, byte_pos)
+    }
+
+    fn slice(&self, range: Range<usize>) -> Self {
+        self.chars().skip(range.start).take(range.len()).collect()
+    }
+}
+
+impl<T: Clone + Eq + Debug> RunStorage<T> for Vec<T> {
+    fn push(&mut self, elem: T) {
+        Vec::push(self, elem);
+    }
+
+    fn elem(&self, position: usize) -> Option<T> {
+        <[T]>::get(self, position).cloned()
+    }
+
+    fn elems(&self) -> impl Iterator<Item = T> + '_ {
+        <[T]>::iter(self).cloned()
+    }
+
+    fn split_off(&mut self, position: usize) -> Self {
+        Vec::split_off(self, position)
+    }
+
+    fn slice(&self, range: Range<usize>) -> Self {
+        self[range].to_vec()
+    }
+}
+
+impl Element for char {
+    type Run = String;
+
+    fn hash_update(&self, hasher: &mut blake3::Hasher) {
+        hasher.update(&(*self as u32).to_le_bytes());
+    }
+
+    fn weight(&self) -> Weight {
+        Weight {
+            len: 1,
+            utf16: self.len_utf16(),
+            utf8: self.len_utf8(),
+            lines: usize::from(*self == '\n'),
+        }
+    }
+}
+
+impl Element for String {
+    type Run = Vec<String>;
+
+    fn hash_update(&self, hasher: &mut blake3::Hasher) {
+        hasher.update(&(self.len() as u64).to_le_bytes());
+        hasher.update(self.as_bytes());
+    }
+}
+
+macro_rules! int_element {
+    ($($int:ty),*) => {
+        $(
+            impl Element for $int {
+                type Run = Vec<$int>;
+
+                fn hash_update(&self, hasher: &mut blake3::Hasher) {
+                    hasher.update(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+int_element!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HashSeq, decode_delta, decode_hashseq, encode_delta, encode_hashseq};
+    use quickcheck_macros::quickcheck;
+
+    #[test]
+    fn test_string_run_storage_counts_chars() {
+        let mut run: String = "aé🦀b".into();
+        assert_eq!(run.elem(2), Some('🦀'));
+        assert_eq!(run.elem(4), None);
+        assert_eq!(run.slice(1..3), "é🦀");
+        assert_eq!(RunStorage::split_off(&mut run, 2), "🦀b");
+        assert_eq!(run, "aé");
+    }
+
+    #[test]
+    fn test_list_of_strings() {
+        let mut alice: HashSeq<String> = HashSeq::default();
+        alice.insert_batch(0, ["milk".to_string(), "eggs".to_string()]);
+        let mut bob = alice.clone();
+
+        alice.insert(1, "bread".to_string());
+        bob.remove(0);
+        bob.insert(1, "coffee".to_string());
+        alice.merge(&bob);
+        bob.merge(&alice);
+
+        assert_eq!(alice, bob);
+        assert_eq!(
+            alice.iter().collect::<Vec<_>>(),
+            ["bread", "eggs", "coffee"]
+        );
+        assert_eq!(
+            bob.iter().collect::<Vec<_>>(),
+            alice.iter().collect::<Vec<_>>()
+        );
+
+        let decoded: HashSeq<String> = decode_hashseq(&encode_hashseq(&alice)).unwrap();
+        assert_eq!(decoded, alice);
+        assert_eq!(
+            decoded.iter().collect::<Vec<_>>(),
+            ["bread", "eggs", "coffee"]
+        );
+    }
+
+    #[test]
+    fn test_elements_hash_by_value() {
+        // Two strings that would collide if they weren't length-prefixed.
+        let mut a: HashSeq<String> = HashSeq::default();
+        a.insert_batch(0, ["ab".to_string(), "c".to_string()]);
+        let mut b: HashSeq<String> = HashSeq::default();
+        b.insert_batch(0, ["a".to_string(), "bc".to_string()]);
+        a.merge(&b);
+        assert_eq!(a.len(), 4);
+    }
+
+    #[quickcheck]
+    fn prop_u64_replicas_converge(
+        base: Vec<u64>,
+        ops_a: Vec<(bool, u8, u64)>,
+        ops_b: Vec<(bool, u8, u64)>,
+    ) {
+        fn edit(seq: &mut HashSeq<u64>, ops: &[(bool, u8, u64)]) {
+            for &(insert_or_remove, idx, elem) in ops {
+                let idx = idx as usize;
+                if insert_or_remove {
+                    seq.insert(idx.min(seq.len()), elem);
+                } else if !seq.is_empty() {
+                    seq.remove(idx.min(seq.len() - 1));
+                }
+            }
+        }
+
+        let mut a: HashSeq<u64> = HashSeq::default();
+        let base_ops = a.insert_batch(0, base.iter().copied());
+        let mut b: HashSeq<u64> = HashSeq::default();
+        for op in decode_delta(&encode_delta(&base_ops)).unwrap() {
+            b.apply_op(op);
+        }
+        assert_eq!(b.iter().collect::<Vec<_>>(), base);
+
+        edit(&mut a, &ops_a);
+        edit(&mut b, &ops_b);
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert_eq!(ab, ba);
+        assert_eq!(ab.iter().collect::<Vec<_>>(), ba.iter().collect::<Vec<_>>());
+    }
+}
@@ -1,7 +1,12 @@
 use std::collections::{BTreeSet, HashMap};
 
 use crate::hashseq::{CausalInsert, CausalRemove};
-use crate::{HashNode, HashSeq, Id, Op, Run};
+use crate::bloom::BloomFilter;
+use crate::cursor::{Cursor, Gravity};
+use crate::reconcile::{Fingerprint, Range, RangeMode, ReconcileMessage};
+use crate::sync::SyncMessage;
+use crate::element::RunStorage;
+use crate::{Element, HashNode, HashSeq, Id, Op, Run};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DecodeError {
@@ -11,6 +16,11 @@ pub enum DecodeError {
     InvalidOpTag(u8),
     EmptyRun,
     InvalidIdIndex(usize),
+    InvalidMessageTag(u8),
+    InvalidRangeTag(u8),
+    EmptyBloomFilter,
+    InvalidBloomHashCount(usize),
+    InvalidCursorFlags(u8),
 }
 
 impl std::fmt::Display for DecodeError {
@@ -22,6 +32,13 @@ impl std::fmt::Display for DecodeError {
             DecodeError::InvalidOpTag(tag) => write!(f, "invalid operation tag: {}", tag),
             DecodeError::EmptyRun => write!(f, "run string cannot be empty"),
             DecodeError::InvalidIdIndex(idx) => write!(f, "invalid ID index: {}", idx),
+            DecodeError::InvalidMessageTag(tag) => write!(f, "invalid sync message tag: {}", tag),
+            DecodeError::InvalidRangeTag(tag) => write!(f, "invalid reconcile range tag: {}", tag),
+            DecodeError::EmptyBloomFilter => write!(f, "bloom filter cannot be empty"),
+            DecodeError::InvalidBloomHashCount(n) => {
+                write!(f, "invalid bloom filter hash count: {}", n)
+            }
+            DecodeError::InvalidCursorFlags(flags) => write!(f, "invalid cursor flags: {}", flags),
         }
     }
 }
@@ -34,6 +51,11 @@ const TAG_INSERT_ROOT: u8 = 0x01;
 const TAG_INSERT_BEFORE: u8 = 0x02;
 const TAG_REMOVE: u8 = 0x03;
 const TAG_INSERT_AFTER: u8 = 0x04;
+const TAG_ADD_MARK: u8 = 0x05;
+const TAG_REMOVE_MARK: u8 = 0x06;
+const TAG_MOVE_AFTER: u8 = 0x07;
+const TAG_MOVE_BEFORE: u8 = 0x08;
+const TAG_REMOVE_RANGE: u8 = 0x09;
 
 // --- Varint (LEB128) encoding/decoding ---
 
@@ -139,6 +161,83 @@ pub fn decode_string(bytes: &[u8]) -> Result<(String, usize), DecodeError> {
     Ok((s.to_string(), varint_size + len))
 }
 
+// --- Element encoding/decoding ---
+
+/// Elements the encoders in this module can write. `char`s are written as
+/// UTF-8, and runs of them as a single string.
+pub trait EncodeElement: Element {
+    fn encode_elem(&self, buf: &mut Vec<u8>);
+
+    fn decode_elem(bytes: &[u8]) -> Result<(Self, usize), DecodeError>;
+
+    /// Encode the contents of a run: by default a count and then each element.
+    fn encode_run_elems(run: &Self::Run, buf: &mut Vec<u8>) {
+        encode_varint(run.elems().count(), buf);
+        for elem in run.elems() {
+            elem.encode_elem(buf);
+        }
+    }
+
+    fn decode_run_elems(bytes: &[u8]) -> Result<(Self::Run, usize), DecodeError> {
+        let (count, mut pos) = decode_varint(bytes)?;
+        let mut run = Self::Run::default();
+        for _ in 0..count {
+            let (elem, size) = Self::decode_elem(&bytes[pos..])?;
+            run.push(elem);
+            pos += size;
+        }
+        Ok((run, pos))
+    }
+}
+
+impl EncodeElement for char {
+    fn encode_elem(&self, buf: &mut Vec<u8>) {
+        encode_utf8_char(*self, buf);
+    }
+
+    fn decode_elem(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        decode_utf8_char(bytes)
+    }
+
+    fn encode_run_elems(run: &String, buf: &mut Vec<u8>) {
+        encode_string(run, buf);
+    }
+
+    fn decode_run_elems(bytes: &[u8]) -> Result<(String, usize), DecodeError> {
+        decode_string(bytes)
+    }
+}
+
+impl EncodeElement for String {
+    fn encode_elem(&self, buf: &mut Vec<u8>) {
+        encode_string(self, buf);
+    }
+
+    fn decode_elem(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        decode_string(bytes)
+    }
+}
+
+macro_rules! encode_int_element {
+    ($($int:ty),*) => {
+        $(
+            impl EncodeElement for $int {
+                fn encode_elem(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode_elem(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+                    const SIZE: usize = std::mem::size_of::<$int>();
+                    let le_bytes = bytes.get(..SIZE).ok_or(DecodeError::UnexpectedEof)?;
+                    Ok((<$int>::from_le_bytes(le_bytes.try_into().unwrap()), SIZE))
+                }
+            }
+        )*
+    };
+}
+
+encode_int_element!(u8, u16, u32, u64, i8, i16, i32, i64);
+
 // --- Id set encoding/decoding ---
 
 pub fn encode_id_set(ids: &BTreeSet<Id>, buf: &mut Vec<u8>) {
@@ -163,13 +262,13 @@ pub fn decode_id_set(bytes: &[u8]) -> Result<(BTreeSet<Id>, usize), DecodeError>
 
 // --- Run encoding/decoding ---
 
-pub fn encode_run(run: &Run, buf: &mut Vec<u8>) {
+pub fn encode_run<T: EncodeElement>(run: &Run<T>, buf: &mut Vec<u8>) {
     encode_id(&run.insert_after, buf);
     encode_id_set(&run.first_extra_deps, buf);
-    encode_string(&run.run, buf);
+    T::encode_run_elems(&run.run, buf);
 }
 
-pub fn decode_run(bytes: &[u8]) -> Result<(Run, usize), DecodeError> {
+pub fn decode_run<T: EncodeElement>(bytes: &[u8]) -> Result<(Run<T>, usize), DecodeError> {
     let mut pos = 0;
 
     let (insert_after, id_size) = decode_id(bytes)?;
@@ -178,11 +277,11 @@ pub fn decode_run(bytes: &[u8]) -> Result<(Run, usize), DecodeError> {
     let (first_extra_deps, deps_size) = decode_id_set(&bytes[pos..])?;
     pos += deps_size;
 
-    let (run_str, str_size) = decode_string(&bytes[pos..])?;
+    let (run_str, str_size) = T::decode_run_elems(&bytes[pos..])?;
     pos += str_size;
 
     // Reconstruct the Run with computed elements
-    let mut chars = run_str.chars();
+    let mut chars = run_str.elems();
     let first_char = chars.next().ok_or(DecodeError::EmptyRun)?;
 
     let mut run = Run::new(insert_after, first_extra_deps, first_char);
@@ -195,24 +294,24 @@ pub fn decode_run(bytes: &[u8]) -> Result<(Run, usize), DecodeError> {
 
 // --- HashNode (InsertRoot, InsertBefore, Remove) encoding/decoding ---
 
-pub fn encode_hash_node(node: &HashNode, buf: &mut Vec<u8>) {
+pub fn encode_hash_node<T: EncodeElement>(node: &HashNode<T>, buf: &mut Vec<u8>) {
     match &node.op {
         Op::InsertRoot(ch) => {
             buf.push(TAG_INSERT_ROOT);
             encode_id_set(&node.extra_dependencies, buf);
-            encode_utf8_char(*ch, buf);
+            ch.encode_elem(buf);
         }
         Op::InsertAfter(id, ch) => {
             buf.push(TAG_INSERT_AFTER);
             encode_id_set(&node.extra_dependencies, buf);
             encode_id(id, buf);
-            encode_utf8_char(*ch, buf);
+            ch.encode_elem(buf);
         }
         Op::InsertBefore(id, ch) => {
             buf.push(TAG_INSERT_BEFORE);
             encode_id_set(&node.extra_dependencies, buf);
             encode_id(id, buf);
-            encode_utf8_char(*ch, buf);
+            ch.encode_elem(buf);
         }
         Op::Remove(ids) => {
             buf.push(TAG_REMOVE);
@@ -222,10 +321,28 @@ pub fn encode_hash_node(node: &HashNode, buf: &mut Vec<u8>) {
                 encode_id(id, buf);
             }
         }
+        Op::AddMark { .. } | Op::RemoveMark { .. } => {
+            buf.push(mark_tag(&node.op));
+            encode_id_set(&node.extra_dependencies, buf);
+            encode_mark(&node.op, buf, encode_id);
+        }
+        Op::Move { .. } => {
+            buf.push(move_tag(&node.op));
+            encode_id_set(&node.extra_dependencies, buf);
+            encode_move(&node.op, buf, encode_id);
+        }
+        Op::RemoveRange { start, end } => {
+            buf.push(TAG_REMOVE_RANGE);
+            encode_id_set(&node.extra_dependencies, buf);
+            encode_id(start, buf);
+            encode_id(end, buf);
+        }
     }
 }
 
-fn decode_insert_after(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
+fn decode_insert_after<T: EncodeElement>(
+    bytes: &[u8],
+) -> Result<(HashNode<T>, usize), DecodeError> {
     let mut pos = 0;
 
     let (extra_deps, deps_size) = decode_id_set(bytes)?;
@@ -234,7 +351,7 @@ fn decode_insert_after(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
     let (after_id, id_size) = decode_id(&bytes[pos..])?;
     pos += id_size;
 
-    let (ch, ch_size) = decode_utf8_char(&bytes[pos..])?;
+    let (ch, ch_size) = T::decode_elem(&bytes[pos..])?;
     pos += ch_size;
 
     Ok((
@@ -246,13 +363,13 @@ fn decode_insert_after(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
     ))
 }
 
-fn decode_insert_root(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
+fn decode_insert_root<T: EncodeElement>(bytes: &[u8]) -> Result<(HashNode<T>, usize), DecodeError> {
     let mut pos = 0;
 
     let (extra_deps, deps_size) = decode_id_set(bytes)?;
     pos += deps_size;
 
-    let (ch, ch_size) = decode_utf8_char(&bytes[pos..])?;
+    let (ch, ch_size) = T::decode_elem(&bytes[pos..])?;
     pos += ch_size;
 
     Ok((
@@ -264,7 +381,9 @@ fn decode_insert_root(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
     ))
 }
 
-fn decode_insert_before(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
+fn decode_insert_before<T: EncodeElement>(
+    bytes: &[u8],
+) -> Result<(HashNode<T>, usize), DecodeError> {
     let mut pos = 0;
 
     let (extra_deps, deps_size) = decode_id_set(bytes)?;
@@ -273,7 +392,7 @@ fn decode_insert_before(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError>
     let (before_id, id_size) = decode_id(&bytes[pos..])?;
     pos += id_size;
 
-    let (ch, ch_size) = decode_utf8_char(&bytes[pos..])?;
+    let (ch, ch_size) = T::decode_elem(&bytes[pos..])?;
     pos += ch_size;
 
     Ok((
@@ -285,7 +404,7 @@ fn decode_insert_before(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError>
     ))
 }
 
-fn decode_remove(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
+fn decode_remove<T: EncodeElement>(bytes: &[u8]) -> Result<(HashNode<T>, usize), DecodeError> {
     let mut pos = 0;
 
     let (extra_deps, deps_size) = decode_id_set(bytes)?;
@@ -310,15 +429,167 @@ fn decode_remove(bytes: &[u8]) -> Result<(HashNode, usize), DecodeError> {
     ))
 }
 
+fn decode_remove_range<T: EncodeElement>(
+    bytes: &[u8],
+) -> Result<(HashNode<T>, usize), DecodeError> {
+    let (extra_deps, mut pos) = decode_id_set(bytes)?;
+    let (start, size) = decode_id(&bytes[pos..])?;
+    pos += size;
+    let (end, size) = decode_id(&bytes[pos..])?;
+    pos += size;
+
+    Ok((
+        HashNode {
+            extra_dependencies: extra_deps,
+            op: Op::RemoveRange { start, end },
+        },
+        pos,
+    ))
+}
+
+fn decode_mark_node<T: EncodeElement>(
+    tag: u8,
+    bytes: &[u8],
+) -> Result<(HashNode<T>, usize), DecodeError> {
+    let (extra_deps, deps_size) = decode_id_set(bytes)?;
+    let (op, mark_size) = decode_mark(tag, &bytes[deps_size..], decode_id)?;
+
+    Ok((
+        HashNode {
+            extra_dependencies: extra_deps,
+            op,
+        },
+        deps_size + mark_size,
+    ))
+}
+
+fn decode_move_node<T: EncodeElement>(
+    tag: u8,
+    bytes: &[u8],
+) -> Result<(HashNode<T>, usize), DecodeError> {
+    let (extra_deps, deps_size) = decode_id_set(bytes)?;
+    let (op, move_size) = decode_move(tag, &bytes[deps_size..], decode_id)?;
+
+    Ok((
+        HashNode {
+            extra_dependencies: extra_deps,
+            op,
+        },
+        deps_size + move_size,
+    ))
+}
+
+// --- Mark (AddMark, RemoveMark) encoding/decoding ---
+//
+// Format: [start][end][clock: u64 LE][name][value, AddMark only], where the
+// edges are cursors (see `encode_cursor`) with their anchors written by
+// `encode_ref`, so each format can refer to ids the way it does elsewhere.
+
+fn mark_tag<T>(op: &Op<T>) -> u8 {
+    match op {
+        Op::AddMark { .. } => TAG_ADD_MARK,
+        _ => TAG_REMOVE_MARK,
+    }
+}
+
+fn encode_mark<T>(op: &Op<T>, buf: &mut Vec<u8>, encode_ref: impl Fn(&Id, &mut Vec<u8>)) {
+    let (start, end, clock, name, value) = match op {
+        Op::AddMark { start, end, clock, name, value } => (start, end, clock, name, Some(value)),
+        Op::RemoveMark { start, end, clock, name } => (start, end, clock, name, None),
+        _ => unreachable!("not a mark"),
+    };
+    encode_cursor_with(start, buf, &encode_ref);
+    encode_cursor_with(end, buf, &encode_ref);
+    buf.extend_from_slice(&clock.to_le_bytes());
+    encode_string(name, buf);
+    if let Some(value) = value {
+        encode_string(value, buf);
+    }
+}
+
+fn decode_mark<T>(
+    tag: u8,
+    bytes: &[u8],
+    decode_ref: impl Fn(&[u8]) -> Result<(Id, usize), DecodeError>,
+) -> Result<(Op<T>, usize), DecodeError> {
+    let (start, mut pos) = decode_cursor_with(bytes, &decode_ref)?;
+    let (end, end_size) = decode_cursor_with(&bytes[pos..], &decode_ref)?;
+    pos += end_size;
+
+    let clock = bytes.get(pos..pos + 8).ok_or(DecodeError::UnexpectedEof)?;
+    let clock = u64::from_le_bytes(clock.try_into().unwrap());
+    pos += 8;
+
+    let (name, name_size) = decode_string(&bytes[pos..])?;
+    pos += name_size;
+
+    let op = if tag == TAG_ADD_MARK {
+        let (value, value_size) = decode_string(&bytes[pos..])?;
+        pos += value_size;
+        Op::AddMark { start, end, clock, name, value }
+    } else {
+        Op::RemoveMark { start, end, clock, name }
+    };
+    Ok((op, pos))
+}
+
+// --- Move encoding/decoding ---
+//
+// Format: [anchor][element][clock: u64 LE], with the ids written by
+// `encode_ref`. The tag says which side of the anchor the move goes.
+
+fn move_tag<T>(op: &Op<T>) -> u8 {
+    match op {
+        Op::Move { before: true, .. } => TAG_MOVE_BEFORE,
+        _ => TAG_MOVE_AFTER,
+    }
+}
+
+fn encode_move<T>(op: &Op<T>, buf: &mut Vec<u8>, encode_ref: impl Fn(&Id, &mut Vec<u8>)) {
+    let Op::Move { anchor, element, clock, .. } = op else {
+        unreachable!("not a move")
+    };
+    encode_ref(anchor, buf);
+    encode_ref(element, buf);
+    buf.extend_from_slice(&clock.to_le_bytes());
+}
+
+fn decode_move<T>(
+    tag: u8,
+    bytes: &[u8],
+    decode_ref: impl Fn(&[u8]) -> Result<(Id, usize), DecodeError>,
+) -> Result<(Op<T>, usize), DecodeError> {
+    let (anchor, mut pos) = decode_ref(bytes)?;
+    let (element, element_size) = decode_ref(&bytes[pos..])?;
+    pos += element_size;
+
+    let clock = bytes.get(pos..pos + 8).ok_or(DecodeError::UnexpectedEof)?;
+    let clock = u64::from_le_bytes(clock.try_into().unwrap());
+    pos += 8;
+
+    let before = tag == TAG_MOVE_BEFORE;
+    Ok((Op::Move { anchor, before, element, clock }, pos))
+}
+
 // --- Unified operation type for batch encoding ---
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum EncodableOp {
-    Run(Run),
-    Node(HashNode),
+pub enum EncodableOp<T: Element = char> {
+    Run(Run<T>),
+    Node(HashNode<T>),
+}
+
+impl<T: Element> EncodableOp<T> {
+    /// The id of the first node this op creates.
+    pub fn first_id(&self) -> Id {
+        match self {
+            EncodableOp::Run(run) => run.first_id(),
+            EncodableOp::Node(node) => node.id(),
+        }
+    }
 }
 
-pub fn encode_op(op: &EncodableOp, buf: &mut Vec<u8>) {
+pub fn encode_op<T: EncodeElement>(op: &EncodableOp<T>, buf: &mut Vec<u8>) {
     match op {
         EncodableOp::Run(run) => {
             buf.push(TAG_RUN);
@@ -328,7 +599,7 @@ pub fn encode_op(op: &EncodableOp, buf: &mut Vec<u8>) {
     }
 }
 
-pub fn decode_op(bytes: &[u8]) -> Result<(EncodableOp, usize), DecodeError> {
+pub fn decode_op<T: EncodeElement>(bytes: &[u8]) -> Result<(EncodableOp<T>, usize), DecodeError> {
     if bytes.is_empty() {
         return Err(DecodeError::UnexpectedEof);
     }
@@ -357,13 +628,25 @@ pub fn decode_op(bytes: &[u8]) -> Result<(EncodableOp, usize), DecodeError> {
             let (node, size) = decode_insert_after(bytes)?;
             Ok((EncodableOp::Node(node), 1 + size))
         }
+        TAG_ADD_MARK | TAG_REMOVE_MARK => {
+            let (node, size) = decode_mark_node(tag, bytes)?;
+            Ok((EncodableOp::Node(node), 1 + size))
+        }
+        TAG_MOVE_AFTER | TAG_MOVE_BEFORE => {
+            let (node, size) = decode_move_node(tag, bytes)?;
+            Ok((EncodableOp::Node(node), 1 + size))
+        }
+        TAG_REMOVE_RANGE => {
+            let (node, size) = decode_remove_range(bytes)?;
+            Ok((EncodableOp::Node(node), 1 + size))
+        }
         _ => Err(DecodeError::InvalidOpTag(tag)),
     }
 }
 
 // --- Batch encoding/decoding ---
 
-pub fn encode_batch(ops: &[EncodableOp]) -> Vec<u8> {
+pub fn encode_batch<T: EncodeElement>(ops: &[EncodableOp<T>]) -> Vec<u8> {
     let mut buf = Vec::new();
     encode_varint(ops.len(), &mut buf);
     for op in ops {
@@ -372,7 +655,7 @@ pub fn encode_batch(ops: &[EncodableOp]) -> Vec<u8> {
     buf
 }
 
-pub fn decode_batch(bytes: &[u8]) -> Result<Vec<EncodableOp>, DecodeError> {
+pub fn decode_batch<T: EncodeElement>(bytes: &[u8]) -> Result<Vec<EncodableOp<T>>, DecodeError> {
     let (count, mut pos) = decode_varint(bytes)?;
     let mut ops = Vec::with_capacity(count);
 
@@ -393,7 +676,7 @@ pub fn decode_batch(bytes: &[u8]) -> Result<Vec<EncodableOp>, DecodeError> {
 // dictionary header so each unique ID only takes 32 bytes once and is
 // referenced by varint index thereafter.
 //
-// Format: [id_dict][roots][runs][befores][removes][orphans]
+// Format: [id_dict][roots][runs][befores][removes][range_removes][marks][moves][orphans]
 
 // Op reference tags used during encoding to classify which positional
 // section a given ID belongs to.
@@ -420,8 +703,11 @@ struct OpRef {
 /// - [num_single_run][...]            { idx_set extra_deps, varint run_idx, varint elem_idx }
 /// - [num_before_removes][...]        { idx_set extra_deps, varint before_idx }
 /// - [num_root_removes][...]          { idx_set extra_deps, varint root_idx }
+/// - [num_range_removes][...]         { idx_set extra_deps, idx start, idx end }
+/// - [num_marks][marks...]            tagged marks with idx-encoded IDs
+/// - [num_moves][moves...]            tagged moves with idx-encoded IDs
 /// - [num_orphans][orphans...]        tagged HashNodes with idx-encoded IDs
-pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
+pub fn encode_hashseq<T: EncodeElement>(seq: &HashSeq<T>) -> Vec<u8> {
     // Build ID -> OpRef mapping for compact remove encoding.
     let mut id_to_ref: HashMap<Id, OpRef> = HashMap::new();
 
@@ -429,9 +715,9 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
     // runs/before_nodes/remove_nodes are HashMaps with a randomized iteration order;
     // we sort by ID so the encoded bytes are byte-identical across processes.
     let roots: Vec<_> = seq.root_nodes.iter().collect();
-    let mut runs: Vec<&Run> = seq.runs.values().collect();
+    let mut runs: Vec<&Run<T>> = seq.runs.values().collect();
     runs.sort_by_key(|r| r.elements.first().copied());
-    let mut befores: Vec<(&Id, &CausalInsert)> = seq.before_nodes.iter().collect();
+    let mut befores: Vec<(&Id, &CausalInsert<T>)> = seq.before_nodes.iter().collect();
     befores.sort_by_key(|(id, _)| **id);
 
     for (op_idx, (id, _root)) in roots.iter().enumerate() {
@@ -456,7 +742,13 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
 
     let mut removes: Vec<(&Id, &CausalRemove)> = seq.remove_nodes.iter().collect();
     removes.sort_by_key(|(id, _)| **id);
-    let mut orphans: Vec<&HashNode> = seq.orphaned.iter().collect();
+    let mut marks: Vec<_> = seq.mark_nodes.iter().collect();
+    marks.sort_by_key(|(id, _)| **id);
+    let mut range_removes: Vec<_> = seq.remove_range_nodes.iter().collect();
+    range_removes.sort_by_key(|(id, _)| **id);
+    let mut moves: Vec<_> = seq.move_nodes.iter().collect();
+    moves.sort_by_key(|(id, _)| **id);
+    let mut orphans: Vec<&HashNode<T>> = seq.orphaned.iter().collect();
     orphans.sort_by_key(|n| n.id());
     let mut remove_infos: Vec<RemoveInfo> = Vec::new();
 
@@ -648,6 +940,27 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
             id_set.insert(*dep);
         }
     }
+    for (_id, range) in &range_removes {
+        for dep in &range.extra_dependencies {
+            id_set.insert(*dep);
+        }
+        id_set.insert(range.start);
+        id_set.insert(range.end);
+    }
+    for (_id, mark) in &marks {
+        for dep in &mark.extra_dependencies {
+            id_set.insert(*dep);
+        }
+        id_set.extend(mark.start.anchor);
+        id_set.extend(mark.end.anchor);
+    }
+    for (_id, mv) in &moves {
+        for dep in &mv.extra_dependencies {
+            id_set.insert(*dep);
+        }
+        id_set.insert(mv.anchor);
+        id_set.insert(mv.element);
+    }
     for orphan in &orphans {
         for dep in &orphan.extra_dependencies {
             id_set.insert(*dep);
@@ -662,6 +975,17 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
                     id_set.insert(*id);
                 }
             }
+            Op::AddMark { .. } | Op::RemoveMark { .. } => {
+                id_set.extend(orphan.op.anchors());
+            }
+            Op::Move { anchor, element, .. } => {
+                id_set.insert(*anchor);
+                id_set.insert(*element);
+            }
+            Op::RemoveRange { start, end } => {
+                id_set.insert(*start);
+                id_set.insert(*end);
+            }
         }
     }
 
@@ -691,7 +1015,7 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
     encode_varint(roots.len(), &mut buf);
     for (_id, root) in &roots {
         encode_idx_set(&root.extra_dependencies, &mut buf);
-        encode_utf8_char(root.ch, &mut buf);
+        root.ch.encode_elem(&mut buf);
     }
 
     // Runs
@@ -707,7 +1031,7 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
     for run in &runs {
         encode_idx(&run.insert_after, &mut buf);
         encode_idx_set(&run.first_extra_deps, &mut buf);
-        encode_string(&run.run, &mut buf);
+        T::encode_run_elems(&run.run, &mut buf);
     }
 
     // Befores
@@ -715,7 +1039,7 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
     for (_id, before) in &befores {
         encode_idx_set(&before.extra_dependencies, &mut buf);
         encode_idx(&before.anchor, &mut buf);
-        encode_utf8_char(before.ch, &mut buf);
+        before.ch.encode_elem(&mut buf);
     }
 
     // Forward remove runs
@@ -758,6 +1082,32 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
         encode_varint(*root_idx, &mut buf);
     }
 
+    // Range removes
+    encode_varint(range_removes.len(), &mut buf);
+    for (_id, range) in &range_removes {
+        encode_idx_set(&range.extra_dependencies, &mut buf);
+        encode_idx(&range.start, &mut buf);
+        encode_idx(&range.end, &mut buf);
+    }
+
+    // Marks (tagged, with idx-encoded IDs)
+    encode_varint(marks.len(), &mut buf);
+    for (_id, mark) in &marks {
+        let op = mark.op::<T>();
+        buf.push(mark_tag(&op));
+        encode_idx_set(&mark.extra_dependencies, &mut buf);
+        encode_mark(&op, &mut buf, encode_idx);
+    }
+
+    // Moves (tagged, with idx-encoded IDs)
+    encode_varint(moves.len(), &mut buf);
+    for (_id, mv) in &moves {
+        let op = mv.op::<T>();
+        buf.push(move_tag(&op));
+        encode_idx_set(&mv.extra_dependencies, &mut buf);
+        encode_move(&op, &mut buf, encode_idx);
+    }
+
     // Orphans (tagged, with idx-encoded IDs)
     encode_varint(orphans.len(), &mut buf);
     for orphan in &orphans {
@@ -765,19 +1115,19 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
             Op::InsertRoot(ch) => {
                 buf.push(TAG_INSERT_ROOT);
                 encode_idx_set(&orphan.extra_dependencies, &mut buf);
-                encode_utf8_char(*ch, &mut buf);
+                ch.encode_elem(&mut buf);
             }
             Op::InsertAfter(id, ch) => {
                 buf.push(TAG_INSERT_AFTER);
                 encode_idx_set(&orphan.extra_dependencies, &mut buf);
                 encode_idx(id, &mut buf);
-                encode_utf8_char(*ch, &mut buf);
+                ch.encode_elem(&mut buf);
             }
             Op::InsertBefore(id, ch) => {
                 buf.push(TAG_INSERT_BEFORE);
                 encode_idx_set(&orphan.extra_dependencies, &mut buf);
                 encode_idx(id, &mut buf);
-                encode_utf8_char(*ch, &mut buf);
+                ch.encode_elem(&mut buf);
             }
             Op::Remove(ids) => {
                 buf.push(TAG_REMOVE);
@@ -787,6 +1137,22 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
                     encode_idx(id, &mut buf);
                 }
             }
+            Op::AddMark { .. } | Op::RemoveMark { .. } => {
+                buf.push(mark_tag(&orphan.op));
+                encode_idx_set(&orphan.extra_dependencies, &mut buf);
+                encode_mark(&orphan.op, &mut buf, encode_idx);
+            }
+            Op::Move { .. } => {
+                buf.push(move_tag(&orphan.op));
+                encode_idx_set(&orphan.extra_dependencies, &mut buf);
+                encode_move(&orphan.op, &mut buf, encode_idx);
+            }
+            Op::RemoveRange { start, end } => {
+                buf.push(TAG_REMOVE_RANGE);
+                encode_idx_set(&orphan.extra_dependencies, &mut buf);
+                encode_idx(start, &mut buf);
+                encode_idx(end, &mut buf);
+            }
         }
     }
 
@@ -794,7 +1160,7 @@ pub fn encode_hashseq(seq: &HashSeq) -> Vec<u8> {
 }
 
 /// Decode a HashSeq from its byte representation.
-pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
+pub fn decode_hashseq<T: EncodeElement>(bytes: &[u8]) -> Result<HashSeq<T>, DecodeError> {
     let mut pos = 0;
 
     // Read dictionary
@@ -841,7 +1207,7 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
     for _ in 0..num_roots {
         let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
         pos += size;
-        let (ch, size) = decode_utf8_char(&bytes[pos..])?;
+        let (ch, size) = T::decode_elem(&bytes[pos..])?;
         pos += size;
         let node = HashNode {
             extra_dependencies: extra_deps,
@@ -859,10 +1225,10 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
         pos += size;
         let (first_extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
         pos += size;
-        let (run_str, size) = decode_string(&bytes[pos..])?;
+        let (run_str, size) = T::decode_run_elems(&bytes[pos..])?;
         pos += size;
 
-        let mut chars = run_str.chars();
+        let mut chars = run_str.elems();
         let first_char = chars.next().ok_or(DecodeError::EmptyRun)?;
         let mut run = Run::new(insert_after, first_extra_deps.clone(), first_char);
         for ch in chars {
@@ -883,7 +1249,7 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
         pos += size;
         let (anchor, size) = decode_idx_at(&bytes[pos..])?;
         pos += size;
-        let (ch, size) = decode_utf8_char(&bytes[pos..])?;
+        let (ch, size) = T::decode_elem(&bytes[pos..])?;
         pos += size;
         let node = HashNode {
             extra_dependencies: extra_deps,
@@ -1038,6 +1404,60 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
         });
     }
 
+    // Range removes
+    let (num_range_removes, size) = decode_varint(&bytes[pos..])?;
+    pos += size;
+    for _ in 0..num_range_removes {
+        let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
+        pos += size;
+        let (start, size) = decode_idx_at(&bytes[pos..])?;
+        pos += size;
+        let (end, size) = decode_idx_at(&bytes[pos..])?;
+        pos += size;
+        seq.apply(HashNode {
+            extra_dependencies: extra_deps,
+            op: Op::RemoveRange { start, end },
+        });
+    }
+
+    // Marks (tagged)
+    let (num_marks, size) = decode_varint(&bytes[pos..])?;
+    pos += size;
+    for _ in 0..num_marks {
+        let tag = *bytes.get(pos).ok_or(DecodeError::UnexpectedEof)?;
+        pos += 1;
+        if tag != TAG_ADD_MARK && tag != TAG_REMOVE_MARK {
+            return Err(DecodeError::InvalidOpTag(tag));
+        }
+        let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
+        pos += size;
+        let (op, size) = decode_mark(tag, &bytes[pos..], decode_idx_at)?;
+        pos += size;
+        seq.apply(HashNode {
+            extra_dependencies: extra_deps,
+            op,
+        });
+    }
+
+    // Moves (tagged)
+    let (num_moves, size) = decode_varint(&bytes[pos..])?;
+    pos += size;
+    for _ in 0..num_moves {
+        let tag = *bytes.get(pos).ok_or(DecodeError::UnexpectedEof)?;
+        pos += 1;
+        if tag != TAG_MOVE_AFTER && tag != TAG_MOVE_BEFORE {
+            return Err(DecodeError::InvalidOpTag(tag));
+        }
+        let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
+        pos += size;
+        let (op, size) = decode_move(tag, &bytes[pos..], decode_idx_at)?;
+        pos += size;
+        seq.apply(HashNode {
+            extra_dependencies: extra_deps,
+            op,
+        });
+    }
+
     // Orphans (tagged)
     let (num_orphans, size) = decode_varint(&bytes[pos..])?;
     pos += size;
@@ -1051,7 +1471,7 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
             TAG_INSERT_ROOT => {
                 let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
                 pos += size;
-                let (ch, size) = decode_utf8_char(&bytes[pos..])?;
+                let (ch, size) = T::decode_elem(&bytes[pos..])?;
                 pos += size;
                 seq.apply(HashNode {
                     extra_dependencies: extra_deps,
@@ -1063,7 +1483,7 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
                 pos += size;
                 let (id, size) = decode_idx_at(&bytes[pos..])?;
                 pos += size;
-                let (ch, size) = decode_utf8_char(&bytes[pos..])?;
+                let (ch, size) = T::decode_elem(&bytes[pos..])?;
                 pos += size;
                 seq.apply(HashNode {
                     extra_dependencies: extra_deps,
@@ -1075,7 +1495,7 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
                 pos += size;
                 let (id, size) = decode_idx_at(&bytes[pos..])?;
                 pos += size;
-                let (ch, size) = decode_utf8_char(&bytes[pos..])?;
+                let (ch, size) = T::decode_elem(&bytes[pos..])?;
                 pos += size;
                 seq.apply(HashNode {
                     extra_dependencies: extra_deps,
@@ -1098,6 +1518,38 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
                     op: Op::Remove(removed_ids),
                 });
             }
+            TAG_ADD_MARK | TAG_REMOVE_MARK => {
+                let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
+                pos += size;
+                let (op, size) = decode_mark(tag, &bytes[pos..], decode_idx_at)?;
+                pos += size;
+                seq.apply(HashNode {
+                    extra_dependencies: extra_deps,
+                    op,
+                });
+            }
+            TAG_MOVE_AFTER | TAG_MOVE_BEFORE => {
+                let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
+                pos += size;
+                let (op, size) = decode_move(tag, &bytes[pos..], decode_idx_at)?;
+                pos += size;
+                seq.apply(HashNode {
+                    extra_dependencies: extra_deps,
+                    op,
+                });
+            }
+            TAG_REMOVE_RANGE => {
+                let (extra_deps, size) = decode_idx_set_at(&bytes[pos..])?;
+                pos += size;
+                let (start, size) = decode_idx_at(&bytes[pos..])?;
+                pos += size;
+                let (end, size) = decode_idx_at(&bytes[pos..])?;
+                pos += size;
+                seq.apply(HashNode {
+                    extra_dependencies: extra_deps,
+                    op: Op::RemoveRange { start, end },
+                });
+            }
             _ => return Err(DecodeError::InvalidOpTag(tag)),
         }
     }
@@ -1105,9 +1557,510 @@ pub fn decode_hashseq(bytes: &[u8]) -> Result<HashSeq, DecodeError> {
     Ok(seq)
 }
 
+// --- Delta encoding/decoding ---
+//
+// A delta is a causally ordered list of ops, as produced by `HashSeq::ops_since`.
+// IDs created by an earlier op in the delta are referenced positionally by
+// (op_idx, elem_idx), the same trick `encode_hashseq` uses for removes. Every
+// other ID goes through a dictionary header.
+//
+// Format: [id_dict][num_ops][ops...]
+//
+// An ID reference is a varint `dict_idx << 1`, or `op_idx << 1 | 1` followed by
+// a varint `elem_idx` when `op_idx` is a run.
+
+/// Encode a list of ops (e.g. from `HashSeq::ops_since`) into a compact delta.
+///
+/// Ops are written in the given order; an op may only be referenced positionally
+/// by ops that come after it, so causally ordered input compresses best.
+pub fn encode_delta<T: EncodeElement>(ops: &[EncodableOp<T>]) -> Vec<u8> {
+    // Dictionary: every referenced ID that isn't produced by an earlier op.
+    let mut produced: HashMap<Id, (usize, usize)> = HashMap::new();
+    let mut id_set: BTreeSet<Id> = BTreeSet::new();
+    for (op_idx, op) in ops.iter().enumerate() {
+        let refs: Vec<&Id> = match op {
+            EncodableOp::Run(run) => std::iter::once(&run.insert_after)
+                .chain(&run.first_extra_deps)
+                .collect(),
+            EncodableOp::Node(node) => node.iter_dependencies().collect(),
+        };
+        id_set.extend(refs.into_iter().filter(|id| !produced.contains_key(*id)));
+        match op {
+            EncodableOp::Run(run) => {
+                for (elem_idx, id) in run.elements.iter().enumerate() {
+                    produced.entry(*id).or_insert((op_idx, elem_idx));
+                }
+            }
+            EncodableOp::Node(node) => {
+                produced.entry(node.id()).or_insert((op_idx, 0));
+            }
+        }
+    }
+
+    let id_list: Vec<Id> = id_set.into_iter().collect();
+    let id_to_idx: HashMap<Id, usize> =
+        id_list.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+    let mut buf = Vec::new();
+    encode_varint(id_list.len(), &mut buf);
+    for id in &id_list {
+        encode_id(id, &mut buf);
+    }
+
+    encode_varint(ops.len(), &mut buf);
+    for (op_idx, op) in ops.iter().enumerate() {
+        let encode_ref = |id: &Id, buf: &mut Vec<u8>| match produced.get(id) {
+            Some(&(src_idx, elem_idx)) if src_idx < op_idx => {
+                encode_varint(src_idx << 1 | 1, buf);
+                if let EncodableOp::Run(_) = ops[src_idx] {
+                    encode_varint(elem_idx, buf);
+                }
+            }
+            _ => encode_varint(id_to_idx[id] << 1, buf),
+        };
+        let encode_ref_set = |ids: &BTreeSet<Id>, buf: &mut Vec<u8>| {
+            encode_varint(ids.len(), buf);
+            for id in ids {
+                encode_ref(id, buf);
+            }
+        };
+
+        match op {
+            EncodableOp::Run(run) => {
+                buf.push(TAG_RUN);
+                encode_ref(&run.insert_after, &mut buf);
+                encode_ref_set(&run.first_extra_deps, &mut buf);
+                T::encode_run_elems(&run.run, &mut buf);
+            }
+            EncodableOp::Node(node) => match &node.op {
+                Op::InsertRoot(ch) => {
+                    buf.push(TAG_INSERT_ROOT);
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    ch.encode_elem(&mut buf);
+                }
+                Op::InsertAfter(id, ch) => {
+                    buf.push(TAG_INSERT_AFTER);
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    encode_ref(id, &mut buf);
+                    ch.encode_elem(&mut buf);
+                }
+                Op::InsertBefore(id, ch) => {
+                    buf.push(TAG_INSERT_BEFORE);
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    encode_ref(id, &mut buf);
+                    ch.encode_elem(&mut buf);
+                }
+                Op::Remove(ids) => {
+                    buf.push(TAG_REMOVE);
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    encode_ref_set(ids, &mut buf);
+                }
+                Op::AddMark { .. } | Op::RemoveMark { .. } => {
+                    buf.push(mark_tag(&node.op));
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    encode_mark(&node.op, &mut buf, encode_ref);
+                }
+                Op::Move { .. } => {
+                    buf.push(move_tag(&node.op));
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    encode_move(&node.op, &mut buf, encode_ref);
+                }
+                Op::RemoveRange { start, end } => {
+                    buf.push(TAG_REMOVE_RANGE);
+                    encode_ref_set(&node.extra_dependencies, &mut buf);
+                    encode_ref(start, &mut buf);
+                    encode_ref(end, &mut buf);
+                }
+            },
+        }
+    }
+
+    buf
+}
+
+/// Decode a delta produced by `encode_delta`.
+pub fn decode_delta<T: EncodeElement>(bytes: &[u8]) -> Result<Vec<EncodableOp<T>>, DecodeError> {
+    let mut pos = 0;
+
+    let (num_ids, size) = decode_varint(bytes)?;
+    pos += size;
+
+    let mut id_list: Vec<Id> = Vec::with_capacity(num_ids.min(bytes.len() / 32));
+    for _ in 0..num_ids {
+        let (id, size) = decode_id(&bytes[pos..])?;
+        id_list.push(id);
+        pos += size;
+    }
+
+    let (num_ops, size) = decode_varint(&bytes[pos..])?;
+    pos += size;
+
+    let mut ops: Vec<EncodableOp<T>> = Vec::new();
+    for _ in 0..num_ops {
+        let decode_ref = |bytes: &[u8]| -> Result<(Id, usize), DecodeError> {
+            let (r, mut size) = decode_varint(bytes)?;
+            if r & 1 == 0 {
+                let idx = r >> 1;
+                let id = id_list
+                    .get(idx)
+                    .copied()
+                    .ok_or(DecodeError::InvalidIdIndex(idx))?;
+                return Ok((id, size));
+            }
+            let op_idx = r >> 1;
+            let id = match ops.get(op_idx) {
+                Some(EncodableOp::Run(run)) => {
+                    let (elem_idx, elem_size) = decode_varint(&bytes[size..])?;
+                    size += elem_size;
+                    run.elements
+                        .get(elem_idx)
+                        .copied()
+                        .ok_or(DecodeError::InvalidIdIndex(elem_idx))?
+                }
+                Some(EncodableOp::Node(node)) => node.id(),
+                None => return Err(DecodeError::InvalidIdIndex(op_idx)),
+            };
+            Ok((id, size))
+        };
+        let decode_ref_set = |bytes: &[u8]| -> Result<(BTreeSet<Id>, usize), DecodeError> {
+            let (count, mut total) = decode_varint(bytes)?;
+            let mut ids = BTreeSet::new();
+            for _ in 0..count {
+                let (id, size) = decode_ref(&bytes[total..])?;
+                ids.insert(id);
+                total += size;
+            }
+            Ok((ids, total))
+        };
+
+        if pos >= bytes.len() {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let tag = bytes[pos];
+        pos += 1;
+        let op = match tag {
+            TAG_RUN => {
+                let (insert_after, size) = decode_ref(&bytes[pos..])?;
+                pos += size;
+                let (first_extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (run_str, size) = T::decode_run_elems(&bytes[pos..])?;
+                pos += size;
+
+                let mut chars = run_str.elems();
+                let first_char = chars.next().ok_or(DecodeError::EmptyRun)?;
+                let mut run = Run::new(insert_after, first_extra_deps, first_char);
+                for ch in chars {
+                    run.extend(ch);
+                }
+                EncodableOp::Run(run)
+            }
+            TAG_INSERT_ROOT => {
+                let (extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (ch, size) = T::decode_elem(&bytes[pos..])?;
+                pos += size;
+                EncodableOp::Node(HashNode {
+                    extra_dependencies: extra_deps,
+                    op: Op::InsertRoot(ch),
+                })
+            }
+            TAG_INSERT_AFTER | TAG_INSERT_BEFORE => {
+                let (extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (id, size) = decode_ref(&bytes[pos..])?;
+                pos += size;
+                let (ch, size) = T::decode_elem(&bytes[pos..])?;
+                pos += size;
+                let op = if tag == TAG_INSERT_AFTER {
+                    Op::InsertAfter(id, ch)
+                } else {
+                    Op::InsertBefore(id, ch)
+                };
+                EncodableOp::Node(HashNode {
+                    extra_dependencies: extra_deps,
+                    op,
+                })
+            }
+            TAG_REMOVE => {
+                let (extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (removed_ids, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                EncodableOp::Node(HashNode {
+                    extra_dependencies: extra_deps,
+                    op: Op::Remove(removed_ids),
+                })
+            }
+            TAG_ADD_MARK | TAG_REMOVE_MARK => {
+                let (extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (op, size) = decode_mark(tag, &bytes[pos..], decode_ref)?;
+                pos += size;
+                EncodableOp::Node(HashNode {
+                    extra_dependencies: extra_deps,
+                    op,
+                })
+            }
+            TAG_MOVE_AFTER | TAG_MOVE_BEFORE => {
+                let (extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (op, size) = decode_move(tag, &bytes[pos..], decode_ref)?;
+                pos += size;
+                EncodableOp::Node(HashNode {
+                    extra_dependencies: extra_deps,
+                    op,
+                })
+            }
+            TAG_REMOVE_RANGE => {
+                let (extra_deps, size) = decode_ref_set(&bytes[pos..])?;
+                pos += size;
+                let (start, size) = decode_ref(&bytes[pos..])?;
+                pos += size;
+                let (end, size) = decode_ref(&bytes[pos..])?;
+                pos += size;
+                EncodableOp::Node(HashNode {
+                    extra_dependencies: extra_deps,
+                    op: Op::RemoveRange { start, end },
+                })
+            }
+            _ => return Err(DecodeError::InvalidOpTag(tag)),
+        };
+        ops.push(op);
+    }
+
+    Ok(ops)
+}
+
+// --- Bloom filter encoding/decoding ---
+//
+// Format: [num_hashes][num_words][words...], each word 8 bytes little-endian.
+
+pub fn encode_bloom_filter(filter: &BloomFilter, buf: &mut Vec<u8>) {
+    encode_varint(filter.num_hashes() as usize, buf);
+    encode_varint(filter.bits().len(), buf);
+    for word in filter.bits() {
+        buf.extend_from_slice(&word.to_le_bytes());
+    }
+}
+
+pub fn decode_bloom_filter(bytes: &[u8]) -> Result<(BloomFilter, usize), DecodeError> {
+    let (num_hashes, mut pos) = decode_varint(bytes)?;
+    // Zero hashes would contain everything, and a huge count makes every
+    // lookup loop that many times.
+    let num_hashes = u32::try_from(num_hashes)
+        .ok()
+        .filter(|n| (1..=BloomFilter::MAX_HASHES).contains(n))
+        .ok_or(DecodeError::InvalidBloomHashCount(num_hashes))?;
+    let (num_words, size) = decode_varint(&bytes[pos..])?;
+    pos += size;
+    if num_words == 0 {
+        return Err(DecodeError::EmptyBloomFilter);
+    }
+
+    let words = bytes[pos..]
+        .get(..num_words.checked_mul(8).ok_or(DecodeError::UnexpectedEof)?)
+        .ok_or(DecodeError::UnexpectedEof)?;
+    let bits = words
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    pos += words.len();
+
+    Ok((BloomFilter::from_parts(bits, num_hashes), pos))
+}
+
+// --- Cursor encoding/decoding ---
+//
+// Format: [flags][anchor?], where bit 0 of the flags is set for right gravity
+// and bit 1 when an anchor ID follows.
+
+const CURSOR_RIGHT: u8 = 0x01;
+const CURSOR_ANCHORED: u8 = 0x02;
+
+pub fn encode_cursor(cursor: &Cursor, buf: &mut Vec<u8>) {
+    encode_cursor_with(cursor, buf, encode_id);
+}
+
+pub fn decode_cursor(bytes: &[u8]) -> Result<(Cursor, usize), DecodeError> {
+    decode_cursor_with(bytes, decode_id)
+}
+
+fn encode_cursor_with(cursor: &Cursor, buf: &mut Vec<u8>, encode_ref: impl Fn(&Id, &mut Vec<u8>)) {
+    let mut flags = 0;
+    if cursor.gravity == Gravity::Right {
+        flags |= CURSOR_RIGHT;
+    }
+    if cursor.anchor.is_some() {
+        flags |= CURSOR_ANCHORED;
+    }
+    buf.push(flags);
+    if let Some(anchor) = &cursor.anchor {
+        encode_ref(anchor, buf);
+    }
+}
+
+fn decode_cursor_with(
+    bytes: &[u8],
+    decode_ref: impl Fn(&[u8]) -> Result<(Id, usize), DecodeError>,
+) -> Result<(Cursor, usize), DecodeError> {
+    let (&flags, rest) = bytes.split_first().ok_or(DecodeError::UnexpectedEof)?;
+    if flags & !(CURSOR_RIGHT | CURSOR_ANCHORED) != 0 {
+        return Err(DecodeError::InvalidCursorFlags(flags));
+    }
+    let gravity = if flags & CURSOR_RIGHT != 0 {
+        Gravity::Right
+    } else {
+        Gravity::Left
+    };
+    let (anchor, size) = if flags & CURSOR_ANCHORED != 0 {
+        let (id, size) = decode_ref(rest)?;
+        (Some(id), size)
+    } else {
+        (None, 0)
+    };
+    Ok((Cursor { anchor, gravity }, 1 + size))
+}
+
+// --- Sync message encoding/decoding ---
+//
+// Format: [tag][payload], where Announce and Request carry an ID set, Ops
+// carries a delta (see `encode_delta`) and Summary a Bloom filter.
+
+const MSG_ANNOUNCE: u8 = 0x00;
+const MSG_REQUEST: u8 = 0x01;
+const MSG_OPS: u8 = 0x02;
+const MSG_SUMMARY: u8 = 0x03;
+
+pub fn encode_sync_message<T: EncodeElement>(msg: &SyncMessage<T>) -> Vec<u8> {
+    match msg {
+        SyncMessage::Announce(tips) => {
+            let mut buf = vec![MSG_ANNOUNCE];
+            encode_id_set(tips, &mut buf);
+            buf
+        }
+        SyncMessage::Request(ids) => {
+            let mut buf = vec![MSG_REQUEST];
+            encode_id_set(ids, &mut buf);
+            buf
+        }
+        SyncMessage::Ops(ops) => {
+            let mut buf = vec![MSG_OPS];
+            buf.extend(encode_delta(ops));
+            buf
+        }
+        SyncMessage::Summary(filter) => {
+            let mut buf = vec![MSG_SUMMARY];
+            encode_bloom_filter(filter, &mut buf);
+            buf
+        }
+    }
+}
+
+pub fn decode_sync_message<T: EncodeElement>(bytes: &[u8]) -> Result<SyncMessage<T>, DecodeError> {
+    let (&tag, payload) = bytes.split_first().ok_or(DecodeError::UnexpectedEof)?;
+    match tag {
+        MSG_ANNOUNCE => Ok(SyncMessage::Announce(decode_id_set(payload)?.0)),
+        MSG_REQUEST => Ok(SyncMessage::Request(decode_id_set(payload)?.0)),
+        MSG_OPS => Ok(SyncMessage::Ops(decode_delta(payload)?)),
+        MSG_SUMMARY => Ok(SyncMessage::Summary(decode_bloom_filter(payload)?.0)),
+        _ => Err(DecodeError::InvalidMessageTag(tag)),
+    }
+}
+
+// --- Range reconciliation message encoding/decoding ---
+//
+// Format: [num_ranges][ranges...], where each range is an upper bound
+// (0x00 for the end of the id space, or 0x01 followed by an ID) and a
+// mode tag followed by its payload.
+
+const RANGE_SKIP: u8 = 0x00;
+const RANGE_FINGERPRINT: u8 = 0x01;
+const RANGE_ID_LIST: u8 = 0x02;
+
+pub fn encode_reconcile_message(msg: &ReconcileMessage) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_varint(msg.ranges.len(), &mut buf);
+    for range in &msg.ranges {
+        match &range.upper {
+            None => buf.push(0x00),
+            Some(id) => {
+                buf.push(0x01);
+                encode_id(id, &mut buf);
+            }
+        }
+        match &range.mode {
+            RangeMode::Skip => buf.push(RANGE_SKIP),
+            RangeMode::Fingerprint(fp) => {
+                buf.push(RANGE_FINGERPRINT);
+                encode_varint(fp.count, &mut buf);
+                buf.extend_from_slice(&fp.xor);
+            }
+            RangeMode::IdList(ids) => {
+                buf.push(RANGE_ID_LIST);
+                encode_varint(ids.len(), &mut buf);
+                for id in ids {
+                    encode_id(id, &mut buf);
+                }
+            }
+        }
+    }
+    buf
+}
+
+pub fn decode_reconcile_message(bytes: &[u8]) -> Result<ReconcileMessage, DecodeError> {
+    let (num_ranges, mut pos) = decode_varint(bytes)?;
+
+    let mut ranges = Vec::with_capacity(num_ranges.min(bytes.len() / 2));
+    for _ in 0..num_ranges {
+        let upper = match bytes.get(pos) {
+            None => return Err(DecodeError::UnexpectedEof),
+            Some(0x00) => {
+                pos += 1;
+                None
+            }
+            Some(0x01) => {
+                let (id, size) = decode_id(&bytes[pos + 1..])?;
+                pos += 1 + size;
+                Some(id)
+            }
+            Some(&tag) => return Err(DecodeError::InvalidRangeTag(tag)),
+        };
+
+        let tag = *bytes.get(pos).ok_or(DecodeError::UnexpectedEof)?;
+        pos += 1;
+        let mode = match tag {
+            RANGE_SKIP => RangeMode::Skip,
+            RANGE_FINGERPRINT => {
+                let (count, size) = decode_varint(&bytes[pos..])?;
+                pos += size;
+                let (xor, size) = decode_id(&bytes[pos..])?;
+                pos += size;
+                RangeMode::Fingerprint(Fingerprint { count, xor: xor.0 })
+            }
+            RANGE_ID_LIST => {
+                let (count, size) = decode_varint(&bytes[pos..])?;
+                pos += size;
+                let mut ids = Vec::with_capacity(count.min(bytes.len() / 32));
+                for _ in 0..count {
+                    let (id, size) = decode_id(&bytes[pos..])?;
+                    ids.push(id);
+                    pos += size;
+                }
+                RangeMode::IdList(ids)
+            }
+            _ => return Err(DecodeError::InvalidRangeTag(tag)),
+        };
+        ranges.push(Range { upper, mode });
+    }
+
+    Ok(ReconcileMessage { ranges })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Expand;
     use quickcheck_macros::quickcheck;
 
     fn test_id(n: u8) -> Id {
@@ -1231,7 +2184,7 @@ mod tests {
         remove_ids.insert(test_id(2));
         remove_ids.insert(test_id(3));
 
-        let node = HashNode {
+        let node: HashNode = HashNode {
             extra_dependencies: BTreeSet::new(),
             op: Op::Remove(remove_ids),
         };
@@ -1322,7 +2275,7 @@ mod tests {
 
     #[test]
     fn test_hashseq_empty_roundtrip() {
-        let seq = HashSeq::default();
+        let seq: HashSeq = HashSeq::default();
         let encoded = encode_hashseq(&seq);
         let decoded = decode_hashseq(&encoded).unwrap();
 
@@ -1428,7 +2381,7 @@ mod tests {
         let original_str: String = seq.iter().collect();
 
         let encoded = encode_hashseq(&seq);
-        let decoded = decode_hashseq(&encoded).unwrap();
+        let decoded: HashSeq = decode_hashseq(&encoded).unwrap();
 
         original_str == decoded.iter().collect::<String>()
     }
@@ -1473,7 +2426,7 @@ mod tests {
         }
 
         let first = encode_hashseq(&seq);
-        let decoded = decode_hashseq(&first).unwrap();
+        let decoded: HashSeq = decode_hashseq(&first).unwrap();
         let second = encode_hashseq(&decoded);
         first == second
     }
@@ -1500,5 +2453,206 @@ mod tests {
 
         original_str == decoded.iter().collect::<String>() && seq == decoded
     }
+    #[test]
+    fn test_delta_roundtrip() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello".chars());
+        let tips = seq.tips().clone();
+
+        seq.insert_batch(5, " world".chars());
+        seq.insert(0, '>');
+        seq.remove(3);
+
+        let ops = seq.ops_since(&tips);
+        let encoded = encode_delta(&ops);
+        assert_eq!(decode_delta(&encoded).unwrap(), ops);
+    }
+
+    #[test]
+    fn test_delta_rejects_forward_op_ref() {
+        // One op (a root) whose only dependency points at op 0, i.e. itself.
+        let bytes = [0x00, 0x01, TAG_INSERT_ROOT, 0x01, 0x01, b'a'];
+        assert_eq!(decode_delta::<char>(&bytes), Err(DecodeError::InvalidIdIndex(0)));
+    }
+
+    /// Ops produced earlier in the delta are referenced positionally, so a delta
+    /// of local edits only pays for the IDs the remote already has.
+    #[test]
+    fn test_delta_is_smaller_than_batch() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "the quick brown fox".chars());
+        let tips = seq.tips().clone();
+
+        for i in 0..10 {
+            seq.insert(i * 2, '_');
+        }
+        seq.remove(5);
+
+        let ops = seq.ops_since(&tips);
+        assert!(encode_delta(&ops).len() < encode_batch(&ops).len());
+    }
+
+    #[test]
+    fn test_marks_roundtrip() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello world".chars());
+        let tips = seq.tips().clone();
+        seq.add_mark(0..5, "bold", "true", Expand::End);
+        seq.insert(0, '>');
+        seq.add_mark(0..seq.len(), "link", "https://example.com", Expand::None);
+        seq.remove_mark(2..4, "bold", Expand::Both);
+
+        let ops = seq.ops_since(&tips);
+        assert_eq!(decode_batch(&encode_batch(&ops)).unwrap(), ops);
+        assert_eq!(decode_delta(&encode_delta(&ops)).unwrap(), ops);
+
+        let decoded: HashSeq = decode_hashseq(&encode_hashseq(&seq)).unwrap();
+        assert_eq!(decoded, seq);
+        assert_eq!(decoded.mark_spans(), seq.mark_spans());
+
+        // Marks still waiting on their text survive as orphans.
+        let mut fresh = HashSeq::default();
+        for op in ops {
+            fresh.apply_op(op);
+        }
+        let decoded: HashSeq = decode_hashseq(&encode_hashseq(&fresh)).unwrap();
+        assert_eq!(decoded.orphans().len(), fresh.orphans().len());
+        assert!(decoded.orphans().iter().all(|node| fresh.orphans().contains(&node.id())));
+    }
+
+    #[test]
+    fn test_moves_roundtrip() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "one two three".chars());
+        let tips = seq.tips().clone();
+        seq.move_range(4..8, 13);
+        seq.move_range(9..13, 0);
+        seq.insert(8, '!');
+        assert_eq!(seq.to_string(), "two one !three");
+
+        let ops = seq.ops_since(&tips);
+        assert_eq!(decode_batch(&encode_batch(&ops)).unwrap(), ops);
+        assert_eq!(decode_delta(&encode_delta(&ops)).unwrap(), ops);
+
+        let decoded: HashSeq = decode_hashseq(&encode_hashseq(&seq)).unwrap();
+        assert_eq!(decoded, seq);
+        assert_eq!(decoded.to_string(), "two one !three");
+
+        // Moves still waiting on their text survive as orphans.
+        let mut fresh = HashSeq::default();
+        for op in ops {
+            fresh.apply_op(op);
+        }
+        let decoded: HashSeq = decode_hashseq(&encode_hashseq(&fresh)).unwrap();
+        assert_eq!(decoded.orphans().len(), fresh.orphans().len());
+        assert!(decoded.orphans().iter().all(|node| fresh.orphans().contains(&node.id())));
+    }
+
+    #[test]
+    fn test_range_removes_roundtrip() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "one two three".chars());
+        let mut bob = seq.clone();
+        let tips = seq.tips().clone();
+        seq.remove_range_intent(3, 4);
+        seq.insert(3, '!');
+        bob.insert(5, '?');
+        seq.merge(&bob);
+        assert_eq!(seq.to_string(), "one! three");
+
+        let ops = seq.ops_since(&tips);
+        assert_eq!(decode_batch(&encode_batch(&ops)).unwrap(), ops);
+        assert_eq!(decode_delta(&encode_delta(&ops)).unwrap(), ops);
+
+        let decoded: HashSeq = decode_hashseq(&encode_hashseq(&seq)).unwrap();
+        assert_eq!(decoded, seq);
+        assert_eq!(decoded.to_string(), "one! three");
+    }
+
+    #[quickcheck]
+    fn prop_delta_roundtrip(base: Vec<(bool, u8, char)>, edits: Vec<(bool, u8, char)>) -> bool {
+        fn apply(seq: &mut HashSeq, ops: Vec<(bool, u8, char)>) {
+            for (is_insert, idx, ch) in ops {
+                let idx = idx as usize;
+                if is_insert {
+                    seq.insert(idx % (seq.len() + 1), ch);
+                } else if !seq.is_empty() {
+                    seq.remove(idx % seq.len());
+                }
+            }
+        }
+
+        let mut seq = HashSeq::default();
+        apply(&mut seq, base);
+        let tips = seq.tips().clone();
+        apply(&mut seq, edits);
+
+        let ops = seq.ops_since(&tips);
+        decode_delta(&encode_delta(&ops)).unwrap() == ops
+    }
+    #[test]
+    fn test_sync_message_roundtrip() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "sync".chars());
+        seq.remove(0);
+
+        let msgs = [
+            SyncMessage::Announce(seq.tips().clone()),
+            SyncMessage::Request(BTreeSet::new()),
+            SyncMessage::Ops(seq.ops_since(&BTreeSet::new())),
+        ];
+        for msg in msgs {
+            assert_eq!(decode_sync_message(&encode_sync_message(&msg)).unwrap(), msg);
+        }
+        assert_eq!(decode_sync_message::<char>(&[0x07]), Err(DecodeError::InvalidMessageTag(0x07)));
+        assert_eq!(decode_sync_message::<char>(&[]), Err(DecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_reconcile_message_rejects_bad_tags() {
+        assert_eq!(decode_reconcile_message(&[0x01, 0x02]), Err(DecodeError::InvalidRangeTag(0x02)));
+        assert_eq!(decode_reconcile_message(&[0x01, 0x00, 0x07]), Err(DecodeError::InvalidRangeTag(0x07)));
+        assert_eq!(decode_reconcile_message(&[0x01, 0x00]), Err(DecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_bloom_filter_roundtrip() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "bloom".chars());
+        let filter = BloomFilter::from_seq(&seq, 0.01);
+
+        let mut buf = Vec::new();
+        encode_bloom_filter(&filter, &mut buf);
+        assert_eq!(decode_bloom_filter(&buf).unwrap(), (filter.clone(), buf.len()));
+
+        let msg: SyncMessage = SyncMessage::Summary(filter);
+        assert_eq!(decode_sync_message(&encode_sync_message(&msg)).unwrap(), msg);
+
+        assert_eq!(decode_bloom_filter(&[0x03, 0x00]), Err(DecodeError::EmptyBloomFilter));
+        assert_eq!(decode_bloom_filter(&buf[..buf.len() - 1]), Err(DecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_bloom_filter_rejects_bad_hash_counts() {
+        let filter = |num_hashes: usize| {
+            let mut buf = Vec::new();
+            encode_varint(num_hashes, &mut buf);
+            encode_varint(1, &mut buf);
+            buf.extend_from_slice(&u64::MAX.to_le_bytes());
+            buf
+        };
+
+        // Zero hashes would claim to contain every id.
+        assert_eq!(decode_bloom_filter(&filter(0)), Err(DecodeError::InvalidBloomHashCount(0)));
+        assert_eq!(decode_bloom_filter(&filter(33)), Err(DecodeError::InvalidBloomHashCount(33)));
+        assert_eq!(
+            decode_bloom_filter(&filter(u32::MAX as usize)),
+            Err(DecodeError::InvalidBloomHashCount(u32::MAX as usize))
+        );
+        assert!(decode_bloom_filter(&filter(32)).is_ok());
+
+        let msg = [&[MSG_SUMMARY][..], &filter(u32::MAX as usize)].concat();
+        assert_eq!(decode_sync_message::<char>(&msg), Err(DecodeError::InvalidBloomHashCount(u32::MAX as usize)));
+    }
 
 }
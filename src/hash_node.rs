@@ -2,59 +2,99 @@ use std::collections::BTreeSet;
 
 use serde::{Deserialize, Serialize};
 
-use crate::Id;
+use crate::{Cursor, Element, Gravity, Id};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
-pub enum Op {
-    InsertRoot(char),
-    InsertAfter(Id, char),
-    InsertBefore(Id, char),
+pub enum Op<T = char> {
+    InsertRoot(T),
+    InsertAfter(Id, T),
+    InsertBefore(Id, T),
     Remove(BTreeSet<Id>),
+    /// Format the elements between two cursors, e.g. `bold` or a `link` to a URL.
+    /// Where marks with the same name overlap, the highest `(clock, id)` wins.
+    AddMark {
+        start: Cursor,
+        end: Cursor,
+        clock: u64,
+        name: String,
+        value: String,
+    },
+    /// Clear the mark called `name` between two cursors.
+    RemoveMark {
+        start: Cursor,
+        end: Cursor,
+        clock: u64,
+        name: String,
+    },
+    /// Put the inserted element `element` right after `anchor`, or right before
+    /// it if `before` is set. Where moves of the same element are concurrent,
+    /// the highest `clock` wins, then the move that started the range.
+    Move {
+        anchor: Id,
+        before: bool,
+        element: Id,
+        clock: u64,
+    },
+    /// Remove everything from `start` to `end`, both included, in iteration
+    /// order: the elements there now, and the ones peers insert or move
+    /// between them concurrently. Whatever is put there after seeing the
+    /// remove stays.
+    RemoveRange {
+        start: Id,
+        end: Id,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct HashNode {
+pub struct HashNode<T = char> {
     pub extra_dependencies: BTreeSet<Id>,
-    pub op: Op,
+    pub op: Op<T>,
 }
 
-impl Op {
-    /// Returns the primary dependency if this op has one (avoids allocation)
-    fn primary_dep(&self) -> Option<&Id> {
+impl<T: Element> Op<T> {
+    /// Returns the ids this op is positioned against (avoids allocation)
+    pub(crate) fn anchors(&self) -> impl Iterator<Item = &Id> {
         match self {
-            Op::InsertRoot(_) => None,
-            Op::InsertAfter(dep, _) | Op::InsertBefore(dep, _) => Some(dep),
-            Op::Remove(_) => None,
-        }
-    }
-
-    /// Returns iterator over remove dependencies (for Remove ops only)
-    fn remove_deps(&self) -> impl Iterator<Item = &Id> {
-        match self {
-            Op::Remove(deps) => Some(deps.iter()),
-            _ => None,
+            Op::InsertRoot(_) | Op::Remove(_) => [None, None],
+            Op::InsertAfter(dep, _) | Op::InsertBefore(dep, _) | Op::Move { anchor: dep, .. } => {
+                [Some(dep), None]
+            }
+            Op::AddMark { start, end, .. } | Op::RemoveMark { start, end, .. } => {
+                [start.anchor.as_ref(), end.anchor.as_ref()]
+            }
+            Op::RemoveRange { start, end } => [Some(start), Some(end)],
         }
         .into_iter()
         .flatten()
     }
 
+    /// Returns the elements a Remove or Move acts on (avoids allocation)
+    pub(crate) fn targets(&self) -> impl Iterator<Item = &Id> {
+        let (removed, moved) = match self {
+            Op::Remove(deps) => (Some(deps.iter()), None),
+            Op::Move { element, .. } => (None, Some(element)),
+            _ => (None, None),
+        };
+        removed.into_iter().flatten().chain(moved)
+    }
+
     fn hash_update(&self, hasher: &mut blake3::Hasher) {
         match self {
             Op::InsertRoot(c) => {
                 hasher.update(b"root");
-                hasher.update(&(*c as u32).to_le_bytes());
+                c.hash_update(hasher);
             }
             Op::InsertAfter(n, c) => {
                 hasher.update(b"after");
                 hasher.update(&n.0);
                 hasher.update(b"$");
-                hasher.update(&(*c as u32).to_le_bytes());
+                c.hash_update(hasher);
             }
             Op::InsertBefore(n, c) => {
                 hasher.update(b"before");
                 hasher.update(&n.0);
                 hasher.update(b"$");
-                hasher.update(&(*c as u32).to_le_bytes());
+                c.hash_update(hasher);
             }
             Op::Remove(n) => {
                 hasher.update(b"remove");
@@ -62,17 +102,77 @@ impl Op {
                     hasher.update(&node_id.0);
                 }
             }
+            Op::AddMark {
+                start,
+                end,
+                clock,
+                name,
+                value,
+            } => {
+                hasher.update(b"add_mark");
+                hash_mark(hasher, start, end, *clock, name);
+                hasher.update(&(value.len() as u64).to_le_bytes());
+                hasher.update(value.as_bytes());
+            }
+            Op::RemoveMark {
+                start,
+                end,
+                clock,
+                name,
+            } => {
+                hasher.update(b"remove_mark");
+                hash_mark(hasher, start, end, *clock, name);
+            }
+            Op::Move {
+                anchor,
+                before,
+                element,
+                clock,
+            } => {
+                let side: &[u8] = if *before {
+                    b"move_before"
+                } else {
+                    b"move_after"
+                };
+                hasher.update(side);
+                hasher.update(&anchor.0);
+                hasher.update(&element.0);
+                hasher.update(&clock.to_le_bytes());
+            }
+            Op::RemoveRange { start, end } => {
+                hasher.update(b"remove_range");
+                hasher.update(&start.0);
+                hasher.update(&end.0);
+            }
         }
     }
 }
 
-impl HashNode {
+fn hash_mark(hasher: &mut blake3::Hasher, start: &Cursor, end: &Cursor, clock: u64, name: &str) {
+    // Fixed-width tags, so that no two cursor pairs hash the same bytes.
+    for cursor in [start, end] {
+        let gravity = match cursor.gravity {
+            Gravity::Left => 0u8,
+            Gravity::Right => 1,
+        };
+        hasher.update(&[gravity]);
+        match &cursor.anchor {
+            Some(anchor) => hasher.update(&[1]).update(&anchor.0),
+            None => hasher.update(&[0]),
+        };
+    }
+    hasher.update(&clock.to_le_bytes());
+    hasher.update(&(name.len() as u64).to_le_bytes());
+    hasher.update(name.as_bytes());
+}
+
+impl<T: Element> HashNode<T> {
     /// Iterate over all dependencies without allocation
     pub fn iter_dependencies(&self) -> impl Iterator<Item = &Id> {
         self.extra_dependencies
             .iter()
-            .chain(self.op.primary_dep())
-            .chain(self.op.remove_deps())
+            .chain(self.op.anchors())
+            .chain(self.op.targets())
     }
 
     pub fn id(&self) -> Id {
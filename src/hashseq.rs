@@ -1,9 +1,21 @@
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ops::{Bound, Range};
+use std::panic::{self, AssertUnwindSafe};
 
-use associative_positional_list::AssociativePositionalList;
 use rustc_hash::{FxHashMap, FxHashSet};
-
-use crate::{HashNode, HashSeqIter, Id, Op, Run};
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+use crate::annotations::Expand;
+use crate::changes::ChangeLog;
+use crate::cursor::Gravity;
+use crate::element::RunStorage;
+use crate::orphans::{DroppedOrphan, OrphanPolicy};
+use crate::transaction::{Journal, OpenJournal, Undo};
+use crate::validation::{RejectedSet, ValidationPolicy, Validator};
+use crate::{
+    BloomFilter, Change, Chunks, Cursor, Element, EncodableOp, HashNode, HashSeqIter, Id, Op,
+    OrphanBuffer, Run, Transaction, WeightedList,
+};
 
 /// HashMap keyed by `Id`. Uses FxHash instead of SipHash: safe because `Id` is
 /// already a BLAKE3 hash, so adversaries cannot craft colliding keys without
@@ -12,18 +24,75 @@ pub type IdMap<V> = FxHashMap<Id, V>;
 /// HashSet of `Id`. Same FxHash rationale as `IdMap`.
 pub type IdSet = FxHashSet<Id>;
 
+/// Which side of its parent a node hangs off of in the causal tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Before,
+    After,
+}
+
+/// What happened to a node handed to `HashSeq::try_apply`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyOutcome {
+    /// The node was applied. `unorphaned` lists previously orphaned nodes that
+    /// could be applied as a result, in the order they were applied, and
+    /// `rejected` the ones that were rejected instead, and why.
+    Applied {
+        unorphaned: Vec<Id>,
+        rejected: Vec<(Id, RejectReason)>,
+    },
+    /// We already had this node.
+    Duplicate,
+    /// Some dependencies haven't arrived yet; the node waits in the orphan buffer.
+    Orphaned { missing: Vec<Id> },
+    /// The node can never be applied. Orphans that depended on it are rejected
+    /// too, and can be checked with `HashSeq::is_rejected`.
+    Rejected(RejectReason),
+}
+
+/// Why a node was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// An insert, move, mark or range remove anchored on a Remove or
+    /// RemoveRange node: removes have no position to insert next to.
+    AnchorIsRemove(Id),
+    /// An insert, move, mark or range remove anchored on a mark node, which
+    /// has no position either.
+    AnchorIsMark(Id),
+    /// The node depends on a node that was rejected.
+    DependsOnRejected(Id),
+    /// A Remove that doesn't remove anything.
+    EmptyRemove,
+    /// A Remove targeting a Remove or RemoveRange node.
+    RemovesRemove(Id),
+    /// A Remove targeting a mark node. Marks are cleared with `RemoveMark`.
+    RemovesMark(Id),
+    /// A Remove targeting a move node. The element the move holds is removed
+    /// by its own id.
+    RemovesMove(Id),
+    /// A Move of something that isn't an inserted element.
+    MovesNonInsert(Id),
+    /// A mark or move whose clock is more than one past the highest clock of
+    /// its kind in its causal history. Honest replicas only ever count up by one.
+    ClockTooHigh { clock: u64, max: u64 },
+    /// More `extra_dependencies` than the policy allows.
+    TooManyDependencies { count: usize, max: usize },
+    /// Rejected by a custom `ValidationPolicy`.
+    Policy(String),
+}
+
 /// Location information for where a node ID can be found
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RunPosition {
     pub run_id: Id,
     pub position: usize,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
-pub struct CausalInsert {
+pub struct CausalInsert<T = char> {
     pub extra_dependencies: BTreeSet<Id>,
     pub anchor: Id,
-    pub ch: char,
+    pub ch: T,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -32,21 +101,116 @@ pub struct CausalRemove {
     pub nodes: BTreeSet<Id>,
 }
 
+/// A `RemoveRange`. Its span is resolved against the causal tree as nodes
+/// arrive, so it isn't stored here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CausalRemoveRange {
+    pub extra_dependencies: BTreeSet<Id>,
+    pub start: Id,
+    pub end: Id,
+}
+
+impl CausalRemoveRange {
+    pub(crate) fn op<T>(&self) -> Op<T> {
+        Op::RemoveRange {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
-pub struct CausalRoot {
+pub struct CausalRoot<T = char> {
+    pub extra_dependencies: BTreeSet<Id>,
+    pub ch: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CausalMark {
+    pub extra_dependencies: BTreeSet<Id>,
+    pub start: Cursor,
+    pub end: Cursor,
+    pub clock: u64,
+    pub name: String,
+    /// The value the mark is set to, `None` if it is cleared.
+    pub value: Option<String>,
+}
+
+impl CausalMark {
+    pub(crate) fn op<T>(&self) -> Op<T> {
+        let (start, end, clock, name) = (self.start, self.end, self.clock, self.name.clone());
+        match &self.value {
+            Some(value) => Op::AddMark {
+                start,
+                end,
+                clock,
+                name,
+                value: value.clone(),
+            },
+            None => Op::RemoveMark {
+                start,
+                end,
+                clock,
+                name,
+            },
+        }
+    }
+}
+
+/// A `Move` of `element`. It's a slot in the causal tree like an insert is,
+/// which holds the element while it's the winning move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CausalMove {
     pub extra_dependencies: BTreeSet<Id>,
-    pub ch: char,
+    pub anchor: Id,
+    pub before: bool,
+    pub element: Id,
+    pub clock: u64,
+    /// The first move of the range this one was made with. Concurrent moves
+    /// with the same clock are ordered by it, so a range moves as a whole.
+    pub head: Id,
+}
+
+impl CausalMove {
+    pub(crate) fn op<T>(&self) -> Op<T> {
+        Op::Move {
+            anchor: self.anchor,
+            before: self.before,
+            element: self.element,
+            clock: self.clock,
+        }
+    }
+}
+
+/// The highest clocks in a node's causal history, the node itself included.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Clocks {
+    mark: u64,
+    moves: u64,
+}
+
+impl Clocks {
+    fn max(self, other: Self) -> Self {
+        Self {
+            mark: self.mark.max(other.mark),
+            moves: self.moves.max(other.moves),
+        }
+    }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct HashSeq {
+/// A sequence of `T`s, `char` by default.
+#[derive(Debug, Clone)]
+pub struct HashSeq<T: Element = char> {
     // Sequential inserts are coalesced into runs; everything else lives as individual nodes.
-    pub runs: IdMap<Run>,
-    pub root_nodes: BTreeMap<Id, CausalRoot>,
-    pub before_nodes: IdMap<CausalInsert>,
+    pub runs: IdMap<Run<T>>,
+    pub root_nodes: BTreeMap<Id, CausalRoot<T>>,
+    pub before_nodes: IdMap<CausalInsert<T>>,
     // Reverse index: anchor -> list of nodes inserted before that anchor
     pub befores_by_anchor: IdMap<BTreeSet<Id>>,
     pub remove_nodes: IdMap<CausalRemove>,
+    pub remove_range_nodes: IdMap<CausalRemoveRange>,
+    pub mark_nodes: IdMap<CausalMark>,
+    pub move_nodes: IdMap<CausalMove>,
 
     // ID resolution index for O(1) lookup of any node
     pub run_index: IdMap<RunPosition>,
@@ -56,44 +220,303 @@ pub struct HashSeq {
 
     pub removed_inserts: IdSet,
     pub(crate) tips: BTreeSet<Id>,
-    // orphaned uses HashNode as key (not Id), so keep std HashSet — the input is
-    // adversary-controllable and benefits from SipHash's HashDoS protection.
-    pub(crate) orphaned: HashSet<HashNode>,
-    index: AssociativePositionalList<Id>,
+    pub(crate) orphaned: OrphanBuffer<T>,
+    // Nodes rejected by validation, so that anything built on them is rejected
+    // too. Bounded by the orphan policy's `max_count`, and never persisted.
+    rejected: RejectedSet,
+    validator: Validator<T>,
+    // `None` unless changes are being recorded.
+    changes: Option<ChangeLog<T>>,
+    pub(crate) index: WeightedList<T>,
+    // Highest mark clock seen, so local marks win over every mark we know of.
+    mark_clock: u64,
+    // The clocks in each node's causal history, used to bound the clocks of
+    // new nodes. Only nonzero ones are kept, and run elements share their run's.
+    clocks: IdMap<Clocks>,
+    // The moves of each moved element as (clock, head, move); the last one wins.
+    moves_by_element: IdMap<BTreeSet<(u64, Id, Id)>>,
+    // Slots that don't hold their element: inserts whose element was moved
+    // away, and moves that lost to another move of the same element.
+    vacated: IdSet,
+    // Highest move clock seen, so local moves win over every move we know of.
+    move_clock: u64,
+    // The range removes whose span each slot is in, and whether the slot was
+    // made after seeing the range remove, so it survives it.
+    spanned_by: IdMap<BTreeMap<Id, bool>>,
+    // Slots in the span of a range remove they don't survive.
+    range_removed: IdSet,
+    // What the open transaction changed, if there is one.
+    journal: OpenJournal<T>,
+}
+
+impl<T: Element> Default for HashSeq<T> {
+    fn default() -> Self {
+        Self {
+            runs: IdMap::default(),
+            root_nodes: BTreeMap::new(),
+            before_nodes: IdMap::default(),
+            befores_by_anchor: IdMap::default(),
+            remove_nodes: IdMap::default(),
+            remove_range_nodes: IdMap::default(),
+            mark_nodes: IdMap::default(),
+            move_nodes: IdMap::default(),
+            run_index: IdMap::default(),
+            afters: IdMap::default(),
+            removed_inserts: IdSet::default(),
+            tips: BTreeSet::new(),
+            orphaned: OrphanBuffer::default(),
+            rejected: RejectedSet::default(),
+            validator: Validator::default(),
+            changes: None,
+            index: WeightedList::new(),
+            mark_clock: 0,
+            clocks: IdMap::default(),
+            moves_by_element: IdMap::default(),
+            vacated: IdSet::default(),
+            move_clock: 0,
+            spanned_by: IdMap::default(),
+            range_removed: IdSet::default(),
+            journal: OpenJournal::default(),
+        }
+    }
 }
 
-impl PartialEq for HashSeq {
+impl<T: Element> PartialEq for HashSeq<T> {
     fn eq(&self, other: &Self) -> bool {
         self.tips == other.tips
     }
 }
 
-impl Eq for HashSeq {}
+impl<T: Element> Eq for HashSeq<T> {}
+
+impl HashSeq<char> {
+    /// Get the character value for a given node ID
+    pub fn get_node_char(&self, id: &Id) -> char {
+        self.get_node_elem(id)
+    }
+
+    /// Length of the text in UTF-16 code units.
+    pub fn utf16_len(&self) -> usize {
+        self.index.total().utf16
+    }
+
+    /// Length of the text in UTF-8 bytes.
+    pub fn utf8_len(&self) -> usize {
+        self.index.total().utf8
+    }
+
+    /// The UTF-16 offset of the char at `idx`.
+    pub fn index_to_utf16(&self, idx: usize) -> usize {
+        self.index.prefix(idx).utf16
+    }
+
+    /// The char index at a UTF-16 offset, or `None` if the offset is past the
+    /// end or splits a surrogate pair.
+    pub fn utf16_to_index(&self, offset: usize) -> Option<usize> {
+        let prefix = self.index.seek(offset, |w| w.utf16)?;
+        (prefix.utf16 == offset).then_some(prefix.len)
+    }
+
+    /// The UTF-8 offset of the char at `idx`.
+    pub fn index_to_utf8(&self, idx: usize) -> usize {
+        self.index.prefix(idx).utf8
+    }
+
+    /// The char index at a UTF-8 offset, or `None` if the offset is past the
+    /// end or not on a char boundary.
+    pub fn utf8_to_index(&self, offset: usize) -> Option<usize> {
+        let prefix = self.index.seek(offset, |w| w.utf8)?;
+        (prefix.utf8 == offset).then_some(prefix.len)
+    }
+
+    /// `insert_batch` at a UTF-16 offset, as used by JavaScript strings.
+    ///
+    /// `None`, and nothing is inserted, if the offset is past the end or
+    /// splits a surrogate pair.
+    pub fn insert_utf16(&mut self, offset: usize, text: &str) -> Option<Vec<EncodableOp>> {
+        let idx = self.utf16_to_index(offset)?;
+        Some(self.insert_batch(idx, text.chars()))
+    }
+
+    /// `remove_batch` over `len` UTF-16 code units starting at `offset`.
+    ///
+    /// `None`, and nothing is removed, if either end is past the end of the
+    /// text or splits a surrogate pair.
+    pub fn remove_utf16(&mut self, offset: usize, len: usize) -> Option<Vec<EncodableOp>> {
+        let start = self.utf16_to_index(offset)?;
+        let end = self.utf16_to_index(offset.checked_add(len)?)?;
+        Some(self.remove_batch(start, end - start))
+    }
+
+    /// `insert_batch` at a UTF-8 byte offset.
+    ///
+    /// `None`, and nothing is inserted, if the offset is past the end or not
+    /// on a char boundary.
+    pub fn insert_utf8(&mut self, offset: usize, text: &str) -> Option<Vec<EncodableOp>> {
+        let idx = self.utf8_to_index(offset)?;
+        Some(self.insert_batch(idx, text.chars()))
+    }
+
+    /// `remove_batch` over `len` UTF-8 bytes starting at `offset`.
+    ///
+    /// `None`, and nothing is removed, if either end is past the end of the
+    /// text or not on a char boundary.
+    pub fn remove_utf8(&mut self, offset: usize, len: usize) -> Option<Vec<EncodableOp>> {
+        let start = self.utf8_to_index(offset)?;
+        let end = self.utf8_to_index(offset.checked_add(len)?)?;
+        Some(self.remove_batch(start, end - start))
+    }
+
+    /// The text in the char `range`, clamped to the length of the text.
+    /// O(log n) plus the length of the range.
+    pub fn slice(&self, range: Range<usize>) -> String {
+        self.index.range(range).map(|(_, ch)| *ch).collect()
+    }
+
+    /// Number of lines, i.e. one more than the number of `'\n'`s.
+    pub fn line_count(&self) -> usize {
+        self.index.total().lines + 1
+    }
+
+    /// The char index where `line` starts, or `None` if there are fewer lines.
+    pub fn line_to_index(&self, line: usize) -> Option<usize> {
+        self.index.seek(line, |w| w.lines).map(|prefix| prefix.len)
+    }
+
+    /// The line of the char at `idx` and its column in chars. `idx` can be the
+    /// length of the text, for the position after the last char.
+    pub fn index_to_line_col(&self, idx: usize) -> (usize, usize) {
+        let line = self.index.prefix(idx).lines;
+        let start = self.line_to_index(line).expect("lines up to idx exist");
+        (line, idx - start)
+    }
+
+    /// The text of `line` without its `'\n'`. O(log n) plus the length of the line.
+    pub fn line(&self, line: usize) -> Option<String> {
+        let start = self.line_to_index(line)?;
+        let text = self
+            .index
+            .iter_from(start)
+            .map(|(_, ch)| *ch)
+            .take_while(|ch| *ch != '\n')
+            .collect();
+        Some(text)
+    }
+
+    /// The text as slices of consecutive chars borrowed from runs, each with
+    /// the id and index of its first char. Much cheaper than `iter()` for
+    /// rendering or exporting the whole text.
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks::new(self)
+    }
+
+    /// The text split where its marks change, each piece with the marks set
+    /// on it. See `mark_spans`.
+    pub fn marked_chunks(&self) -> impl Iterator<Item = (String, BTreeMap<String, String>)> + '_ {
+        self.mark_spans()
+            .into_iter()
+            .map(|(range, marks)| (self.slice(range), marks))
+    }
+
+    /// Number of extended grapheme clusters, i.e. user-perceived characters.
+    /// Walks the whole text.
+    pub fn grapheme_count(&self) -> usize {
+        self.to_string().graphemes(true).count()
+    }
+
+    /// The char index where the `grapheme`th grapheme cluster starts, or the
+    /// length for one past the last cluster. `None` beyond that. Walks the text.
+    pub fn grapheme_to_index(&self, grapheme: usize) -> Option<usize> {
+        let text = self.to_string();
+        let mut graphemes = text.graphemes(true);
+        let mut idx = 0;
+        for _ in 0..grapheme {
+            idx += graphemes.next()?.chars().count();
+        }
+        Some(idx)
+    }
+
+    /// The grapheme cluster the char at `idx` belongs to. Walks the text up to it.
+    pub fn index_to_grapheme(&self, idx: usize) -> usize {
+        let text: String = self.iter().take(idx + 1).collect();
+        text.graphemes(true).count().saturating_sub(1)
+    }
+
+    /// The char range of the grapheme cluster containing the char at `idx`.
+    ///
+    /// Only looks at the chars around `idx`, widening the window until the
+    /// cluster boundaries no longer depend on what's outside of it.
+    pub fn grapheme_at(&self, idx: usize) -> Option<Range<usize>> {
+        if idx >= self.len() {
+            return None;
+        }
+        let mut radius = 16;
+        loop {
+            let start = idx.saturating_sub(radius);
+            let end = (idx + radius + 1).min(self.len());
+            let window: String = self
+                .index
+                .iter_from(start)
+                .take(end - start)
+                .map(|(_, ch)| *ch)
+                .collect();
+            if let Some(range) =
+                grapheme_in_window(&window, idx - start, start > 0, end < self.len())
+            {
+                return Some(start + range.start..start + range.end);
+            }
+            radius *= 4;
+        }
+    }
+
+    /// Remove the whole grapheme cluster containing the char at `idx`, so that
+    /// emoji sequences and combining marks are never split.
+    pub fn remove_grapheme_at(&mut self, idx: usize) -> Vec<EncodableOp> {
+        match self.grapheme_at(idx) {
+            Some(range) => self.remove_batch(range.start, range.len()),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for HashSeq<char> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(&chunk.text)?;
+        }
+        Ok(())
+    }
+}
 
-impl HashSeq {
+impl<T: Element> HashSeq<T> {
     /// Check if a node ID exists (either in runs or individual nodes)
     pub fn contains_node(&self, id: &Id) -> bool {
         // Check run_index first since most nodes are in runs
         self.run_index.contains_key(id)
             || self.before_nodes.contains_key(id)
             || self.remove_nodes.contains_key(id)
+            || self.remove_range_nodes.contains_key(id)
             || self.root_nodes.contains_key(id)
+            || self.mark_nodes.contains_key(id)
+            || self.move_nodes.contains_key(id)
     }
 
-    /// Get the character value for a given node ID
-    pub fn get_node_char(&self, id: &Id) -> char {
+    /// Get the element inserted by a given node ID, or moved by a given move.
+    pub fn get_node_elem(&self, id: &Id) -> T {
+        if let Some(mv) = self.move_nodes.get(id) {
+            return self.get_node_elem(&mv.element);
+        }
         if let Some(root) = self.root_nodes.get(id) {
-            return root.ch;
+            return root.ch.clone();
         }
         if let Some(before) = self.before_nodes.get(id) {
-            return before.ch;
+            return before.ch.clone();
         }
         let run_pos = &self.run_index[id];
 
         self.runs[&run_pos.run_id]
             .run
-            .chars()
-            .nth(run_pos.position)
+            .elem(run_pos.position)
             .unwrap()
     }
 
@@ -105,10 +528,140 @@ impl HashSeq {
         self.index.is_empty()
     }
 
-    pub fn orphans(&self) -> &HashSet<HashNode> {
+    pub fn orphans(&self) -> &OrphanBuffer<T> {
         &self.orphaned
     }
 
+    /// Ids of every applied node: run elements, roots, befores, removes, marks
+    /// and moves. Orphans are not included.
+    pub fn node_ids(&self) -> impl Iterator<Item = &Id> + '_ {
+        self.run_index
+            .keys()
+            .chain(self.root_nodes.keys())
+            .chain(self.before_nodes.keys())
+            .chain(self.remove_nodes.keys())
+            .chain(self.remove_range_nodes.keys())
+            .chain(self.mark_nodes.keys())
+            .chain(self.move_nodes.keys())
+    }
+
+    /// Dependencies that orphaned nodes are waiting on and that no orphan provides.
+    pub fn missing_dependencies(&self) -> BTreeSet<Id> {
+        let orphan_ids: IdSet = self.orphaned.iter().map(HashNode::id).collect();
+        self.orphaned
+            .iter()
+            .flat_map(HashNode::iter_dependencies)
+            .filter(|id| !self.contains_node(id) && !orphan_ids.contains(id))
+            .copied()
+            .collect()
+    }
+
+    /// Replace the rules nodes are checked against before being applied. Nodes
+    /// that are already applied are not re-checked.
+    pub fn set_validation_policy(&mut self, policy: impl ValidationPolicy<T> + 'static) {
+        self.validator = Validator::new(policy);
+    }
+
+    /// Whether a node was rejected, directly or because it depends on a rejected node.
+    ///
+    /// Rejections are per-session: `encode_hashseq` and `merge` don't carry
+    /// them, and once there are more than the orphan policy's `max_count`
+    /// the oldest are forgotten. A forgotten node is rejected again if it's
+    /// sent again, since validation is deterministic; until then, nodes built
+    /// on it wait as orphans.
+    pub fn is_rejected(&self, id: &Id) -> bool {
+        self.rejected.contains(id)
+    }
+
+    /// Limit the orphan buffer. Orphans the new policy doesn't allow are
+    /// dropped right away. `max_count` also bounds the rejected ids we remember.
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        self.orphaned.set_policy(policy);
+        self.rejected.set_max_count(policy.max_count);
+    }
+
+    /// Orphans dropped by the orphan policy since the last call. Dropped nodes
+    /// are simply forgotten: they are reported again by `missing_dependencies`
+    /// if anything still waits on them, and can be applied if sent again.
+    pub fn take_dropped_orphans(&mut self) -> Vec<DroppedOrphan> {
+        self.orphaned.take_dropped()
+    }
+
+    /// Start or stop recording the changes made to the visible sequence, by
+    /// local edits as well as by remote ops, merges and orphans being applied.
+    /// Stopping discards the changes that weren't taken yet.
+    pub fn set_record_changes(&mut self, record: bool) {
+        self.changes = record.then(ChangeLog::default);
+    }
+
+    /// The changes recorded since the last call, in the order they happened.
+    /// Replaying them on the text as it was when recording started yields the
+    /// current text.
+    pub fn take_changes(&mut self) -> Vec<Change<T>> {
+        self.changes
+            .as_mut()
+            .map(ChangeLog::take)
+            .unwrap_or_default()
+    }
+
+    /// The current causal frontier: nodes that no other node depends on.
+    pub fn tips(&self) -> &BTreeSet<Id> {
+        &self.tips
+    }
+
+    /// Rebuild the `HashNode` for an applied node.
+    pub fn node(&self, id: &Id) -> Option<HashNode<T>> {
+        if let Some(root) = self.root_nodes.get(id) {
+            return Some(HashNode {
+                extra_dependencies: root.extra_dependencies.clone(),
+                op: Op::InsertRoot(root.ch.clone()),
+            });
+        }
+        if let Some(before) = self.before_nodes.get(id) {
+            return Some(HashNode {
+                extra_dependencies: before.extra_dependencies.clone(),
+                op: Op::InsertBefore(before.anchor, before.ch.clone()),
+            });
+        }
+        if let Some(remove) = self.remove_nodes.get(id) {
+            return Some(HashNode {
+                extra_dependencies: remove.extra_dependencies.clone(),
+                op: Op::Remove(remove.nodes.clone()),
+            });
+        }
+        if let Some(range) = self.remove_range_nodes.get(id) {
+            return Some(HashNode {
+                extra_dependencies: range.extra_dependencies.clone(),
+                op: range.op(),
+            });
+        }
+        if let Some(mark) = self.mark_nodes.get(id) {
+            return Some(HashNode {
+                extra_dependencies: mark.extra_dependencies.clone(),
+                op: mark.op(),
+            });
+        }
+        if let Some(mv) = self.move_nodes.get(id) {
+            return Some(HashNode {
+                extra_dependencies: mv.extra_dependencies.clone(),
+                op: mv.op(),
+            });
+        }
+        let run_pos = self.run_index.get(id)?;
+        let run = &self.runs[&run_pos.run_id];
+        let ch = run.run.elem(run_pos.position).unwrap();
+        Some(match run_pos.position {
+            0 => HashNode {
+                extra_dependencies: run.first_extra_deps.clone(),
+                op: Op::InsertAfter(run.insert_after, ch),
+            },
+            p => HashNode {
+                extra_dependencies: BTreeSet::new(),
+                op: Op::InsertAfter(run.elements[p - 1], ch),
+            },
+        })
+    }
+
     /// Get a stable reference to an Id from existing data structures.
     /// Used by HashSeqIter to return references without a separate nodes set.
     pub(crate) fn get_id_ref(&self, id: &Id) -> Option<&Id> {
@@ -124,6 +677,9 @@ impl HashSeq {
         if let Some((id_ref, _)) = self.run_index.get_key_value(id) {
             return Some(id_ref);
         }
+        if let Some((id_ref, _)) = self.move_nodes.get_key_value(id) {
+            return Some(id_ref);
+        }
         None
     }
 
@@ -170,207 +726,820 @@ impl HashSeq {
         false
     }
 
-    fn neighbours(&mut self, idx: usize) -> (Option<Id>, Option<Id>) {
-        let left = idx
-            .checked_sub(1)
-            .and_then(|prev_idx| self.index.get(prev_idx).copied());
-
-        let right = self.index.get(idx).copied();
-
-        (left, right)
+    /// Whether the insert or move `id` holds an element that hasn't been removed.
+    pub(crate) fn is_visible(&self, id: &Id) -> bool {
+        !self.vacated.contains(id)
+            && !self.range_removed.contains(id)
+            && !self.removed_inserts.contains(self.element_of(id))
     }
 
-    /// Clone of `self.tips` with `anchor` removed.
-    ///
-    /// Fast path: sequential typing leaves `tips == {anchor}`, in which case the
-    /// result is empty and we skip cloning the BTreeSet entirely (which would
-    /// allocate a tree node just to drop it).
-    fn tips_minus(&self, anchor: &Id) -> BTreeSet<Id> {
-        if self.tips.len() == 1 && self.tips.contains(anchor) {
-            BTreeSet::new()
-        } else {
-            let mut deps = self.tips.clone();
-            deps.remove(anchor);
-            deps
-        }
+    /// The element the insert or move `id` is a slot for.
+    fn element_of<'a>(&'a self, id: &'a Id) -> &'a Id {
+        self.move_nodes.get(id).map_or(id, |mv| &mv.element)
     }
 
-    pub fn insert(&mut self, idx: usize, value: char) {
-        self.insert_batch(idx, [value]);
+    /// The slot holding `element`: its winning move, or its insert if it never moved.
+    fn current_slot(&self, element: &Id) -> Id {
+        self.moves_by_element
+            .get(element)
+            .and_then(|moves| moves.last())
+            .map_or(*element, |(_, _, slot)| *slot)
     }
 
-    pub fn insert_batch(&mut self, idx: usize, batch: impl IntoIterator<Item = char>) {
-        let chars: Vec<char> = batch.into_iter().collect();
+    /// The highest clocks in the causal history of the applied node `id`.
+    fn clocks_of(&self, id: &Id) -> Clocks {
+        // A run element past the head only depends on the one before it.
+        let key = self.run_index.get(id).map_or(id, |run_pos| &run_pos.run_id);
+        self.clocks.get(key).copied().unwrap_or_default()
+    }
 
-        if chars.is_empty() {
-            return;
+    /// The highest clocks in the causal history of `node`, the node excluded.
+    fn clocks_before(&self, node: &HashNode<T>) -> Clocks {
+        if self.clocks.is_empty() {
+            return Clocks::default();
         }
+        node.iter_dependencies()
+            .map(|dep| self.clocks_of(dep))
+            .fold(Clocks::default(), Clocks::max)
+    }
 
-        let (left, right) = self.neighbours(idx);
-
-        match (left, right) {
-            (Some(left_id), Some(right_id)) => {
-                let mut chars_iter = chars.into_iter();
-                let first_ch = chars_iter.next().unwrap();
+    fn is_insert(&self, id: &Id) -> bool {
+        self.run_index.contains_key(id)
+            || self.before_nodes.contains_key(id)
+            || self.root_nodes.contains_key(id)
+    }
 
-                let first_node = if self.is_causally_before(&left_id, &right_id) {
-                    // Using InsertAfter for the first node doesn't work.
-                    // use InsertBefore right_id instead
-                    HashNode {
-                        extra_dependencies: self.tips_minus(&right_id),
-                        op: Op::InsertBefore(right_id, first_ch),
-                    }
-                } else {
-                    HashNode {
-                        extra_dependencies: self.tips_minus(&left_id),
-                        op: Op::InsertAfter(left_id, first_ch),
-                    }
-                };
+    /// Whether `id` is a slot in the causal tree, i.e. an insert or a move.
+    /// Only slots have a position.
+    fn is_slot(&self, id: &Id) -> bool {
+        self.is_insert(id) || self.move_nodes.contains_key(id)
+    }
 
-                let mut prev_id = first_node.id();
-                self.apply_with_id(prev_id, first_node);
+    /// The node `id` hangs off of in the causal tree, and on which side. Roots have no parent.
+    fn parent(&self, id: &Id) -> Option<(Id, Side)> {
+        if let Some(before) = self.before_nodes.get(id) {
+            return Some((before.anchor, Side::Before));
+        }
+        if let Some(mv) = self.move_nodes.get(id) {
+            let side = if mv.before { Side::Before } else { Side::After };
+            return Some((mv.anchor, side));
+        }
+        let run_pos = self.run_index.get(id)?;
+        let run = &self.runs[&run_pos.run_id];
+        let parent = match run_pos.position {
+            0 => run.insert_after,
+            p => run.elements[p - 1],
+        };
+        Some((parent, Side::After))
+    }
 
-                // Subsequent nodes have empty extra_deps
-                for ch in chars_iter {
-                    let node = HashNode {
-                        extra_dependencies: BTreeSet::new(),
-                        op: Op::InsertAfter(prev_id, ch),
-                    };
-                    prev_id = node.id();
-                    self.apply_with_id(prev_id, node);
+    /// Number of visible nodes that precede `id` in iteration order, i.e. the
+    /// position `id` occupies in `index` (or would occupy, if it were visible).
+    fn visible_position(&self, id: &Id) -> usize {
+        if let Some(p) = self.index.find(id) {
+            return p;
+        }
+        // Step outwards in both directions until either side reaches a visible
+        // node or an end of the sequence, so tombstones only cost as much as the
+        // shorter stretch of them next to `id`.
+        let (mut prev, mut next) = (*id, *id);
+        loop {
+            match self.following(&next) {
+                None => return self.index.len(),
+                Some(n) if self.is_visible(&n) => {
+                    return self.index.find(&n).expect("visible nodes are indexed");
                 }
+                Some(n) => next = n,
             }
-            (Some(left_id), None) => {
-                // there is no right node, we just chain from left
-                let mut chars_iter = chars.into_iter();
-
-                // First node needs full tips (minus anchor)
-                let first_ch = chars_iter.next().unwrap();
-                let first_node = HashNode {
-                    extra_dependencies: self.tips_minus(&left_id),
-                    op: Op::InsertAfter(left_id, first_ch),
-                };
-                let mut prev_id = first_node.id();
-                self.apply_with_id(prev_id, first_node);
-
-                // Subsequent nodes have empty extra_deps since tips = {prev_id} after first apply
-                for ch in chars_iter {
-                    let node = HashNode {
-                        extra_dependencies: BTreeSet::new(),
-                        op: Op::InsertAfter(prev_id, ch),
-                    };
-                    prev_id = node.id();
-                    self.apply_with_id(prev_id, node);
+            match self.preceding(&prev) {
+                None => return 0,
+                Some(p) if self.is_visible(&p) => {
+                    return self.index.find(&p).expect("visible nodes are indexed") + 1;
                 }
+                Some(p) => prev = p,
             }
-            (None, Some(right_id)) => {
-                let mut chars_iter = chars.into_iter();
-                let first_node = HashNode {
-                    extra_dependencies: self.tips_minus(&right_id),
-                    op: Op::InsertBefore(right_id, chars_iter.next().unwrap()),
-                };
+        }
+    }
 
-                let mut prev_id = first_node.id();
-                self.apply_with_id(prev_id, first_node);
+    /// The node right before `id` in iteration order, tombstones included.
+    fn preceding(&self, id: &Id) -> Option<Id> {
+        if let Some(last) = self.befores(id).next_back() {
+            return Some(self.last_in_subtree(*last));
+        }
 
-                // Subsequent nodes have empty extra_deps
-                for ch in chars_iter {
-                    let node = HashNode {
-                        extra_dependencies: BTreeSet::new(),
-                        op: Op::InsertAfter(prev_id, ch),
-                    };
-                    prev_id = node.id();
-                    self.apply_with_id(prev_id, node);
+        let mut current = *id;
+        loop {
+            match self.parent(&current) {
+                None => {
+                    return self
+                        .root_nodes
+                        .range(..current)
+                        .next_back()
+                        .map(|(root, _)| self.last_in_subtree(*root));
+                }
+                Some((parent, Side::After)) => {
+                    return Some(
+                        match self
+                            .afters(&parent)
+                            .rev()
+                            .find(|sibling| **sibling < current)
+                        {
+                            Some(sibling) => self.last_in_subtree(*sibling),
+                            None => parent,
+                        },
+                    );
+                }
+                Some((parent, Side::Before)) => {
+                    if let Some(sibling) = self.befores(&parent).rev().find(|s| **s < current) {
+                        return Some(self.last_in_subtree(*sibling));
+                    }
+                    current = parent;
                 }
             }
-            (None, None) => {
-                // seq is empty
-                let mut chars_iter = chars.into_iter();
-
-                let first_node = HashNode {
-                    extra_dependencies: self.tips.clone(),
-                    op: Op::InsertRoot(chars_iter.next().unwrap()),
-                };
+        }
+    }
 
-                let mut prev_id = first_node.id();
-                self.apply_with_id(prev_id, first_node);
+    /// The node right after `id` in iteration order, tombstones included.
+    fn following(&self, id: &Id) -> Option<Id> {
+        if let Some(first) = self.afters(id).next() {
+            return Some(self.first_in_subtree(*first));
+        }
 
-                // Subsequent nodes have empty extra_deps
-                for ch in chars_iter {
-                    let node = HashNode {
-                        extra_dependencies: BTreeSet::new(),
-                        op: Op::InsertAfter(prev_id, ch),
-                    };
-                    prev_id = node.id();
-                    self.apply_with_id(prev_id, node);
+        let mut current = *id;
+        loop {
+            // Only the tail of a run can fork, so skip straight to its head.
+            if let Some(run_pos) = self.run_index.get(&current) {
+                current = run_pos.run_id;
+            }
+            match self.parent(&current) {
+                None => {
+                    return self
+                        .root_nodes
+                        .range((Bound::Excluded(current), Bound::Unbounded))
+                        .next()
+                        .map(|(root, _)| self.first_in_subtree(*root));
+                }
+                Some((parent, Side::After)) => {
+                    if let Some(sibling) = self.afters(&parent).find(|s| **s > current) {
+                        return Some(self.first_in_subtree(*sibling));
+                    }
+                    current = parent;
+                }
+                Some((parent, Side::Before)) => {
+                    if let Some(sibling) = self.befores(&parent).find(|s| **s > current) {
+                        return Some(self.first_in_subtree(*sibling));
+                    }
+                    return Some(parent);
                 }
             }
         }
     }
 
-    pub fn remove(&mut self, idx: usize) {
-        self.remove_batch(idx, 1);
+    fn neighbours(&mut self, idx: usize) -> (Option<Id>, Option<Id>) {
+        let left = idx
+            .checked_sub(1)
+            .and_then(|prev_idx| self.index.get(prev_idx).copied());
+
+        let right = self.index.get(idx).copied();
+
+        (left, right)
     }
 
-    pub fn remove_batch(&mut self, idx: usize, amount: usize) {
-        if amount == 0 {
-            // Nothing to remove
-            return;
+    /// Clone of `self.tips` with `anchor` removed.
+    ///
+    /// Fast path: sequential typing leaves `tips == {anchor}`, in which case the
+    /// result is empty and we skip cloning the BTreeSet entirely (which would
+    /// allocate a tree node just to drop it).
+    fn tips_minus(&self, anchor: &Id) -> BTreeSet<Id> {
+        if self.tips.len() == 1 && self.tips.contains(anchor) {
+            BTreeSet::new()
+        } else {
+            self.local_dependencies(|tip| tip == anchor)
         }
+    }
 
-        let mut to_remove = BTreeSet::new();
-        for pos in idx..(idx + amount) {
-            if let Some(id) = self.index.get(pos) {
-                to_remove.insert(*id);
-            } else {
-                break;
+    /// The tips a local edit depends on, besides the ones `skip` picks out
+    /// because the edit already refers to them.
+    ///
+    /// Capped at what the validation policy accepts, so the edit isn't
+    /// rejected here or by peers. The tips left out stay tips, and later
+    /// nodes pick them up. Tips that aren't inserts are kept first: whether
+    /// an edit has seen a range remove, mark or move changes what it means.
+    fn local_dependencies(&self, skip: impl Fn(&Id) -> bool) -> BTreeSet<Id> {
+        let deps = self.tips.iter().filter(|tip| !skip(tip));
+        match self.validator.max_extra_dependencies() {
+            Some(max) if self.tips.len() > max => {
+                let (inserts, others): (Vec<&Id>, Vec<&Id>) =
+                    deps.partition(|tip| self.is_insert(tip));
+                others
+                    .into_iter()
+                    .chain(inserts)
+                    .take(max)
+                    .copied()
+                    .collect()
             }
+            _ => deps.copied().collect(),
         }
+    }
 
-        let extra_dependencies = BTreeSet::from_iter(self.tips.difference(&to_remove).cloned());
-        let op = Op::Remove(to_remove);
+    pub fn insert(&mut self, idx: usize, value: T) -> Vec<EncodableOp<T>> {
+        self.insert_batch(idx, [value])
+    }
 
-        let node = HashNode {
-            extra_dependencies,
-            op,
+    /// Insert `batch` at `idx`, returning the ops that were created so they can be
+    /// shipped to peers (e.g. through `encode_batch`) and applied with `apply_op`.
+    ///
+    /// Everything after the first character is emitted as a single `Run`; the first
+    /// character joins that run when it is an `InsertAfter`, otherwise it's sent as
+    /// a standalone node.
+    pub fn insert_batch(
+        &mut self,
+        idx: usize,
+        batch: impl IntoIterator<Item = T>,
+    ) -> Vec<EncodableOp<T>> {
+        let mut chars = batch.into_iter();
+
+        let Some(first_ch) = chars.next() else {
+            return Vec::new();
         };
 
-        self.apply(node);
+        let first_node = match self.neighbours(idx) {
+            (Some(left_id), Some(right_id)) if self.is_causally_before(&left_id, &right_id) => {
+                // Using InsertAfter for the first node doesn't work.
+                // use InsertBefore right_id instead
+                HashNode {
+                    extra_dependencies: self.tips_minus(&right_id),
+                    op: Op::InsertBefore(right_id, first_ch),
+                }
+            }
+            (Some(left_id), _) => HashNode {
+                extra_dependencies: self.tips_minus(&left_id),
+                op: Op::InsertAfter(left_id, first_ch),
+            },
+            (None, Some(right_id)) => HashNode {
+                extra_dependencies: self.tips_minus(&right_id),
+                op: Op::InsertBefore(right_id, first_ch),
+            },
+            // seq is empty
+            (None, None) => HashNode {
+                extra_dependencies: self.local_dependencies(|_| false),
+                op: Op::InsertRoot(first_ch),
+            },
+        };
+
+        self.apply_chain(first_node, chars)
     }
 
-    fn any_missing_dependencies<'a>(&self, deps: impl IntoIterator<Item = &'a Id>) -> bool {
-        for dep in deps {
-            if !self.contains_node(dep) {
-                return true;
+    /// Apply `first` followed by a chain of `InsertAfter`s for `rest`, collecting
+    /// the applied nodes into ops. Stops at the first node that isn't applied.
+    fn apply_chain(
+        &mut self,
+        first: HashNode<T>,
+        mut rest: impl Iterator<Item = T>,
+    ) -> Vec<EncodableOp<T>> {
+        let mut ops = Vec::new();
+        let mut run: Option<Run<T>> = None;
+
+        let mut next = Some(first);
+        while let Some(node) = next.take() {
+            let id = node.id();
+            if !self.apply_local_with_id(id, node.clone()) {
+                break;
+            }
+            match (node.op, &mut run) {
+                // Past its head, a run only holds nodes depending on nothing but
+                // the one before them.
+                (Op::InsertAfter(_, ch), Some(run)) if node.extra_dependencies.is_empty() => {
+                    run.extend_with_id(id, ch)
+                }
+                (Op::InsertAfter(anchor, ch), _) => {
+                    ops.extend(run.take().map(EncodableOp::Run));
+                    run = Some(Run {
+                        insert_after: anchor,
+                        first_extra_deps: node.extra_dependencies,
+                        run: std::iter::once(ch).collect(),
+                        elements: vec![id],
+                    });
+                }
+                (op, _) => ops.push(EncodableOp::Node(HashNode {
+                    extra_dependencies: node.extra_dependencies,
+                    op,
+                })),
             }
+
+            // Usually the tips are just `id` by now, unless some were left
+            // out of the previous node's dependencies.
+            next = rest.next().map(|ch| HashNode {
+                extra_dependencies: self.tips_minus(&id),
+                op: Op::InsertAfter(id, ch),
+            });
         }
 
-        false
+        ops.extend(run.map(EncodableOp::Run));
+        ops
     }
 
-    fn insert_root(&mut self, root_id: Id, root: CausalRoot) {
-        let position = if let Some(next_root) = self
-            .root_nodes
-            .keys()
-            .filter(|id| *id >= &root_id)
-            .find(|id| !self.removed_inserts.contains(*id))
-        {
-            // new root is inserted just before the next biggest root
-            self.index.find(next_root).unwrap()
-        } else {
-            // otherwise if there is no bigger root, the new root is
-            // inserted at end of list
+    /// Apply a node made by a local edit, returning its op if it was applied.
+    fn apply_local(&mut self, node: HashNode<T>) -> Option<EncodableOp<T>> {
+        let id = node.id();
+        self.apply_local_with_id(id, node.clone())
+            .then_some(EncodableOp::Node(node))
+    }
+
+    /// Apply a node made by a local edit, whose dependencies are all present.
+    /// Returns whether it was applied. A rejected local node isn't remembered
+    /// as rejected: it was never sent, so nothing can build on it.
+    fn apply_local_with_id(&mut self, id: Id, node: HashNode<T>) -> bool {
+        if self.contains_node(&id) || self.validate(&node).is_err() {
+            return false;
+        }
+        self.integrate(id, node);
+        // Within a transaction, orphans wait for the commit: they aren't
+        // journaled, so they can't be rolled back.
+        if self.journal.0.is_none() {
+            self.apply_ready_orphans(id);
+        }
+        true
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Vec<EncodableOp<T>> {
+        self.remove_batch(idx, 1)
+    }
+
+    /// Remove `amount` elements starting at `idx`, returning the `Remove` op that
+    /// was created (empty if there was nothing to remove).
+    pub fn remove_batch(&mut self, idx: usize, amount: usize) -> Vec<EncodableOp<T>> {
+        let to_remove = self
+            .index
+            .range(idx..idx + amount)
+            .map(|(id, _)| *self.element_of(id));
+        let to_remove = BTreeSet::from_iter(to_remove);
+        self.remove_set(to_remove)
+    }
+
+    /// Remove `amount` elements starting at `idx` as a range, returning the
+    /// `RemoveRange` op that was created (empty if there was nothing to remove).
+    ///
+    /// Unlike `remove_batch`, which removes the elements there now, this also
+    /// removes what peers concurrently insert or move into the range, so a
+    /// deleted sentence doesn't come back as fragments of their typing.
+    /// Whatever is put in the range after seeing the remove stays. Finding
+    /// out which inserts are concurrent walks their causal history, so it
+    /// costs more to merge edits made inside a removed range.
+    pub fn remove_range_intent(&mut self, idx: usize, amount: usize) -> Vec<EncodableOp<T>> {
+        let mut slots = self.index.range(idx..idx + amount).map(|(id, _)| *id);
+        let Some(start) = slots.next() else {
+            return Vec::new();
+        };
+        let end = slots.next_back().unwrap_or(start);
+
+        let extra_dependencies = self.local_dependencies(|tip| *tip == start || *tip == end);
+        let node = HashNode {
+            extra_dependencies,
+            op: Op::RemoveRange { start, end },
+        };
+
+        self.apply_local(node).into_iter().collect()
+    }
+
+    /// Insert `batch` directly after the element `anchor`, as it stands now.
+    /// `anchor` can have been removed, the batch then lands where it was, or
+    /// moved, the batch then follows it.
+    /// Unlike `insert_batch`, this doesn't shift when concurrent edits move
+    /// the anchor's index.
+    ///
+    /// # Panics
+    ///
+    /// If `anchor` isn't an insert in this sequence.
+    pub fn insert_after_id(
+        &mut self,
+        anchor: &Id,
+        batch: impl IntoIterator<Item = T>,
+    ) -> Vec<EncodableOp<T>> {
+        self.assert_is_insert(anchor);
+        let anchor = &self.current_slot(self.element_of(anchor));
+        let mut chars = batch.into_iter();
+        let Some(first_ch) = chars.next() else {
+            return Vec::new();
+        };
+
+        // Hanging off of `anchor` would put us among its existing afters,
+        // so go before whatever comes right after it instead.
+        let op = match self.afters(anchor).next() {
+            None => Op::InsertAfter(*anchor, first_ch),
+            Some(next) => Op::InsertBefore(self.first_in_subtree(*next), first_ch),
+        };
+        self.apply_chain(self.anchored_node(op), chars)
+    }
+
+    /// Insert `batch` directly before the element `anchor`, as it stands now.
+    /// `anchor` can have been removed, the batch then lands where it was, or
+    /// moved, the batch then follows it.
+    ///
+    /// # Panics
+    ///
+    /// If `anchor` isn't an insert in this sequence.
+    pub fn insert_before_id(
+        &mut self,
+        anchor: &Id,
+        batch: impl IntoIterator<Item = T>,
+    ) -> Vec<EncodableOp<T>> {
+        self.assert_is_insert(anchor);
+        let anchor = &self.current_slot(self.element_of(anchor));
+        let mut chars = batch.into_iter();
+        let Some(first_ch) = chars.next() else {
+            return Vec::new();
+        };
+
+        let op = match self.befores(anchor).next_back() {
+            None => Op::InsertBefore(*anchor, first_ch),
+            Some(prev) => Op::InsertAfter(self.last_in_subtree(*prev), first_ch),
+        };
+        self.apply_chain(self.anchored_node(op), chars)
+    }
+
+    /// Remove the elements in `ids`, returning the `Remove` op that was created.
+    /// Ids that aren't visible elements of this sequence are skipped, so the op
+    /// is empty if none of them are. Moved elements can be given by the id
+    /// they were inserted with or the id of the move holding them.
+    pub fn remove_ids(&mut self, ids: &BTreeSet<Id>) -> Vec<EncodableOp<T>> {
+        let to_remove = ids
+            .iter()
+            .map(|id| *self.element_of(id))
+            .filter(|element| self.index.find(&self.current_slot(element)).is_some())
+            .collect();
+        self.remove_set(to_remove)
+    }
+
+    /// Replace the elements in `range` with `batch`, returning the ops created.
+    ///
+    /// The insert is anchored on the removed elements rather than on an index,
+    /// and depends on the `Remove`, so peers get one causally linked group
+    /// and the new text stays where the old text was under concurrent edits.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds, like `Vec::splice`.
+    pub fn splice(
+        &mut self,
+        range: Range<usize>,
+        batch: impl IntoIterator<Item = T>,
+    ) -> Vec<EncodableOp<T>> {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "splice range {range:?} out of bounds for length {}",
+            self.len()
+        );
+        if range.is_empty() {
+            return self.insert_batch(range.start, batch);
+        }
+
+        let first_removed = *self.index.get(range.start).expect("range is in bounds");
+        let mut ops = self.remove_batch(range.start, range.len());
+        ops.extend(self.insert_before_id(&first_removed, batch));
+        ops
+    }
+
+    /// Move the elements in `range` so they sit at `to`, an index into the
+    /// sequence as it is before the move, returning the ops created.
+    ///
+    /// The elements keep their ids, so removes, cursors and marks anchored on
+    /// them follow them, and concurrent moves of the same elements don't
+    /// duplicate them: the latest move wins, concurrent ones are ordered by
+    /// their clocks, then their ids, and a range moved by one op moves as a
+    /// whole. Text a peer inserts into the range concurrently stays where the
+    /// range was. Moving a range to where it already is creates no op.
+    ///
+    /// # Panics
+    ///
+    /// If `range` or `to` is out of bounds.
+    pub fn move_range(&mut self, range: Range<usize>, to: usize) -> Vec<EncodableOp<T>> {
+        assert!(
+            range.start <= range.end && range.end <= self.len() && to <= self.len(),
+            "move of {range:?} to {to} out of bounds for length {}",
+            self.len()
+        );
+        if range.is_empty() || (range.start..=range.end).contains(&to) {
+            return Vec::new();
+        }
+
+        let elements: Vec<Id> = self
+            .index
+            .range(range)
+            .map(|(id, _)| *self.element_of(id))
+            .collect();
+        let (mut anchor, mut before) = match self.neighbours(to) {
+            (Some(left_id), Some(right_id)) if self.is_causally_before(&left_id, &right_id) => {
+                (right_id, true)
+            }
+            (Some(left_id), _) => (left_id, false),
+            (None, Some(right_id)) => (right_id, true),
+            (None, None) => unreachable!("the range is not empty"),
+        };
+
+        // The rest of the range follows the first element, each after the last.
+        let clock = self
+            .move_clock
+            .checked_add(1)
+            .expect("move clocks past u64::MAX - 1 are rejected");
+        let mut ops = Vec::new();
+        for element in elements {
+            let extra_dependencies =
+                self.local_dependencies(|tip| *tip == anchor || *tip == element);
+            let node = HashNode {
+                extra_dependencies,
+                op: Op::Move {
+                    anchor,
+                    before,
+                    element,
+                    clock,
+                },
+            };
+            (anchor, before) = (node.id(), false);
+            match self.apply_local(node) {
+                Some(op) => ops.push(op),
+                None => break,
+            }
+        }
+        ops
+    }
+
+    /// Make several edits as one batch. The edits `f` makes through the
+    /// transaction are applied as it goes; if it returns `Ok`, their ops are
+    /// returned in order, ready for `encode_batch`. If it returns `Err` or
+    /// panics, the sequence is put back exactly as it was, recorded changes
+    /// included, before the error is returned or the panic resumed.
+    ///
+    /// Rolling back undoes what each edit changed, so it costs about as much
+    /// as the edits did. Orphans that the edits would release wait until the
+    /// transaction commits.
+    pub fn transaction<E>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction<'_, T>) -> Result<(), E>,
+    ) -> Result<Vec<EncodableOp<T>>, E> {
+        self.journal.0 = Some(Journal {
+            tips: self.tips.clone(),
+            mark_clock: self.mark_clock,
+            move_clock: self.move_clock,
+            changes: self.changes.as_ref().map(ChangeLog::mark),
+            extended: IdSet::default(),
+            undo: Vec::new(),
+        });
+        let mut tx = Transaction::new(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut tx)));
+        let ops = tx.into_ops();
+        let journal = self.journal.0.take().unwrap();
+        match result {
+            Ok(Ok(())) => {
+                for undo in journal.undo {
+                    if let Undo::Applied(id) = undo {
+                        self.apply_ready_orphans(id);
+                    }
+                }
+                Ok(ops)
+            }
+            Ok(Err(err)) => {
+                self.roll_back(journal);
+                Err(err)
+            }
+            Err(payload) => {
+                self.roll_back(journal);
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Undo everything in `journal`, last first.
+    fn roll_back(&mut self, journal: Journal<T>) {
+        for undo in journal.undo.into_iter().rev() {
+            match undo {
+                Undo::Applied(id) => {
+                    self.runs.remove(&id);
+                    self.root_nodes.remove(&id);
+                    self.before_nodes.remove(&id);
+                    self.remove_nodes.remove(&id);
+                    self.remove_range_nodes.remove(&id);
+                    self.mark_nodes.remove(&id);
+                    self.move_nodes.remove(&id);
+                    self.run_index.remove(&id);
+                    self.clocks.remove(&id);
+                    self.vacated.remove(&id);
+                    self.spanned_by.remove(&id);
+                    self.range_removed.remove(&id);
+                }
+                Undo::After { anchor, id } => unlink(&mut self.afters, anchor, &id),
+                Undo::Before { anchor, id } => unlink(&mut self.befores_by_anchor, anchor, &id),
+                Undo::Extended { run_id, len } => {
+                    self.runs.get_mut(&run_id).unwrap().truncate(len);
+                }
+                Undo::Split { left, right } => {
+                    let right_run = self.runs.remove(&right).unwrap();
+                    self.clocks.remove(&right);
+                    let left_run = self.runs.get_mut(&left).unwrap();
+                    for (idx, elem_id) in right_run.elements.iter().enumerate() {
+                        self.run_index.insert(
+                            *elem_id,
+                            RunPosition {
+                                run_id: left,
+                                position: left_run.len() + idx,
+                            },
+                        );
+                    }
+                    left_run.join(right_run);
+                }
+                Undo::Removed(id) => {
+                    self.removed_inserts.remove(&id);
+                }
+                Undo::Vacated(id) => {
+                    self.vacated.remove(&id);
+                }
+                Undo::RangeRemoved(id) => {
+                    self.range_removed.remove(&id);
+                }
+                Undo::Spanned { slot, range } => {
+                    let spans = self.spanned_by.get_mut(&slot).unwrap();
+                    spans.remove(&range);
+                    if spans.is_empty() {
+                        self.spanned_by.remove(&slot);
+                    }
+                }
+                Undo::Moved { element, entry } => {
+                    unlink(&mut self.moves_by_element, element, &entry)
+                }
+                Undo::Indexed(position) => {
+                    self.index.remove(position);
+                }
+                Undo::Unindexed {
+                    position,
+                    id,
+                    value,
+                    weight,
+                } => self.index.insert(position, id, value, weight),
+            }
+        }
+        self.tips = journal.tips;
+        self.mark_clock = journal.mark_clock;
+        self.move_clock = journal.move_clock;
+        if let (Some(changes), Some(mark)) = (&mut self.changes, journal.changes) {
+            changes.rewind(mark);
+        }
+    }
+
+    /// Set the mark `name` to `value` on the elements in `range`, returning the
+    /// op created. Marks sit on top of the elements: `mark_spans` resolves
+    /// them against the current sequence. Where marks with the same name
+    /// overlap, the latest wins, and concurrent ones are ordered by their
+    /// clocks, then their ids. An empty range creates no op.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds.
+    pub fn add_mark(
+        &mut self,
+        range: Range<usize>,
+        name: &str,
+        value: &str,
+        expand: Expand,
+    ) -> Vec<EncodableOp<T>> {
+        self.mark(range, name, Some(value.to_string()), expand)
+    }
+
+    /// Clear the mark `name` from the elements in `range`, returning the op
+    /// created. `expand` says whether text typed at the edges is cleared too.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds.
+    pub fn remove_mark(
+        &mut self,
+        range: Range<usize>,
+        name: &str,
+        expand: Expand,
+    ) -> Vec<EncodableOp<T>> {
+        self.mark(range, name, None, expand)
+    }
+
+    fn mark(
+        &mut self,
+        range: Range<usize>,
+        name: &str,
+        value: Option<String>,
+        expand: Expand,
+    ) -> Vec<EncodableOp<T>> {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "mark range {range:?} out of bounds for length {}",
             self.len()
+        );
+        if range.is_empty() {
+            return Vec::new();
+        }
+
+        let (start, end) = expand.gravities();
+        let mark = CausalMark {
+            extra_dependencies: BTreeSet::new(),
+            start: self.cursor_at(range.start, start),
+            end: self.cursor_at(range.end, end),
+            clock: self
+                .mark_clock
+                .checked_add(1)
+                .expect("mark clocks past u64::MAX - 1 are rejected"),
+            name: name.to_string(),
+            value,
+        };
+        let op = mark.op();
+        let extra_dependencies = self.local_dependencies(|tip| op.anchors().any(|a| a == tip));
+        let node = HashNode {
+            extra_dependencies,
+            op,
+        };
+
+        self.apply_local(node).into_iter().collect()
+    }
+
+    fn remove_set(&mut self, to_remove: BTreeSet<Id>) -> Vec<EncodableOp<T>> {
+        if to_remove.is_empty() {
+            // Nothing to remove
+            return Vec::new();
+        }
+
+        let extra_dependencies = self.local_dependencies(|tip| to_remove.contains(tip));
+        let op = Op::Remove(to_remove);
+
+        let node = HashNode {
+            extra_dependencies,
+            op,
+        };
+
+        self.apply_local(node).into_iter().collect()
+    }
+
+    fn assert_is_insert(&self, id: &Id) {
+        assert!(self.is_slot(id), "{id:?} is not an insert in this sequence");
+    }
+
+    /// A node for an insert `op`, depending on the tips besides its anchor.
+    fn anchored_node(&self, op: Op<T>) -> HashNode<T> {
+        let extra_dependencies = match &op {
+            Op::InsertAfter(anchor, _) | Op::InsertBefore(anchor, _) => self.tips_minus(anchor),
+            _ => self.local_dependencies(|_| false),
         };
-        self.insert_root_with_known_position(root_id, root, position);
+        HashNode {
+            extra_dependencies,
+            op,
+        }
+    }
+
+    /// The first node (in iteration order, tombstones included) of the subtree rooted at `id`.
+    fn first_in_subtree(&self, id: Id) -> Id {
+        let mut first = id;
+        while let Some(before) = self.befores(&first).next() {
+            first = *before;
+        }
+        first
+    }
+
+    /// The last node (in iteration order, tombstones included) of the subtree rooted at `id`.
+    fn last_in_subtree(&self, id: Id) -> Id {
+        let mut last = id;
+        loop {
+            // Only the tail of a run can have children, so skip straight to it.
+            if let Some(run_pos) = self.run_index.get(&last) {
+                last = self.runs[&run_pos.run_id].last_id();
+            }
+            match self.afters(&last).next_back() {
+                Some(after) => last = *after,
+                None => return last,
+            }
+        }
+    }
+
+    /// Apply an op received from a peer, e.g. one returned by `insert_batch` on
+    /// another replica. Runs are decompressed, so every element id is recomputed.
+    pub fn apply_op(&mut self, op: EncodableOp<T>) {
+        self.try_apply_op(op);
+    }
+
+    /// `apply_op`, reporting the outcome for each node the op contains.
+    pub fn try_apply_op(&mut self, op: EncodableOp<T>) -> Vec<ApplyOutcome> {
+        match op {
+            EncodableOp::Node(node) => vec![self.try_apply(node)],
+            EncodableOp::Run(run) => run
+                .decompress()
+                .into_iter()
+                .map(|node| self.try_apply(node))
+                .collect(),
+        }
     }
 
-    fn insert_root_with_known_position(&mut self, id: Id, root: CausalRoot, position: usize) {
-        self.index.insert(position, id);
-        self.root_nodes.insert(id, root);
+    fn insert_root(&mut self, root_id: Id, root: CausalRoot<T>) {
+        let ch = root.ch.clone();
+        self.root_nodes.insert(root_id, root);
+        let position = self.visible_position(&root_id);
+        self.update_position_index(root_id, position, ch);
     }
 
-    fn insert_after(&mut self, id: Id, after: CausalInsert) {
+    fn insert_after(&mut self, id: Id, after: CausalInsert<T>) {
         // Fast path: check for run extension without allocating afters Vec
         if after.extra_dependencies.is_empty()
             && let Some(run_pos) = self.run_index.get(&after.anchor).copied()
@@ -386,7 +1555,16 @@ impl HashSeq {
                 let run = self.runs.get_mut(&run_pos.run_id).unwrap();
                 if run_pos.position + 1 == run.len() {
                     // Run extension - most common case for sequential typing
-                    run.extend_with_id(id, after.ch);
+                    let len = run.len();
+                    run.extend_with_id(id, after.ch.clone());
+                    if let Some(journal) = &mut self.journal.0
+                        && journal.extended.insert(run_pos.run_id)
+                    {
+                        journal.undo.push(Undo::Extended {
+                            run_id: run_pos.run_id,
+                            len,
+                        });
+                    }
                     self.run_index.insert(
                         id,
                         RunPosition {
@@ -394,199 +1572,469 @@ impl HashSeq {
                             position: run_pos.position + 1,
                         },
                     );
-                    let position = self.index.find(&after.anchor).map(|p| p + 1);
-                    self.update_position_index(id, position.unwrap());
+                    let position = match self.index.find(&after.anchor) {
+                        Some(p) => p + 1,
+                        // the anchor has been removed, find the closest visible node before us
+                        None => self.visible_position(&id),
+                    };
+                    self.update_position_index(id, position, after.ch);
                     return;
                 }
             }
         }
 
-        // Slow path: find the smallest afters node >= id and not removed.
-        // Explicit-afters case: O(log n) range seek into the BTreeSet.
-        // Run-fallback case: at most one candidate, just check it.
-        let next_node = if let Some(siblings) = self.afters.get(&after.anchor) {
-            siblings
-                .range(&id..)
-                .find(|aid| !self.removed_inserts.contains(*aid))
-        } else {
-            self.afters(&after.anchor)
-                .find(|aid| **aid >= id && !self.removed_inserts.contains(*aid))
-        };
-        let position = if let Some(next_node) = next_node {
-            // new node is inserted just before the other node after our anchor node that is
-            // bigger than the new node
-            self.index.find(next_node)
-        } else {
-            // otherwise the new node is inserted after our anchor node (unless it has been removed)
-            self.index.find(&after.anchor).map(|p| p + 1)
-        };
+        // Run extension is handled by the fast path above, so this is a fork:
+        // split the anchor's run if we're inserting mid-run, and start a new run.
+        self.split_run_after(&after.anchor);
+        let new_run = Run::new(after.anchor, after.extra_dependencies, after.ch.clone());
+        debug_assert_eq!(new_run.first_id(), id);
+        self.runs.insert(id, new_run);
+        self.run_index.insert(
+            id,
+            RunPosition {
+                run_id: id,
+                position: 0,
+            },
+        );
 
-        if let Some(run_pos) = self.run_index.get(&after.anchor).copied() {
-            // We are inserting after a node that is in a run.
-            // Run extension case is handled by fast path above, so this is a fork/split
-            {
-                if run_pos.position + 1 < self.runs[&run_pos.run_id].len() {
-                    let run = self.runs.get_mut(&run_pos.run_id).unwrap();
-                    let right_run = run.split_at(run_pos.position + 1);
-                    debug_assert_eq!(run.last_id(), after.anchor);
+        // run extension is handled in the fast path above, fork/split updates the afters set
+        self.afters.entry(after.anchor).or_default().insert(id);
+        self.record(|| Undo::After {
+            anchor: after.anchor,
+            id,
+        });
 
-                    let right_run_first_id = right_run.first_id();
+        let position = self.visible_position(&id);
+        self.update_position_index(id, position, after.ch);
+    }
 
-                    // re-index the right run using cached elements
-                    for (idx, elem_id) in right_run.elements.iter().enumerate() {
-                        self.run_index.insert(
-                            *elem_id,
-                            RunPosition {
-                                run_id: right_run_first_id,
-                                position: idx,
-                            },
-                        );
-                    }
+    /// Split the run holding `anchor` right after it, if `anchor` is mid-run,
+    /// so that it can take another after.
+    fn split_run_after(&mut self, anchor: &Id) {
+        let Some(run_pos) = self.run_index.get(anchor).copied() else {
+            return;
+        };
+        if run_pos.position + 1 == self.runs[&run_pos.run_id].len() {
+            return;
+        }
+        let clocks = self.clocks_of(anchor);
+        let run = self.runs.get_mut(&run_pos.run_id).unwrap();
+        let right_run = run.split_at(run_pos.position + 1);
+        debug_assert_eq!(run.last_id(), *anchor);
+
+        let right_run_first_id = right_run.first_id();
+        if clocks != Clocks::default() {
+            self.clocks.insert(right_run_first_id, clocks);
+        }
 
-                    // The split-off portion needs to be tracked in afters
-                    self.afters
-                        .entry(after.anchor)
-                        .or_default()
-                        .insert(right_run_first_id);
-                    self.runs.insert(right_run_first_id, right_run);
-                }
-                let new_run = Run::new(after.anchor, after.extra_dependencies, after.ch);
-                debug_assert_eq!(new_run.first_id(), id);
-                self.runs.insert(id, new_run);
-                self.run_index.insert(
-                    id,
-                    RunPosition {
-                        run_id: id,
-                        position: 0,
-                    },
-                );
-            }
-        } else {
-            // Either anchor is not a run, or we can't extend from it for some reason, start a new run
-            let new_run = Run::new(after.anchor, after.extra_dependencies, after.ch);
-            debug_assert_eq!(new_run.first_id(), id);
-            self.runs.insert(id, new_run);
+        // re-index the right run using cached elements
+        for (idx, elem_id) in right_run.elements.iter().enumerate() {
             self.run_index.insert(
-                id,
+                *elem_id,
                 RunPosition {
-                    run_id: id,
-                    position: 0,
+                    run_id: right_run_first_id,
+                    position: idx,
                 },
             );
+        }
+
+        // The split-off portion needs to be tracked in afters
+        self.afters
+            .entry(*anchor)
+            .or_default()
+            .insert(right_run_first_id);
+        self.runs.insert(right_run_first_id, right_run);
+        self.record(|| Undo::Split {
+            left: run_pos.run_id,
+            right: right_run_first_id,
+        });
+        self.record(|| Undo::After {
+            anchor: *anchor,
+            id: right_run_first_id,
+        });
+    }
+
+    /// Split the run holding `anchor` right before it, if `anchor` is mid-run,
+    /// so that it can take a before.
+    fn split_run_before(&mut self, anchor: &Id) {
+        let Some(run_pos) = self.run_index.get(anchor).copied() else {
+            return;
         };
+        if run_pos.position == 0 {
+            return;
+        }
+        let clocks = self.clocks_of(anchor);
+        let run = self.runs.get_mut(&run_pos.run_id).unwrap();
+        // Get the last ID of the left portion from run's elements cache
+        let left_last_id = run.elements[run_pos.position - 1];
+        let right_run = run.split_at(run_pos.position);
+        let right_run_id = right_run.first_id();
+        debug_assert_eq!(right_run_id, *anchor);
+        if clocks != Clocks::default() {
+            self.clocks.insert(right_run_id, clocks);
+        }
 
-        // run extension is handled in the fast path above, fork/split updates the afters set
-        self.afters.entry(after.anchor).or_default().insert(id);
+        // re-index the right run using cached elements
+        for (idx, elem_id) in right_run.elements.iter().enumerate() {
+            self.run_index.insert(
+                *elem_id,
+                RunPosition {
+                    run_id: right_run_id,
+                    position: idx,
+                },
+            );
+        }
 
-        let position = position.unwrap_or_else(|| {
-            // fall back to iterating over the entire sequence if the anchor node has been removed
-            // or if next_node is not yet in the index (can happen during merge)
-            let (position, _) = self
-                .iter_ids()
-                .enumerate()
-                .find(|(_, n)| n == &&id)
-                .unwrap();
-            position
+        self.runs.insert(right_run_id, right_run);
+        // Track the split in afters so iteration can find the right portion
+        self.afters
+            .entry(left_last_id)
+            .or_default()
+            .insert(right_run_id);
+        self.record(|| Undo::Split {
+            left: run_pos.run_id,
+            right: right_run_id,
+        });
+        self.record(|| Undo::After {
+            anchor: left_last_id,
+            id: right_run_id,
         });
-        self.update_position_index(id, position);
     }
 
-    fn update_position_index(&mut self, id: Id, position: usize) {
-        self.index.insert(position, id);
+    /// Note how to undo a change, if a transaction is open.
+    fn record(&mut self, undo: impl FnOnce() -> Undo<T>) {
+        if let Some(journal) = &mut self.journal.0 {
+            journal.undo.push(undo());
+        }
+    }
+
+    fn update_position_index(&mut self, id: Id, position: usize, ch: T) {
+        self.cover(id);
+        if self.range_removed.contains(&id) {
+            return;
+        }
+        self.index.insert(position, id, ch.clone(), ch.weight());
+        self.record(|| Undo::Indexed(position));
+        if let Some(changes) = &mut self.changes {
+            changes.inserted(position, self.index.prefix(position).utf16, ch);
+        }
     }
 
     fn remove_nodes(&mut self, id: Id, remove: CausalRemove) {
         // TODO: if self.nodes.get(node) is not an insert op, then drop this remove.
         //       Are you sure? looks like we would mark this op as an orphan if we hadn't
         //       seen a node yet.
-        for n in remove.nodes.iter() {
-            if let Some(p) = self.index.find(n) {
-                self.index.remove(p);
+        let positions = remove
+            .nodes
+            .iter()
+            .filter_map(|n| self.index.find(&self.current_slot(n)))
+            .collect();
+        self.remove_positions(positions);
+        for node in &remove.nodes {
+            if self.removed_inserts.insert(*node) {
+                self.record(|| Undo::Removed(*node));
             }
         }
-        self.removed_inserts.extend(&remove.nodes);
         self.remove_nodes.insert(id, remove);
     }
 
-    fn insert_before(&mut self, id: Id, before: CausalInsert) {
-        // O(log n) range seek into the underlying BTreeSet, no intermediate allocation.
-        let position = if let Some(next_node) = self
-            .befores_by_anchor
-            .get(&before.anchor)
-            .and_then(|s| s.range(id..).find(|n| !self.removed_inserts.contains(*n)))
-        {
-            // new node is inserted just before the other node before our anchor node that is
-            // bigger than the new node
-            Some(self.index.find(next_node).unwrap())
-        } else {
-            // otherwise the new node is inserted before our anchor node
-            self.index.find(&before.anchor)
-        };
-
-        if let Some(run_pos) = self.run_index.get(&before.anchor).copied()
-            && run_pos.position > 0
-        {
-            let run = self.runs.get_mut(&run_pos.run_id).unwrap();
-            // Get the last ID of the left portion from run's elements cache
-            let left_last_id = run.elements[run_pos.position - 1];
-            let right_run = run.split_at(run_pos.position);
-            let right_run_id = right_run.first_id();
-            debug_assert_eq!(right_run_id, before.anchor);
-
-            // re-index the right run using cached elements
-            for (idx, elem_id) in right_run.elements.iter().enumerate() {
-                self.run_index.insert(
-                    *elem_id,
-                    RunPosition {
-                        run_id: right_run_id,
-                        position: idx,
-                    },
-                );
+    fn remove_positions(&mut self, mut positions: Vec<usize>) {
+        // Back to front, so positions stay valid and neighbours coalesce into one change.
+        positions.sort_unstable_by(|a, b| b.cmp(a));
+        for p in positions {
+            if let Some(journal) = &mut self.journal.0 {
+                let (id, value) = self.index.entry(p).expect("found positions are in bounds");
+                let weight = value.weight();
+                journal.undo.push(Undo::Unindexed {
+                    position: p,
+                    id: *id,
+                    value: value.clone(),
+                    weight,
+                });
+            }
+            let weight = self.index.remove(p).expect("found positions are in bounds");
+            if let Some(changes) = &mut self.changes {
+                changes.removed(p, self.index.prefix(p).utf16, weight.utf16);
             }
-
-            self.runs.insert(right_run_id, right_run);
-            // Track the split in afters so iteration can find the right portion
-            self.afters
-                .entry(left_last_id)
-                .or_default()
-                .insert(right_run_id);
         }
+    }
 
-        self.befores_by_anchor
-            .entry(before.anchor)
-            .or_default()
-            .insert(id);
-
-        self.before_nodes.insert(id, before);
+    fn remove_range(&mut self, id: Id, range: CausalRemoveRange) {
+        let span = self.span(&range.start, &range.end);
 
-        let position = position.unwrap_or_else(|| {
-            // fall back to iterating over the entire sequence if the anchor node has been removed
-            let (position, _) = self
-                .iter_ids()
-                .enumerate()
-                .find(|(_, n)| n == &&id)
-                .unwrap();
-            position
-        });
-        self.update_position_index(id, position);
+        // Nothing that depends on the remove is here yet.
+        let positions = span
+            .iter()
+            .filter_map(|slot| self.index.find(slot))
+            .collect();
+        for slot in span {
+            self.spanned_by.entry(slot).or_default().insert(id, false);
+            self.record(|| Undo::Spanned { slot, range: id });
+            if self.range_removed.insert(slot) {
+                self.record(|| Undo::RangeRemoved(slot));
+            }
+        }
+        self.remove_positions(positions);
+        self.remove_range_nodes.insert(id, range);
     }
 
-    pub fn apply(&mut self, node: HashNode) {
-        let id = node.id();
-        self.apply_with_id(id, node);
+    /// The slots from `start` to `end`, both included, in iteration order,
+    /// tombstones included. If `end` comes first, nothing is in between.
+    fn span(&self, start: &Id, end: &Id) -> Vec<Id> {
+        // Where a slot sits among the visible ones; a tombstone comes right
+        // before the visible slot at its position. This tells which end comes
+        // first and how many visible slots lie between them, so that a
+        // reversed range doesn't walk to the end of the sequence.
+        let rank = |id: &Id| match self.index.find(id) {
+            Some(idx) => (idx, true),
+            None => (self.visible_position(id), false),
+        };
+        let (start_rank, end_rank) = (rank(start), rank(end));
+        if end_rank < start_rank {
+            return Vec::new();
+        }
+        let mut visible_left = end_rank.0 + usize::from(end_rank.1) - start_rank.0;
+
+        let mut span = vec![*start];
+        loop {
+            let last = span.last().unwrap();
+            if self.index.find(last).is_some() {
+                // Past as many visible slots as lie in between, so `end` came first.
+                let Some(left) = visible_left.checked_sub(1) else {
+                    return Vec::new();
+                };
+                visible_left = left;
+            }
+            if last == end {
+                return span;
+            }
+            match self.following(last) {
+                Some(next) => span.push(next),
+                None => return Vec::new(),
+            }
+        }
     }
 
-    /// Apply a node with a pre-computed ID (avoids double hashing)
-    fn apply_with_id(&mut self, id: Id, node: HashNode) {
-        if self.contains_node(&id) {
-            return; // Already processed this node
+    /// Work out which range removes span the new slot `id`, from the node
+    /// right before it, and hide it if it doesn't survive one of them.
+    fn cover(&mut self, id: Id) {
+        if self.spanned_by.is_empty() {
+            return;
         }
-
-        if self.any_missing_dependencies(node.iter_dependencies()) {
-            self.orphaned.insert(node);
+        let Some(prev) = self.preceding(&id) else {
+            return;
+        };
+        let Some(spans) = self.spanned_by.get(&prev) else {
+            return;
+        };
+        // A node that only builds on `prev`, like the next element of a run,
+        // survives exactly what `prev` survives.
+        let extends_prev = self.dependencies_of(&id) == [prev];
+        let spans: BTreeMap<Id, bool> = spans
+            .iter()
+            .filter(|(range, _)| self.remove_range_nodes[*range].end != prev)
+            .map(|(range, survives)| {
+                let survives = match extends_prev {
+                    true => *survives,
+                    false => self.depends_on(&id, range),
+                };
+                (*range, survives)
+            })
+            .collect();
+        if spans.is_empty() {
             return;
         }
-
+        if spans.values().any(|survives| !survives) {
+            self.range_removed.insert(id);
+        }
+        self.spanned_by.insert(id, spans);
+    }
+
+    fn insert_mark(&mut self, id: Id, mark: CausalMark) {
+        self.mark_clock = self.mark_clock.max(mark.clock);
+        self.mark_nodes.insert(id, mark);
+    }
+
+    fn insert_move(&mut self, id: Id, mv: CausalMove) {
+        self.move_clock = self.move_clock.max(mv.clock);
+        // The move is a new slot in the tree, hanging off its anchor like an insert.
+        if mv.before {
+            self.split_run_before(&mv.anchor);
+            self.befores_by_anchor
+                .entry(mv.anchor)
+                .or_default()
+                .insert(id);
+            self.record(|| Undo::Before {
+                anchor: mv.anchor,
+                id,
+            });
+        } else {
+            self.split_run_after(&mv.anchor);
+            self.afters.entry(mv.anchor).or_default().insert(id);
+            self.record(|| Undo::After {
+                anchor: mv.anchor,
+                id,
+            });
+        }
+
+        let element = mv.element;
+        let old_slot = self.current_slot(&element);
+        let entry = (mv.clock, mv.head, id);
+        self.moves_by_element
+            .entry(element)
+            .or_default()
+            .insert(entry);
+        self.record(|| Undo::Moved { element, entry });
+        self.move_nodes.insert(id, mv);
+        if self.current_slot(&element) != id {
+            // Lost to a move we already have.
+            self.vacated.insert(id);
+            self.cover(id);
+            return;
+        }
+
+        let old_position = self.index.find(&old_slot);
+        if self.vacated.insert(old_slot) {
+            self.record(|| Undo::Vacated(old_slot));
+        }
+        self.remove_positions(old_position.into_iter().collect());
+        if self.removed_inserts.contains(&element) {
+            self.cover(id);
+            return;
+        }
+        let position = self.visible_position(&id);
+        self.update_position_index(id, position, self.get_node_elem(&element));
+    }
+
+    fn insert_before(&mut self, id: Id, before: CausalInsert<T>) {
+        self.split_run_before(&before.anchor);
+        self.befores_by_anchor
+            .entry(before.anchor)
+            .or_default()
+            .insert(id);
+        self.record(|| Undo::Before {
+            anchor: before.anchor,
+            id,
+        });
+
+        let ch = before.ch.clone();
+        self.before_nodes.insert(id, before);
+
+        let position = self.visible_position(&id);
+        self.update_position_index(id, position, ch);
+    }
+
+    pub fn apply(&mut self, node: HashNode<T>) {
+        self.try_apply(node);
+    }
+
+    /// Apply a node, reporting whether it was applied, and if not, why.
+    pub fn try_apply(&mut self, node: HashNode<T>) -> ApplyOutcome {
+        let id = node.id();
+        self.apply_with_id(id, node)
+    }
+
+    /// Apply a node with a pre-computed ID (avoids double hashing)
+    fn apply_with_id(&mut self, id: Id, node: HashNode<T>) -> ApplyOutcome {
+        if self.contains_node(&id) {
+            return ApplyOutcome::Duplicate;
+        }
+
+        if let Some(dep) = node
+            .iter_dependencies()
+            .find(|dep| self.rejected.contains(dep))
+        {
+            let reason = RejectReason::DependsOnRejected(*dep);
+            self.rejected.insert(id);
+            self.apply_ready_orphans(id);
+            return ApplyOutcome::Rejected(reason);
+        }
+
+        let missing: Vec<Id> = node
+            .iter_dependencies()
+            .filter(|dep| !self.contains_node(dep))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            self.orphaned.insert(id, node, &missing);
+            return ApplyOutcome::Orphaned { missing };
+        }
+
+        if let Err(reason) = self.validate(&node) {
+            self.rejected.insert(id);
+            self.apply_ready_orphans(id);
+            return ApplyOutcome::Rejected(reason);
+        }
+
+        self.integrate(id, node);
+
+        let (unorphaned, rejected) = self.apply_ready_orphans(id);
+        ApplyOutcome::Applied {
+            unorphaned,
+            rejected,
+        }
+    }
+
+    /// Checks a node whose dependencies are all present. Inserts, moves, marks
+    /// and range removes must be anchored on inserts or moves, removes can
+    /// only target inserts and moves only move inserts. The rest is up to the
+    /// validation policy.
+    fn validate(&self, node: &HashNode<T>) -> Result<(), RejectReason> {
+        for anchor in node.op.anchors() {
+            if self.remove_nodes.contains_key(anchor)
+                || self.remove_range_nodes.contains_key(anchor)
+            {
+                return Err(RejectReason::AnchorIsRemove(*anchor));
+            }
+            if self.mark_nodes.contains_key(anchor) {
+                return Err(RejectReason::AnchorIsMark(*anchor));
+            }
+        }
+        match &node.op {
+            Op::Remove(targets) => {
+                if let Some(target) = targets.iter().find(|id| self.mark_nodes.contains_key(id)) {
+                    return Err(RejectReason::RemovesMark(*target));
+                }
+                if let Some(target) = targets.iter().find(|id| self.move_nodes.contains_key(id)) {
+                    return Err(RejectReason::RemovesMove(*target));
+                }
+            }
+            Op::Move { element, .. } if !self.is_insert(element) => {
+                return Err(RejectReason::MovesNonInsert(*element));
+            }
+            _ => {}
+        }
+        let clock = match &node.op {
+            Op::AddMark { clock, .. } | Op::RemoveMark { clock, .. } => {
+                Some((*clock, self.clocks_before(node).mark))
+            }
+            Op::Move { clock, .. } => Some((*clock, self.clocks_before(node).moves)),
+            _ => None,
+        };
+        if let Some((clock, seen)) = clock {
+            // Capped below u64::MAX, so a local edit can always go one higher.
+            let max = seen.saturating_add(1).min(u64::MAX - 1);
+            if clock > max {
+                return Err(RejectReason::ClockTooHigh { clock, max });
+            }
+        }
+        self.validator.validate(self, node)
+    }
+
+    /// Add a node whose dependencies are all present to the sequence.
+    fn integrate(&mut self, id: Id, node: HashNode<T>) {
+        let mut clocks = self.clocks_before(&node);
+        match &node.op {
+            Op::AddMark { clock, .. } | Op::RemoveMark { clock, .. } => {
+                clocks.mark = clocks.mark.max(*clock)
+            }
+            Op::Move { clock, .. } => clocks.moves = clocks.moves.max(*clock),
+            _ => {}
+        }
+
+        // Undone last, once nothing else refers to the node.
+        self.record(|| Undo::Applied(id));
+
         // Update tips before consuming node (insert ops don't depend on tips)
         for tip in node.iter_dependencies() {
             self.tips.remove(tip);
@@ -624,65 +2072,803 @@ impl HashSeq {
                     nodes,
                 },
             ),
+            Op::RemoveRange { start, end } => self.remove_range(
+                id,
+                CausalRemoveRange {
+                    extra_dependencies: node.extra_dependencies,
+                    start,
+                    end,
+                },
+            ),
+            Op::AddMark {
+                start,
+                end,
+                clock,
+                name,
+                value,
+            } => self.insert_mark(
+                id,
+                CausalMark {
+                    extra_dependencies: node.extra_dependencies,
+                    start,
+                    end,
+                    clock,
+                    name,
+                    value: Some(value),
+                },
+            ),
+            Op::RemoveMark {
+                start,
+                end,
+                clock,
+                name,
+            } => self.insert_mark(
+                id,
+                CausalMark {
+                    extra_dependencies: node.extra_dependencies,
+                    start,
+                    end,
+                    clock,
+                    name,
+                    value: None,
+                },
+            ),
+            Op::Move {
+                anchor,
+                before,
+                element,
+                clock,
+            } => {
+                // A range is moved as a chain of moves hanging off of each other,
+                // all with the same clock.
+                let head = match self.move_nodes.get(&anchor) {
+                    Some(prev) if !before && prev.clock == clock => prev.head,
+                    _ => id,
+                };
+                self.insert_move(
+                    id,
+                    CausalMove {
+                        extra_dependencies: node.extra_dependencies,
+                        anchor,
+                        before,
+                        element,
+                        clock,
+                        head,
+                    },
+                )
+            }
         }
 
-        for orphan in std::mem::take(&mut self.orphaned) {
-            self.apply(orphan);
+        let extends_run = self
+            .run_index
+            .get(&id)
+            .is_some_and(|run_pos| run_pos.position > 0);
+        if clocks != Clocks::default() && !extends_run {
+            self.clocks.insert(id, clocks);
+        }
+    }
+
+    /// Settle the orphans waiting on `id`, which was just applied or rejected,
+    /// and in cascade the orphans waiting on those. Returns the applied ones
+    /// and the rejected ones, each in the order they were settled.
+    fn apply_ready_orphans(&mut self, id: Id) -> (Vec<Id>, Vec<(Id, RejectReason)>) {
+        let mut applied = Vec::new();
+        let mut rejected = Vec::new();
+        if self.orphaned.is_empty() {
+            return (applied, rejected);
+        }
+        let mut settled = VecDeque::from([id]);
+        while let Some(dep) = settled.pop_front() {
+            for orphan_id in self.orphaned.take_waiting_on(&dep) {
+                let Some(node) = self.orphaned.get(&orphan_id) else {
+                    continue;
+                };
+                let rejected_dep = node
+                    .iter_dependencies()
+                    .find(|d| self.rejected.contains(d))
+                    .copied();
+                let missing: Vec<Id> = node
+                    .iter_dependencies()
+                    .filter(|dep| !self.contains_node(dep))
+                    .copied()
+                    .collect();
+                if rejected_dep.is_none() && !missing.is_empty() {
+                    self.orphaned.refile(orphan_id, &missing);
+                    continue;
+                }
+
+                let node = self.orphaned.remove(&orphan_id).unwrap();
+                if self.contains_node(&orphan_id) {
+                    continue;
+                }
+                let verdict = match rejected_dep {
+                    Some(dep) => Err(RejectReason::DependsOnRejected(dep)),
+                    None => self.validate(&node),
+                };
+                match verdict {
+                    Ok(()) => {
+                        self.integrate(orphan_id, node);
+                        applied.push(orphan_id);
+                    }
+                    Err(reason) => {
+                        self.rejected.insert(orphan_id);
+                        rejected.push((orphan_id, reason));
+                    }
+                }
+                settled.push_back(orphan_id);
+            }
         }
+        (applied, rejected)
     }
 
-    pub fn merge(&mut self, other: Self) {
-        // Simple merge: decompress all nodes from other and apply them
-        // The apply function will rebuild runs when possible
+    /// Merge every node of `other` into `self`.
+    ///
+    /// Ids are taken from `other` instead of being recomputed: runs are merged
+    /// at run granularity using their cached `Run::elements`, so no character
+    /// is hashed again. This is only sound if `other`'s ids are right, which
+    /// holds for replicas built through this API or by `decode_hashseq` (it
+    /// hashes every node it decodes). A `HashSeq` that came from anywhere else,
+    /// e.g. deserialized field by field from an untrusted peer, must be checked
+    /// with `verify_ids` before being merged.
+    pub fn merge(&mut self, other: &Self) {
+        // What we have of a run is a prefix: only the rest of it is merged.
+        let mut units: Vec<MergeUnit<T>> = Vec::new();
+        for run in other.runs.values() {
+            let start = run.elements.partition_point(|id| self.contains_node(id));
+            if start < run.len() {
+                units.push(MergeUnit::Run(run, start));
+            }
+        }
+        for (id, root) in &other.root_nodes {
+            if self.contains_node(id) {
+                continue;
+            }
+            units.push(MergeUnit::Node(
+                *id,
+                HashNode {
+                    extra_dependencies: root.extra_dependencies.clone(),
+                    op: Op::InsertRoot(root.ch.clone()),
+                },
+            ));
+        }
+        for (id, before) in &other.before_nodes {
+            if self.contains_node(id) {
+                continue;
+            }
+            units.push(MergeUnit::Node(
+                *id,
+                HashNode {
+                    extra_dependencies: before.extra_dependencies.clone(),
+                    op: Op::InsertBefore(before.anchor, before.ch.clone()),
+                },
+            ));
+        }
+        for (id, remove) in &other.remove_nodes {
+            if self.contains_node(id) {
+                continue;
+            }
+            units.push(MergeUnit::Node(
+                *id,
+                HashNode {
+                    extra_dependencies: remove.extra_dependencies.clone(),
+                    op: Op::Remove(remove.nodes.clone()),
+                },
+            ));
+        }
+        for (id, range) in &other.remove_range_nodes {
+            if self.contains_node(id) {
+                continue;
+            }
+            units.push(MergeUnit::Node(
+                *id,
+                HashNode {
+                    extra_dependencies: range.extra_dependencies.clone(),
+                    op: range.op(),
+                },
+            ));
+        }
+        for (id, mark) in &other.mark_nodes {
+            if self.contains_node(id) {
+                continue;
+            }
+            units.push(MergeUnit::Node(
+                *id,
+                HashNode {
+                    extra_dependencies: mark.extra_dependencies.clone(),
+                    op: mark.op(),
+                },
+            ));
+        }
+        for (id, mv) in &other.move_nodes {
+            if self.contains_node(id) {
+                continue;
+            }
+            units.push(MergeUnit::Node(
+                *id,
+                HashNode {
+                    extra_dependencies: mv.extra_dependencies.clone(),
+                    op: mv.op(),
+                },
+            ));
+        }
+
+        // Apply in causal order so that nothing goes through the orphan buffer.
+        let mut producer: IdMap<usize> = IdMap::default();
+        for (i, unit) in units.iter().enumerate() {
+            match unit {
+                MergeUnit::Run(run, start) => {
+                    producer.extend(run.elements[*start..].iter().map(|id| (*id, i)))
+                }
+                MergeUnit::Node(id, _) => {
+                    producer.insert(*id, i);
+                }
+            }
+        }
+        let deps: Vec<Vec<usize>> = units
+            .iter()
+            .enumerate()
+            .map(|(i, unit)| {
+                let deps: Vec<&Id> = match unit {
+                    MergeUnit::Run(run, 0) => std::iter::once(&run.insert_after)
+                        .chain(&run.first_extra_deps)
+                        .collect(),
+                    // The element before the suffix is already in `self`.
+                    MergeUnit::Run(..) => Vec::new(),
+                    MergeUnit::Node(_, node) => node.iter_dependencies().collect(),
+                };
+                deps.into_iter()
+                    .filter_map(|dep| producer.get(dep).copied())
+                    .filter(|j| *j != i)
+                    .collect()
+            })
+            .collect();
+
+        let mut units: Vec<Option<MergeUnit<T>>> = units.into_iter().map(Some).collect();
+        for i in topological_order(&deps) {
+            match units[i].take() {
+                Some(MergeUnit::Run(run, start)) => self.merge_run(run, start),
+                Some(MergeUnit::Node(id, node)) => {
+                    self.apply_with_id(id, node);
+                }
+                None => {}
+            }
+        }
+
+        for orphan in other.orphaned.iter() {
+            self.apply(orphan.clone());
+        }
+    }
 
-        for (id, root) in other.root_nodes {
+    /// Apply elements `start..` of another replica's run, reusing its cached ids.
+    /// Each element goes through `apply_with_id`, so consecutive ones extend our
+    /// run through `insert_after`'s fast path, without being hashed again.
+    fn merge_run(&mut self, run: &Run<T>, start: usize) {
+        let (mut anchor, mut extra_dependencies) = match start {
+            0 => (run.insert_after, run.first_extra_deps.clone()),
+            start => (run.elements[start - 1], BTreeSet::new()),
+        };
+        for (id, ch) in run.elements[start..]
+            .iter()
+            .copied()
+            .zip(run.run.elems().skip(start))
+        {
             let node = HashNode {
-                extra_dependencies: root.extra_dependencies,
-                op: Op::InsertRoot(root.ch),
+                extra_dependencies: std::mem::take(&mut extra_dependencies),
+                op: Op::InsertAfter(anchor, ch),
             };
-            debug_assert_eq!(id, node.id());
-            self.apply(node)
+            self.apply_with_id(id, node);
+            anchor = id;
         }
+    }
 
-        for (_run_id, run) in other.runs {
-            for node in run.decompress() {
-                self.apply(node);
+    /// Check that every cached id is the hash of the node it stands for,
+    /// returning the first one that isn't. `merge` trusts these ids, so run
+    /// this first on a `HashSeq` that wasn't built locally or by `decode_hashseq`.
+    pub fn verify_ids(&self) -> Result<(), Id> {
+        for (run_id, run) in &self.runs {
+            if !run.verify_ids() {
+                return Err(*run_id);
+            }
+            if *run_id != run.first_id() {
+                return Err(*run_id);
+            }
+        }
+        for id in self
+            .root_nodes
+            .keys()
+            .chain(self.before_nodes.keys())
+            .chain(self.remove_nodes.keys())
+            .chain(self.remove_range_nodes.keys())
+            .chain(self.mark_nodes.keys())
+            .chain(self.move_nodes.keys())
+        {
+            if self.node(id).map(|node| node.id()) != Some(*id) {
+                return Err(*id);
             }
         }
+        Ok(())
+    }
 
-        for (id, causal_insert) in other.before_nodes {
-            let node = HashNode {
-                extra_dependencies: causal_insert.extra_dependencies,
-                op: Op::InsertBefore(causal_insert.anchor, causal_insert.ch),
+    /// The ids that `id` directly depends on.
+    fn dependencies_of(&self, id: &Id) -> Vec<Id> {
+        if let Some(run_pos) = self.run_index.get(id) {
+            let run = &self.runs[&run_pos.run_id];
+            return match run_pos.position {
+                0 => std::iter::once(&run.insert_after)
+                    .chain(&run.first_extra_deps)
+                    .copied()
+                    .collect(),
+                p => vec![run.elements[p - 1]],
             };
-            debug_assert_eq!(id, node.id());
-            self.apply(node)
         }
+        self.node(id)
+            .map(|node| node.iter_dependencies().copied().collect())
+            .unwrap_or_default()
+    }
 
-        for (id, causal_remove) in other.remove_nodes {
-            let node = HashNode {
-                extra_dependencies: causal_remove.extra_dependencies,
-                op: Op::Remove(causal_remove.nodes),
+    /// Whether `ancestor` is in the causal history of `id`.
+    fn depends_on(&self, id: &Id, ancestor: &Id) -> bool {
+        let mut seen = IdSet::default();
+        let mut stack = vec![*id];
+        while let Some(n) = stack.pop() {
+            // Run elements only depend on the element before them, so skip to the head.
+            let n = self.run_index.get(&n).map_or(n, |run_pos| run_pos.run_id);
+            if !seen.insert(n) {
+                continue;
+            }
+            for dep in self.dependencies_of(&n) {
+                if dep == *ancestor {
+                    return true;
+                }
+                stack.push(dep);
+            }
+        }
+        false
+    }
+
+    /// All ops that a replica whose tips are `remote_tips` is missing, in causal order.
+    ///
+    /// Walks back from the remote tips we know about to find everything the remote
+    /// already has, then walks back from our tips until we hit that frontier. Runs
+    /// are cut so only the missing suffix is sent. Remote tips we've never seen
+    /// can't tell us anything and are ignored, so some ops may be sent redundantly.
+    pub fn ops_since(&self, remote_tips: &BTreeSet<Id>) -> Vec<EncodableOp<T>> {
+        self.ops_needed_for(&self.tips, remote_tips)
+    }
+
+    /// The ops creating `ids`, along with every ancestor a replica whose tips are
+    /// `remote_tips` is missing, in causal order. Ids we don't have are skipped.
+    pub fn ops_needed_for(
+        &self,
+        ids: &BTreeSet<Id>,
+        remote_tips: &BTreeSet<Id>,
+    ) -> Vec<EncodableOp<T>> {
+        // Runs are walked at run granularity: a run element depends on all the
+        // elements before it, so what a replica has of a run is always a prefix.
+        let mut known_prefix: IdMap<usize> = IdMap::default();
+        let mut known_nodes = IdSet::default();
+        let mut stack: Vec<Id> = remote_tips
+            .iter()
+            .filter(|id| self.contains_node(id))
+            .copied()
+            .collect();
+        while let Some(id) = stack.pop() {
+            if let Some(run_pos) = self.run_index.get(&id) {
+                let prefix = known_prefix.entry(run_pos.run_id).or_default();
+                if *prefix > run_pos.position {
+                    continue;
+                }
+                let first_visit = *prefix == 0;
+                *prefix = run_pos.position + 1;
+                if first_visit {
+                    let run = &self.runs[&run_pos.run_id];
+                    stack.extend(self.dependencies_of(&run.first_id()));
+                }
+            } else if known_nodes.insert(id) {
+                stack.extend(self.dependencies_of(&id));
+            }
+        }
+
+        let mut missing_runs: IdMap<usize> = IdMap::default();
+        let mut missing_nodes = IdSet::default();
+        let mut stack: Vec<Id> = ids
+            .iter()
+            .filter(|id| self.contains_node(id))
+            .copied()
+            .collect();
+        while let Some(id) = stack.pop() {
+            if let Some(run_pos) = self.run_index.get(&id) {
+                let prefix = known_prefix.get(&run_pos.run_id).copied().unwrap_or(0);
+                if run_pos.position < prefix || missing_runs.contains_key(&run_pos.run_id) {
+                    continue;
+                }
+                // Everything past the known prefix is missing, not just what we reached.
+                missing_runs.insert(run_pos.run_id, prefix);
+                if prefix == 0 {
+                    let run = &self.runs[&run_pos.run_id];
+                    stack.extend(self.dependencies_of(&run.first_id()));
+                }
+            } else if !known_nodes.contains(&id) && missing_nodes.insert(id) {
+                stack.extend(self.dependencies_of(&id));
+            }
+        }
+
+        self.ops_for_suffixes(missing_runs, missing_nodes)
+    }
+
+    /// The ops a replica summarized by `filter` is likely missing, in causal order.
+    ///
+    /// Anything the filter doesn't contain is missing, and so is everything that
+    /// depends on it. A false positive hides that node from the result: whatever
+    /// we send that depends on it gets orphaned on the other side, the rest is
+    /// caught by exchanging tips afterwards (see `SyncSession`).
+    pub fn ops_missing_from(&self, filter: &BloomFilter) -> Vec<EncodableOp<T>> {
+        // A run is a chain, so what's missing from it is always a suffix.
+        let mut missing_runs: IdMap<Option<usize>> = IdMap::default();
+        let mut missing_nodes: IdMap<bool> = IdMap::default();
+
+        let is_missing = |id: &Id,
+                          missing_runs: &IdMap<Option<usize>>,
+                          missing_nodes: &IdMap<bool>|
+         -> Option<bool> {
+            match self.run_index.get(id) {
+                Some(run_pos) => missing_runs
+                    .get(&run_pos.run_id)
+                    .map(|start| start.is_some_and(|start| run_pos.position >= start)),
+                None => missing_nodes.get(id).copied(),
+            }
+        };
+
+        // Iterative DFS: a node is resolved once all of its dependencies are.
+        let mut stack: Vec<(Id, bool)> = self.node_ids().map(|id| (*id, false)).collect();
+        while let Some((id, expanded)) = stack.pop() {
+            let item = match self.run_index.get(&id) {
+                Some(run_pos) => self.runs[&run_pos.run_id].first_id(),
+                None => id,
             };
-            debug_assert_eq!(id, node.id());
-            self.apply(node)
+            if is_missing(&item, &missing_runs, &missing_nodes).is_some() {
+                continue;
+            }
+            let deps = self.dependencies_of(&item);
+            if !expanded {
+                stack.push((item, true));
+                stack.extend(
+                    deps.into_iter()
+                        .filter(|dep| is_missing(dep, &missing_runs, &missing_nodes).is_none())
+                        .map(|dep| (dep, false)),
+                );
+                continue;
+            }
+
+            let deps_missing = deps
+                .iter()
+                .any(|dep| is_missing(dep, &missing_runs, &missing_nodes) == Some(true));
+            match self.run_index.get(&item) {
+                Some(run_pos) => {
+                    let run = &self.runs[&run_pos.run_id];
+                    let start = if deps_missing {
+                        Some(0)
+                    } else {
+                        run.elements.iter().position(|id| !filter.contains(id))
+                    };
+                    missing_runs.insert(run_pos.run_id, start);
+                }
+                None => {
+                    missing_nodes.insert(item, deps_missing || !filter.contains(&item));
+                }
+            }
+        }
+
+        self.ops_for_suffixes(
+            missing_runs
+                .into_iter()
+                .filter_map(|(run_id, start)| Some((run_id, start?)))
+                .collect(),
+            missing_nodes
+                .into_iter()
+                .filter(|(_, missing)| *missing)
+                .map(|(id, _)| id)
+                .collect(),
+        )
+    }
+
+    /// Ops for the given run suffixes (run id -> first missing position) and
+    /// individual nodes, in causal order.
+    fn ops_for_suffixes(&self, runs: IdMap<usize>, nodes: IdSet) -> Vec<EncodableOp<T>> {
+        let mut ops: Vec<EncodableOp<T>> = runs
+            .into_iter()
+            .map(|(run_id, start)| {
+                let run = &self.runs[&run_id];
+                EncodableOp::Run(run.slice(start..run.len()))
+            })
+            .chain(
+                nodes
+                    .iter()
+                    .filter_map(|id| self.node(id))
+                    .map(EncodableOp::Node),
+            )
+            .collect();
+        ops.sort_by_key(EncodableOp::first_id);
+        causal_order(ops)
+    }
+
+    /// The ops that create exactly the nodes in `ids`, in causal order. Ids we don't
+    /// have are skipped; consecutive elements of a run are sent as a single `Run`.
+    pub fn ops_for_ids<'a>(&self, ids: impl IntoIterator<Item = &'a Id>) -> Vec<EncodableOp<T>> {
+        let mut run_positions: IdMap<Vec<usize>> = IdMap::default();
+        let mut ops = Vec::new();
+        for id in ids {
+            if let Some(run_pos) = self.run_index.get(id) {
+                run_positions
+                    .entry(run_pos.run_id)
+                    .or_default()
+                    .push(run_pos.position);
+            } else if let Some(node) = self.node(id) {
+                ops.push(EncodableOp::Node(node));
+            }
         }
 
-        // Apply all orphaned nodes
-        for orphan in other.orphaned {
-            self.apply(orphan);
+        for (run_id, mut positions) in run_positions {
+            let run = &self.runs[&run_id];
+            positions.sort_unstable();
+            positions.dedup();
+            let mut start = 0;
+            for i in 1..=positions.len() {
+                if i == positions.len() || positions[i] != positions[i - 1] + 1 {
+                    ops.push(EncodableOp::Run(
+                        run.slice(positions[start]..positions[i - 1] + 1),
+                    ));
+                    start = i;
+                }
+            }
         }
+
+        ops.sort_by_key(EncodableOp::first_id);
+        ops.dedup();
+        causal_order(ops)
     }
 
-    pub fn iter_ids(&self) -> HashSeqIter<'_> {
+    pub fn iter_ids(&self) -> HashSeqIter<'_, T> {
         HashSeqIter::new(self)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = char> + '_ {
-        self.iter_ids().map(|id| self.get_node_char(id))
+    /// The visible elements in order, read off the positional index.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = T> + ExactSizeIterator + '_ {
+        self.index.iter().map(|(_, elem)| elem.clone())
+    }
+
+    /// The visible elements from `idx` on.
+    pub fn iter_from(
+        &self,
+        idx: usize,
+    ) -> impl DoubleEndedIterator<Item = T> + ExactSizeIterator + '_ {
+        self.index.iter_from(idx).map(|(_, elem)| elem.clone())
+    }
+
+    /// The visible elements from `id` on. If `id` has been removed, starts at
+    /// the first visible element after it. `None` if `id` isn't an insert we have.
+    pub fn iter_from_id(
+        &self,
+        id: &Id,
+    ) -> Option<impl DoubleEndedIterator<Item = T> + ExactSizeIterator + '_> {
+        if !self.is_slot(id) {
+            return None;
+        }
+        let slot = self.current_slot(self.element_of(id));
+        Some(self.iter_from(self.visible_position(&slot)))
+    }
+
+    /// A cursor at `idx`, i.e. just before the element at `idx`, sticking to
+    /// the element on the side `gravity` says, wherever it is moved.
+    pub fn cursor_at(&self, idx: usize, gravity: Gravity) -> Cursor {
+        let anchor = match gravity {
+            Gravity::Left => idx.checked_sub(1).and_then(|i| self.index.get(i)),
+            Gravity::Right => self.index.get(idx),
+        };
+        Cursor {
+            anchor: anchor.map(|slot| *self.element_of(slot)),
+            gravity,
+        }
+    }
+
+    /// Where `cursor` is now. If its anchor has been removed, that's where the
+    /// anchor would be, between its closest visible neighbours. `None` if the
+    /// anchor isn't an insert we have, e.g. a peer's cursor we're not caught up on.
+    pub fn cursor_index(&self, cursor: &Cursor) -> Option<usize> {
+        let Some(anchor) = cursor.anchor else {
+            return Some(match cursor.gravity {
+                Gravity::Left => 0,
+                Gravity::Right => self.len(),
+            });
+        };
+        if !self.is_slot(&anchor) {
+            return None;
+        }
+        let slot = self.current_slot(self.element_of(&anchor));
+        let idx = match self.index.find(&slot) {
+            Some(idx) if cursor.gravity == Gravity::Left => idx + 1,
+            Some(idx) => idx,
+            None => self.visible_position(&slot),
+        };
+        Some(idx)
+    }
+
+    /// The marks in effect, as the ranges of the sequence over which they
+    /// don't change, in order. The ranges cover the whole sequence, and each
+    /// comes with the value of every mark set on it.
+    ///
+    /// Resolves every mark, so it's O(marks * log n).
+    pub fn mark_spans(&self) -> Vec<(Range<usize>, BTreeMap<String, String>)> {
+        let mut starts: BTreeMap<usize, Vec<(&Id, &CausalMark)>> = BTreeMap::new();
+        let mut ends: BTreeMap<usize, Vec<(&Id, &CausalMark)>> = BTreeMap::new();
+        let mut bounds = BTreeSet::from([0, self.len()]);
+        for (id, mark) in &self.mark_nodes {
+            let start = self
+                .cursor_index(&mark.start)
+                .expect("marks are anchored on inserts");
+            let end = self
+                .cursor_index(&mark.end)
+                .expect("marks are anchored on inserts");
+            // All of the mark's text is gone, or it was squeezed out by an
+            // insert between edges that don't expand.
+            if end <= start {
+                continue;
+            }
+            starts.entry(start).or_default().push((id, mark));
+            ends.entry(end).or_default().push((id, mark));
+            bounds.extend([start, end]);
+        }
+
+        // The marks covering the current span by name, latest last.
+        let mut active: BTreeMap<&str, BTreeMap<(u64, &Id), Option<&str>>> = BTreeMap::new();
+        let mut spans: Vec<(Range<usize>, BTreeMap<String, String>)> = Vec::new();
+        let bounds: Vec<usize> = bounds.into_iter().collect();
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            for (id, mark) in ends.get(&start).into_iter().flatten() {
+                let by_name = active
+                    .get_mut(mark.name.as_str())
+                    .expect("ended marks started");
+                by_name.remove(&(mark.clock, *id));
+                if by_name.is_empty() {
+                    active.remove(mark.name.as_str());
+                }
+            }
+            for (id, mark) in starts.get(&start).into_iter().flatten() {
+                active
+                    .entry(&mark.name)
+                    .or_default()
+                    .insert((mark.clock, *id), mark.value.as_deref());
+            }
+
+            let marks: BTreeMap<String, String> = active
+                .iter()
+                .filter_map(|(name, by_name)| {
+                    let value = by_name.values().next_back()?.as_ref()?;
+                    Some((name.to_string(), value.to_string()))
+                })
+                .collect();
+            match spans.last_mut() {
+                Some((range, last)) if *last == marks => range.end = end,
+                _ => spans.push((start..end, marks)),
+            }
+        }
+        spans
+    }
+
+    /// The id and element at `idx`, in O(log n).
+    pub fn get(&self, idx: usize) -> Option<(Id, T)> {
+        let (id, elem) = self.index.entry(idx)?;
+        Some((*id, elem.clone()))
+    }
+}
+
+/// Take `item` out of the set under `key`, dropping the set once it's empty.
+fn unlink<K: Ord>(map: &mut IdMap<BTreeSet<K>>, key: Id, item: &K) {
+    let set = map.get_mut(&key).unwrap();
+    set.remove(item);
+    if set.is_empty() {
+        map.remove(&key);
+    }
+}
+
+/// The char range of the grapheme cluster containing the `idx`th char of
+/// `window`, or `None` if the answer depends on text beyond the window.
+/// `more_before`/`more_after` say whether the text continues past the window.
+fn grapheme_in_window(
+    window: &str,
+    idx: usize,
+    more_before: bool,
+    more_after: bool,
+) -> Option<Range<usize>> {
+    // Give the cursor a byte of unknown text on either side, so it asks for
+    // context instead of treating the window's edges as the text's.
+    let pre = usize::from(more_before);
+    let len = pre + window.len() + usize::from(more_after);
+    let (offset, _) = window.char_indices().nth(idx)?;
+
+    let mut cursor = GraphemeCursor::new(pre + offset, len, true);
+    let start = match cursor.is_boundary(window, pre).ok()? {
+        true => pre + offset,
+        false => cursor.prev_boundary(window, pre).ok()??,
+    };
+    let mut cursor = GraphemeCursor::new(pre + offset, len, true);
+    let end = cursor.next_boundary(window, pre).ok()??;
+    if start < pre || end > pre + window.len() {
+        return None;
+    }
+
+    let char_idx = |byte: usize| window[..byte - pre].chars().count();
+    Some(char_idx(start)..char_idx(end))
+}
+
+/// Reorder `ops` so that every op comes after the ops producing its dependencies.
+/// Ties keep their relative order, so sorted input gives a deterministic result.
+fn causal_order<T: Element>(ops: Vec<EncodableOp<T>>) -> Vec<EncodableOp<T>> {
+    let mut producer: IdMap<usize> = IdMap::default();
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            EncodableOp::Run(run) => producer.extend(run.elements.iter().map(|id| (*id, i))),
+            EncodableOp::Node(node) => {
+                producer.insert(node.id(), i);
+            }
+        }
+    }
+
+    let deps: Vec<Vec<usize>> = ops
+        .iter()
+        .enumerate()
+        .map(|(i, op)| {
+            let deps: Vec<&Id> = match op {
+                EncodableOp::Run(run) => std::iter::once(&run.insert_after)
+                    .chain(&run.first_extra_deps)
+                    .collect(),
+                EncodableOp::Node(node) => node.iter_dependencies().collect(),
+            };
+            deps.into_iter()
+                .filter_map(|dep| producer.get(dep).copied())
+                .filter(|j| *j != i)
+                .collect()
+        })
+        .collect();
+
+    let order = topological_order(&deps);
+    let mut ops: Vec<Option<EncodableOp<T>>> = ops.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| ops[i].take()).collect()
+}
 
-        // self.index.iter().map(|id| self.get_node_char(&id).unwrap())
+/// Indices `0..deps.len()` ordered so that each comes after the ones it depends on.
+fn topological_order(deps: &[Vec<usize>]) -> Vec<usize> {
+    // Iterative post-order DFS: dependency chains can be as long as the document.
+    let mut visited = vec![false; deps.len()];
+    let mut order = Vec::with_capacity(deps.len());
+    for start in 0..deps.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some((i, next)) = stack.last_mut() {
+            if let Some(&dep) = deps[*i].get(*next) {
+                *next += 1;
+                if !visited[dep] {
+                    visited[dep] = true;
+                    stack.push((dep, 0));
+                }
+            } else {
+                order.push(*i);
+                stack.pop();
+            }
+        }
     }
+
+    order
+}
+
+/// A piece of another replica being merged: a run suffix or a single node.
+enum MergeUnit<'a, T: Element> {
+    Run(&'a Run<T>, usize),
+    Node(Id, HashNode<T>),
 }
 
 #[cfg(test)]
@@ -819,7 +3005,7 @@ mod test {
         seq_with_abcd.insert_batch(0, "abcd".chars());
 
         let mut empty_seq = HashSeq::default();
-        empty_seq.merge(seq_with_abcd.clone());
+        empty_seq.merge(&seq_with_abcd);
 
         // Verify internal structures are identical
         assert_eq!(
@@ -972,7 +3158,7 @@ mod test {
         seq_a.insert_batch(0, "we wrote".chars());
         seq_b.insert_batch(0, "this together ".chars());
 
-        seq_a.merge(seq_b);
+        seq_a.merge(&seq_b);
 
         assert_eq!(&seq_a.iter().collect::<String>(), "this together we wrote");
     }
@@ -985,7 +3171,7 @@ mod test {
         seq_a.insert_batch(0, "hello my name is david".chars());
         seq_b.insert_batch(0, "hello my name is zameena".chars());
 
-        seq_a.merge(seq_b);
+        seq_a.merge(&seq_b);
 
         let merged = seq_a.iter().collect::<String>();
         assert_eq!(merged, "hello my name is zameenadavid");
@@ -1002,7 +3188,7 @@ mod test {
         seq_b.insert_batch(0, "aza".chars());
         assert_eq!(&seq_b.iter().collect::<String>(), "aza");
 
-        seq_a.merge(seq_b);
+        seq_a.merge(&seq_b);
         assert_eq!(&seq_a.iter().collect::<String>(), "azaba");
     }
 
@@ -1014,7 +3200,7 @@ mod test {
         seq_a.insert_batch(0, "aaab".chars());
         seq_b.insert_batch(0, "aaac".chars());
 
-        seq_a.merge(seq_b);
+        seq_a.merge(&seq_b);
 
         let merged = seq_a.iter().collect::<String>();
         assert_eq!(merged, "aaabc");
@@ -1098,7 +3284,83 @@ mod test {
     }
 
     #[test]
-    fn test_out_of_order_remove_is_cached() {
+    fn test_try_apply_outcomes() {
+        let mut seq = HashSeq::default();
+
+        let root = HashNode {
+            op: Op::InsertRoot('a'),
+            extra_dependencies: BTreeSet::new(),
+        };
+        let after = HashNode {
+            op: Op::InsertAfter(root.id(), 'b'),
+            extra_dependencies: BTreeSet::new(),
+        };
+        let after_after = HashNode {
+            op: Op::InsertAfter(after.id(), 'c'),
+            extra_dependencies: BTreeSet::new(),
+        };
+
+        assert_eq!(
+            seq.try_apply(after_after.clone()),
+            ApplyOutcome::Orphaned {
+                missing: vec![after.id()]
+            }
+        );
+        assert_eq!(
+            seq.try_apply(after.clone()),
+            ApplyOutcome::Orphaned {
+                missing: vec![root.id()]
+            }
+        );
+        assert_eq!(
+            seq.try_apply(root.clone()),
+            ApplyOutcome::Applied {
+                unorphaned: vec![after.id(), after_after.id()],
+                rejected: vec![]
+            }
+        );
+        assert_eq!(seq.try_apply(after), ApplyOutcome::Duplicate);
+        assert_eq!(&String::from_iter(seq.iter()), "abc");
+    }
+
+    #[test]
+    fn test_insert_anchored_on_remove_is_rejected() {
+        let mut seq = HashSeq::default();
+        seq.insert(0, 'a');
+        let remove = seq.remove(0);
+        let EncodableOp::Node(remove) = &remove[0] else {
+            panic!("expected a remove node");
+        };
+
+        let insert = HashNode {
+            op: Op::InsertAfter(remove.id(), 'x'),
+            extra_dependencies: BTreeSet::new(),
+        };
+        assert_eq!(
+            seq.try_apply(insert),
+            ApplyOutcome::Rejected(RejectReason::AnchorIsRemove(remove.id()))
+        );
+
+        // Orphans that turn out to be invalid are dropped once their anchor arrives.
+        let mut fresh = HashSeq::default();
+        let insert = HashNode {
+            op: Op::InsertBefore(remove.id(), 'y'),
+            extra_dependencies: BTreeSet::new(),
+        };
+        assert!(matches!(
+            fresh.try_apply(insert),
+            ApplyOutcome::Orphaned { .. }
+        ));
+        for op in seq.ops_since(&BTreeSet::new()) {
+            fresh.apply_op(op);
+        }
+        assert!(fresh.orphans().is_empty());
+        assert_eq!(fresh, seq);
+        assert_eq!(fresh.len(), 0);
+    }
+
+    #[test]
+    fn test_out_of_order_remove_is_cached() {
         let mut seq = HashSeq::default();
 
         // Attempting to remove insert that doesn't yet exist.
@@ -1134,10 +3396,10 @@ mod test {
         seq_b.insert(0, 'b');
 
         let mut ab = seq_a.clone();
-        ab.merge(seq_b.clone());
+        ab.merge(&seq_b);
 
         let mut ba = seq_b.clone();
-        ba.merge(seq_a.clone());
+        ba.merge(&seq_a);
 
         assert_eq!(ab, ba);
     }
@@ -1158,9 +3420,9 @@ mod test {
         // merge(a, b) == merge(b, a)
 
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -1179,9 +3441,9 @@ mod test {
 
         // merge(a, b) == merge(b, a)
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -1200,10 +3462,10 @@ mod test {
 
         // merge(a, b) == merge(b, a)
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -1224,10 +3486,10 @@ mod test {
 
         // merge(a, b) == merge(b, a)
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -1247,10 +3509,10 @@ mod test {
 
         // merge(a, b) == merge(b, a)
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -1278,7 +3540,7 @@ mod test {
         // merge(a, a) == a
 
         let mut merge_self = seq.clone();
-        merge_self.merge(seq.clone());
+        merge_self.merge(&seq);
 
         assert_eq!(merge_self, seq);
     }
@@ -1296,7 +3558,7 @@ mod test {
 
         // merge(a, a) == a
         let mut merge_self = seq.clone();
-        merge_self.merge(seq.clone());
+        merge_self.merge(&seq);
 
         assert_eq!(merge_self, seq);
     }
@@ -1321,7 +3583,7 @@ mod test {
 
         // merge(a, a) == a
         let mut merge_self = seq.clone();
-        merge_self.merge(seq.clone());
+        merge_self.merge(&seq);
 
         assert_eq!(merge_self, seq);
     }
@@ -1366,10 +3628,10 @@ mod test {
         // merge(a, b) == merge(b, a)
 
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -1435,12 +3697,12 @@ mod test {
         // merge(merge(a, b), c) == merge(a, merge(b, c))
 
         let mut ab_then_c = seq_a.clone();
-        ab_then_c.merge(seq_b.clone());
-        ab_then_c.merge(seq_c.clone());
+        ab_then_c.merge(&seq_b);
+        ab_then_c.merge(&seq_c);
 
         let mut bc_then_a = seq_b.clone();
-        bc_then_a.merge(seq_c.clone());
-        bc_then_a.merge(seq_a.clone());
+        bc_then_a.merge(&seq_c);
+        bc_then_a.merge(&seq_a);
 
         assert_eq!(ab_then_c, bc_then_a);
 
@@ -1719,7 +3981,7 @@ mod test {
         }
 
         let mut merged = seq_a.clone();
-        merged.merge(seq_b.clone());
+        merged.merge(&seq_b);
 
         for r in removed {
             seq_a.apply(HashNode {
@@ -1792,7 +4054,7 @@ mod test {
         }
 
         let mut merged = seq_a.clone();
-        merged.merge(seq_b.clone());
+        merged.merge(&seq_b);
 
         for r in removed {
             seq_a.apply(HashNode {
@@ -1872,7 +4134,7 @@ mod test {
         }
 
         let mut merged = seq_a.clone();
-        merged.merge(seq_b.clone());
+        merged.merge(&seq_b);
 
         for r in removed {
             seq_a.apply(HashNode {
@@ -1924,7 +4186,7 @@ mod test {
         seq_b.insert(2, '\u{97}');
 
         let mut merged = seq_a.clone();
-        merged.merge(seq_b.clone());
+        merged.merge(&seq_b);
 
         seq_a.apply(HashNode {
             op: Op::Remove(BTreeSet::from_iter([removed_id])),
@@ -2000,7 +4262,7 @@ mod test {
         }
 
         let mut merged = seq_a.clone();
-        merged.merge(seq_b.clone());
+        merged.merge(&seq_b);
 
         for r in removed {
             seq_a.apply(HashNode {
@@ -2050,10 +4312,10 @@ mod test {
 
         // Test commutativity
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -2077,10 +4339,10 @@ mod test {
 
         // Test commutativity
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         // Compare content and IDs
         let merge_a_b_content: Vec<char> = merge_a_b.iter().collect();
@@ -2110,10 +4372,10 @@ mod test {
 
         // Test commutativity
         let mut merge_a_b = seq_a.clone();
-        merge_a_b.merge(seq_b.clone());
+        merge_a_b.merge(&seq_b);
 
         let mut merge_b_a = seq_b.clone();
-        merge_b_a.merge(seq_a.clone());
+        merge_b_a.merge(&seq_a);
 
         assert_eq!(merge_a_b, merge_b_a);
     }
@@ -2144,4 +4406,1479 @@ mod test {
         // 'b' is an InsertBefore, which creates a before_node
         assert_eq!(String::from_iter(seq.iter()), "ba");
     }
+
+    #[test]
+    fn test_local_edits_return_ops() {
+        let mut seq_a = HashSeq::default();
+        let mut ops = Vec::new();
+        ops.extend(seq_a.insert_batch(0, "hello world".chars()));
+        ops.extend(seq_a.insert(0, '>'));
+        ops.extend(seq_a.insert_batch(6, ", there".chars()));
+        ops.extend(seq_a.remove_batch(1, 2));
+        assert_eq!(seq_a.iter().collect::<String>(), ">llo, there world");
+
+        // Inserts are grouped into runs; the root and the InsertBefore travel as nodes.
+        assert!(matches!(
+            &ops[0],
+            EncodableOp::Node(HashNode {
+                op: Op::InsertRoot('h'),
+                ..
+            })
+        ));
+        assert!(matches!(&ops[1], EncodableOp::Run(run) if run.run == "ello world"));
+        assert!(matches!(
+            &ops[2],
+            EncodableOp::Node(HashNode {
+                op: Op::InsertBefore(_, '>'),
+                ..
+            })
+        ));
+        assert!(
+            matches!(ops.last(), Some(EncodableOp::Node(HashNode { op: Op::Remove(ids), .. })) if ids.len() == 2)
+        );
+
+        let mut seq_b = HashSeq::default();
+        for op in crate::decode_batch(&crate::encode_batch(&ops)).unwrap() {
+            seq_b.apply_op(op);
+        }
+
+        assert_eq!(seq_b.iter().collect::<String>(), ">llo, there world");
+        assert_eq!(seq_a, seq_b);
+    }
+
+    #[test]
+    fn test_noop_edits_return_no_ops() {
+        let mut seq = HashSeq::default();
+        assert!(seq.insert_batch(0, "".chars()).is_empty());
+        assert!(seq.remove_batch(0, 3).is_empty());
+
+        seq.insert_batch(0, "abc".chars());
+        assert!(seq.remove_batch(1, 0).is_empty());
+        assert!(seq.remove_batch(3, 1).is_empty());
+        assert_eq!(seq.iter().collect::<String>(), "abc");
+    }
+
+    // Regression: extending a run whose tail was concurrently removed used to
+    // panic looking the removed tail up in the positional index.
+    #[test]
+    fn test_insert_after_concurrently_removed_anchor() {
+        let mut seq_a = HashSeq::default();
+        seq_a.insert_batch(0, "ab".chars());
+        let mut seq_b = seq_a.clone();
+
+        seq_a.remove(1);
+        let ops = seq_b.insert(2, 'c');
+
+        for op in ops {
+            seq_a.apply_op(op);
+        }
+        seq_b.merge(&seq_a);
+
+        assert_eq!(seq_a.iter().collect::<String>(), "ac");
+        assert_eq!(seq_b.iter().collect::<String>(), "ac");
+    }
+
+    #[test]
+    fn test_insert_between_tombstones() {
+        let mut seq_a = HashSeq::default();
+        seq_a.insert_batch(0, "abcdefgh".chars());
+        let mut seq_b = seq_a.clone();
+
+        // The neighbours of 'x' are all gone on seq_a by the time it arrives.
+        seq_a.remove_batch(1, 6);
+        let mut ops = seq_b.insert(4, 'x');
+        ops.extend(seq_b.insert(0, 'y'));
+
+        for op in ops {
+            seq_a.apply_op(op);
+        }
+        seq_b.merge(&seq_a);
+
+        assert_eq!(seq_a.iter().collect::<String>(), "yaxh");
+        assert_eq!(seq_b.iter().collect::<String>(), "yaxh");
+        let index: Vec<Id> = seq_a.index.iter().map(|(id, _)| *id).collect();
+        let iter: Vec<Id> = seq_a.iter_ids().copied().collect();
+        assert_eq!(index, iter);
+    }
+
+    #[test]
+    fn test_concurrent_root_lands_before_befores() {
+        let mut seq_a = HashSeq::default();
+        let mut seq_b = HashSeq::default();
+
+        seq_a.insert(0, '\0');
+        seq_a.insert(0, '\0');
+        seq_b.insert(0, '\u{1}');
+
+        seq_a.merge(&seq_b);
+
+        let index: Vec<Id> = seq_a.index.iter().map(|(id, _)| *id).collect();
+        let iter: Vec<Id> = seq_a.iter_ids().copied().collect();
+        assert_eq!(index, iter);
+    }
+
+    #[quickcheck]
+    fn prop_broadcast_ops_converge(
+        base: Vec<(bool, u8, char)>,
+        a: Vec<(bool, u8, char)>,
+        b: Vec<(bool, u8, char)>,
+    ) {
+        fn edit(seq: &mut HashSeq, ops: &[(bool, u8, char)]) -> Vec<EncodableOp> {
+            let mut out = Vec::new();
+            for &(insert_or_remove, idx, elem) in ops {
+                let idx = idx as usize;
+                if insert_or_remove {
+                    out.extend(seq.insert(idx.min(seq.len()), elem));
+                } else if !seq.is_empty() {
+                    out.extend(seq.remove(idx.min(seq.len() - 1)));
+                }
+            }
+            out
+        }
+
+        let mut seq_a = HashSeq::default();
+        edit(&mut seq_a, &base);
+        let mut seq_b = seq_a.clone();
+
+        let ops_a = edit(&mut seq_a, &a);
+        let ops_b = edit(&mut seq_b, &b);
+
+        for op in ops_b {
+            seq_a.apply_op(op);
+        }
+        for op in ops_a {
+            seq_b.apply_op(op);
+        }
+
+        assert_eq!(seq_a, seq_b);
+        assert_eq!(
+            seq_a.iter().collect::<String>(),
+            seq_b.iter().collect::<String>()
+        );
+
+        // The positional index must agree with iteration order after concurrent edits.
+        let index: Vec<Id> = seq_a.index.iter().map(|(id, _)| *id).collect();
+        let iter: Vec<Id> = seq_a.iter_ids().copied().collect();
+        assert_eq!(index, iter);
+    }
+
+    #[test]
+    fn test_ops_since_cuts_runs() {
+        let mut seq_a = HashSeq::default();
+        seq_a.insert_batch(0, "hello".chars());
+        let mut seq_b = seq_a.clone();
+
+        seq_a.insert_batch(5, " world".chars());
+
+        let ops = seq_a.ops_since(seq_b.tips());
+        assert_eq!(ops.len(), 1);
+        let EncodableOp::Run(run) = &ops[0] else {
+            panic!("expected a run, got {:?}", ops[0]);
+        };
+        assert_eq!(run.run, " world");
+
+        for op in ops {
+            seq_b.apply_op(op);
+        }
+        assert_eq!(seq_a, seq_b);
+        assert_eq!(seq_b.iter().collect::<String>(), "hello world");
+    }
+
+    #[test]
+    fn test_ops_since_empty_tips_sends_everything() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        seq.insert(0, 'x');
+        seq.remove(2);
+
+        let mut fresh = HashSeq::default();
+        for op in seq.ops_since(&BTreeSet::new()) {
+            fresh.apply_op(op);
+        }
+        assert!(fresh.orphans().is_empty());
+        assert_eq!(fresh, seq);
+        assert_eq!(fresh.iter().collect::<String>(), "xac");
+    }
+
+    #[test]
+    fn test_ops_since_up_to_date() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        seq.remove(1);
+
+        assert_eq!(seq.ops_since(&seq.tips().clone()), vec![]);
+    }
+
+    #[quickcheck]
+    fn prop_ops_since_converges(
+        base: Vec<(bool, u8, char)>,
+        a: Vec<(bool, u8, char)>,
+        b: Vec<(bool, u8, char)>,
+    ) {
+        fn edit(seq: &mut HashSeq, ops: &[(bool, u8, char)]) {
+            for &(insert_or_remove, idx, elem) in ops {
+                let idx = idx as usize;
+                if insert_or_remove {
+                    seq.insert(idx.min(seq.len()), elem);
+                } else if !seq.is_empty() {
+                    seq.remove(idx.min(seq.len() - 1));
+                }
+            }
+        }
+
+        let mut seq_a = HashSeq::default();
+        edit(&mut seq_a, &base);
+        let mut seq_b = seq_a.clone();
+        edit(&mut seq_a, &a);
+        edit(&mut seq_b, &b);
+
+        let delta_a = crate::encode_delta(&seq_a.ops_since(seq_b.tips()));
+        let delta_b = crate::encode_delta(&seq_b.ops_since(seq_a.tips()));
+
+        // Deltas are causally ordered, so nothing should be left orphaned.
+        for op in crate::decode_delta(&delta_b).unwrap() {
+            seq_a.apply_op(op);
+        }
+        for op in crate::decode_delta(&delta_a).unwrap() {
+            seq_b.apply_op(op);
+        }
+
+        assert!(seq_a.orphans().is_empty());
+        assert!(seq_b.orphans().is_empty());
+        assert_eq!(seq_a, seq_b);
+        assert_eq!(
+            seq_a.iter().collect::<String>(),
+            seq_b.iter().collect::<String>()
+        );
+    }
+
+    #[test]
+    fn test_merge_shared_run_prefix() {
+        let mut seq_a = HashSeq::default();
+        seq_a.insert_batch(0, "hello".chars());
+        let mut seq_b = seq_a.clone();
+        seq_a.insert_batch(5, " world".chars());
+        seq_b.insert_batch(0, ">> ".chars());
+
+        // `seq_b` has the start of `seq_a`'s run, only the rest is merged.
+        seq_b.merge(&seq_a);
+        assert!(seq_b.orphans().is_empty());
+        assert_eq!(seq_b.iter().collect::<String>(), ">> hello world");
+        assert_eq!(seq_b.verify_ids(), Ok(()));
+
+        seq_a.merge(&seq_b);
+        assert_eq!(seq_a, seq_b);
+    }
+
+    #[test]
+    fn test_verify_ids_detects_tampering() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello".chars());
+        seq.insert(0, '>');
+        seq.remove(3);
+        assert_eq!(seq.verify_ids(), Ok(()));
+
+        let (run_id, run) = seq.runs.iter_mut().next().unwrap();
+        let run_id = *run_id;
+        run.run = run.run.replace("e", "a");
+        assert_eq!(seq.verify_ids(), Err(run_id));
+    }
+
+    #[quickcheck]
+    fn prop_merge_matches_applying_ops(
+        base: Vec<(bool, u8, char)>,
+        a: Vec<(bool, u8, char)>,
+        b: Vec<(bool, u8, char)>,
+    ) {
+        fn edit(seq: &mut HashSeq, ops: &[(bool, u8, char)]) {
+            for &(insert_or_remove, idx, elem) in ops {
+                let idx = idx as usize;
+                if insert_or_remove {
+                    seq.insert(idx.min(seq.len()), elem);
+                } else if !seq.is_empty() {
+                    seq.remove(idx.min(seq.len() - 1));
+                }
+            }
+        }
+
+        let mut seq_a = HashSeq::default();
+        edit(&mut seq_a, &base);
+        let mut seq_b = seq_a.clone();
+        edit(&mut seq_a, &a);
+        edit(&mut seq_b, &b);
+
+        let mut merged = seq_b.clone();
+        merged.merge(&seq_a);
+        let mut applied = seq_b;
+        for op in seq_a.ops_since(&BTreeSet::new()) {
+            applied.apply_op(op);
+        }
+
+        assert!(merged.orphans().is_empty());
+        assert_eq!(merged, applied);
+        assert_eq!(merged.node_ids().count(), applied.node_ids().count());
+        assert_eq!(
+            merged.iter().collect::<String>(),
+            applied.iter().collect::<String>()
+        );
+    }
+
+    #[test]
+    fn test_utf16_offsets() {
+        let mut seq = HashSeq::default();
+        seq.insert_utf16(0, "a🦀b").unwrap();
+        assert_eq!(seq.len(), 3);
+        assert_eq!(seq.utf16_len(), 4);
+        assert_eq!(seq.utf8_len(), 6);
+        assert_eq!(seq.utf16_to_index(3), Some(2));
+        assert_eq!(seq.utf16_to_index(2), None);
+        assert_eq!(seq.utf16_to_index(5), None);
+        assert_eq!(seq.index_to_utf16(2), 3);
+
+        seq.insert_utf16(3, "é").unwrap();
+        assert_eq!(seq.iter().collect::<String>(), "a🦀éb");
+        seq.remove_utf16(1, 2).unwrap();
+        assert_eq!(seq.iter().collect::<String>(), "aéb");
+
+        seq.insert_utf8(3, "🦀").unwrap();
+        assert_eq!(seq.iter().collect::<String>(), "aé🦀b");
+        assert_eq!(seq.utf8_to_index(2), None);
+        assert_eq!(seq.index_to_utf8(3), 7);
+        seq.remove_utf8(1, 6).unwrap();
+        assert_eq!(seq.iter().collect::<String>(), "ab");
+    }
+
+    #[test]
+    fn test_invalid_utf_offsets_change_nothing() {
+        let mut seq = HashSeq::default();
+        seq.insert_utf16(0, "🦀é").unwrap();
+        assert_eq!(seq.insert_utf16(1, "x"), None);
+        assert_eq!(seq.insert_utf16(4, "x"), None);
+        assert_eq!(seq.remove_utf16(0, 1), None);
+        assert_eq!(seq.remove_utf16(1, usize::MAX), None);
+        assert_eq!(seq.insert_utf8(2, "x"), None);
+        assert_eq!(seq.remove_utf8(4, 1), None);
+        assert_eq!(seq.remove_utf8(usize::MAX, 1), None);
+        assert_eq!(seq.to_string(), "🦀é");
+    }
+
+    #[quickcheck]
+    fn prop_utf_offsets_match_string(edits: Vec<(bool, u8, String)>) {
+        // Offsets land on char boundaries of the model, like an editor's would.
+        fn boundary(text: &str, offset: usize) -> usize {
+            let mut offset = offset.min(text.len());
+            while !text.is_char_boundary(offset) {
+                offset -= 1;
+            }
+            offset
+        }
+
+        let mut seq = HashSeq::default();
+        let mut model = String::new();
+        for (insert_or_remove, offset, text) in edits {
+            let start = boundary(&model, offset as usize);
+            if insert_or_remove {
+                let utf16 = model[..start].encode_utf16().count();
+                seq.insert_utf16(utf16, &text).unwrap();
+                model.insert_str(start, &text);
+            } else {
+                let end = boundary(&model, start + text.len());
+                seq.remove_utf8(start, end - start).unwrap();
+                model.replace_range(start..end, "");
+            }
+        }
+
+        assert_eq!(seq.iter().collect::<String>(), model);
+        assert_eq!(seq.utf8_len(), model.len());
+        assert_eq!(seq.utf16_len(), model.encode_utf16().count());
+        for (idx, (byte, _)) in model.char_indices().enumerate() {
+            let utf16 = model[..byte].encode_utf16().count();
+            assert_eq!(seq.index_to_utf8(idx), byte);
+            assert_eq!(seq.utf8_to_index(byte), Some(idx));
+            assert_eq!(seq.index_to_utf16(idx), utf16);
+            assert_eq!(seq.utf16_to_index(utf16), Some(idx));
+        }
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut seq = HashSeq::default();
+        let text = "ae\u{301}👨\u{200d}👩\u{200d}👧🇺🇸🇬🇧\r\n\u{1100}\u{1161}\u{11a8}!";
+        seq.insert_batch(0, text.chars());
+        assert_eq!(seq.grapheme_count(), 8);
+        assert_eq!(seq.grapheme_at(2), Some(1..3));
+        assert_eq!(seq.grapheme_at(5), Some(3..8));
+        assert_eq!(seq.grapheme_at(10), Some(10..12));
+        assert_eq!(seq.grapheme_at(13), Some(12..14));
+        assert_eq!(seq.grapheme_at(17), Some(17..18));
+        assert_eq!(seq.grapheme_at(18), None);
+        assert_eq!(seq.grapheme_to_index(3), Some(8));
+        assert_eq!(seq.grapheme_to_index(8), Some(18));
+        assert_eq!(seq.grapheme_to_index(9), None);
+        assert_eq!(seq.index_to_grapheme(6), 2);
+
+        seq.remove_grapheme_at(4);
+        seq.remove_grapheme_at(1);
+        assert_eq!(
+            seq.iter().collect::<String>(),
+            "a🇺🇸🇬🇧\r\n\u{1100}\u{1161}\u{11a8}!"
+        );
+        // The second flag, not half of each.
+        seq.remove_grapheme_at(3);
+        assert_eq!(
+            seq.iter().collect::<String>(),
+            "a🇺🇸\r\n\u{1100}\u{1161}\u{11a8}!"
+        );
+    }
+
+    #[test]
+    fn test_graphemes_wider_than_the_window() {
+        let mut seq = HashSeq::default();
+        let flags = "🇺🇸".repeat(50);
+        let marks = "\u{301}".repeat(100);
+        seq.insert_batch(0, format!("x{flags}a{marks}").chars());
+        // Pairing the regional indicators depends on how many come before.
+        assert_eq!(seq.grapheme_at(80), Some(79..81));
+        assert_eq!(seq.grapheme_at(150), Some(101..202));
+        seq.remove_grapheme_at(150);
+        assert_eq!(seq.iter().collect::<String>(), format!("x{flags}"));
+    }
+
+    #[quickcheck]
+    fn prop_grapheme_at_matches_segmentation(picks: Vec<u8>) {
+        const PIECES: [&str; 8] = [
+            "a", "\u{301}", "\u{200d}", "👩", "🇺", "\r", "\n", "\u{1100}",
+        ];
+        let text: String = picks
+            .iter()
+            .map(|p| PIECES[*p as usize % PIECES.len()])
+            .collect();
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, text.chars());
+
+        let mut start = 0;
+        for grapheme in text.graphemes(true) {
+            let end = start + grapheme.chars().count();
+            for idx in start..end {
+                assert_eq!(seq.grapheme_at(idx), Some(start..end));
+            }
+            start = end;
+        }
+        assert_eq!(seq.grapheme_count(), text.graphemes(true).count());
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut seq = HashSeq::default();
+        assert_eq!(seq.line_count(), 1);
+        assert_eq!(seq.line(0), Some(String::new()));
+
+        seq.insert_batch(0, "one\ntwo\n\nfour".chars());
+        assert_eq!(seq.line_count(), 4);
+        assert_eq!(seq.line_to_index(1), Some(4));
+        assert_eq!(seq.line_to_index(3), Some(9));
+        assert_eq!(seq.line_to_index(4), None);
+        assert_eq!(seq.index_to_line_col(6), (1, 2));
+        assert_eq!(seq.index_to_line_col(8), (2, 0));
+        assert_eq!(seq.index_to_line_col(13), (3, 4));
+        assert_eq!(seq.line(1), Some("two".to_string()));
+        assert_eq!(seq.line(2), Some(String::new()));
+        assert_eq!(seq.line(4), None);
+
+        // Joining two lines.
+        seq.remove(3);
+        assert_eq!(seq.line_count(), 3);
+        assert_eq!(seq.line(0), Some("onetwo".to_string()));
+        assert_eq!(seq.index_to_line_col(9), (2, 1));
+    }
+
+    #[quickcheck]
+    fn prop_lines_match_model(edits: Vec<(bool, u8, bool)>) {
+        let mut seq = HashSeq::default();
+        for (i, (insert_or_remove, idx, newline)) in edits.into_iter().enumerate() {
+            let idx = idx as usize;
+            if insert_or_remove {
+                let ch = if newline {
+                    '\n'
+                } else {
+                    (b'a' + (i % 26) as u8) as char
+                };
+                seq.insert(idx.min(seq.len()), ch);
+            } else if !seq.is_empty() {
+                seq.remove(idx.min(seq.len() - 1));
+            }
+        }
+
+        let text: String = seq.iter().collect();
+        let lines: Vec<&str> = text.split('\n').collect();
+        assert_eq!(seq.line_count(), lines.len());
+        let mut start = 0;
+        for (n, line) in lines.iter().enumerate() {
+            assert_eq!(seq.line_to_index(n), Some(start));
+            assert_eq!(seq.line(n).as_deref(), Some(*line));
+            for col in 0..=line.len() {
+                assert_eq!(seq.index_to_line_col(start + col), (n, col));
+            }
+            start += line.len() + 1;
+        }
+        assert_eq!(seq.line_to_index(lines.len()), None);
+    }
+
+    #[test]
+    fn test_random_access() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello world".chars());
+        seq.insert_batch(5, ",".chars());
+        let removed = seq.get(7).unwrap().0;
+        seq.remove(7);
+
+        assert_eq!(seq.get(0).map(|(_, ch)| ch), Some('h'));
+        assert_eq!(seq.get(7).map(|(_, ch)| ch), Some('o'));
+        assert_eq!(seq.get(11), None);
+        assert_eq!(seq.slice(3..8), "lo, o");
+        assert_eq!(seq.slice(8..100), "rld");
+        assert_eq!(seq.slice(20..30), "");
+        assert_eq!(seq.iter().rev().collect::<String>(), "dlro ,olleh");
+        assert_eq!(seq.iter_from(9).collect::<String>(), "ld");
+
+        let (comma, _) = seq.get(5).unwrap();
+        assert_eq!(
+            seq.iter_from_id(&comma).unwrap().collect::<String>(),
+            ", orld"
+        );
+        // 'w' is gone, so we start at what followed it.
+        assert_eq!(
+            seq.iter_from_id(&removed).unwrap().collect::<String>(),
+            "orld"
+        );
+        assert!(seq.iter_from_id(&Id([0; 32])).is_none());
+    }
+
+    #[quickcheck]
+    fn prop_random_access_matches_model(edits: Vec<(bool, u8, char)>, start: u8, end: u8) -> bool {
+        let mut seq = HashSeq::default();
+        let mut model = Vec::new();
+        for (insert_or_remove, idx, ch) in edits {
+            let idx = idx as usize;
+            if insert_or_remove {
+                seq.insert(idx.min(seq.len()), ch);
+                model.insert(idx.min(model.len()), ch);
+            } else if !model.is_empty() {
+                seq.remove(idx.min(seq.len() - 1));
+                model.remove(idx.min(model.len() - 1));
+            }
+        }
+
+        let (start, end) = (start as usize, end as usize);
+        let expected: String = model
+            .iter()
+            .skip(start)
+            .take(end.min(model.len()).saturating_sub(start))
+            .collect();
+        seq.iter_ids()
+            .map(|id| seq.get_node_char(id))
+            .eq(model.iter().copied())
+            && seq.iter().rev().eq(model.iter().rev().copied())
+            && (0..model.len()).all(|i| seq.get(i).map(|(_, ch)| ch) == Some(model[i]))
+            && seq.slice(start..end) == expected
+            && seq.iter_from(start).eq(model.iter().skip(start).copied())
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello world".chars());
+        seq.remove(5);
+        seq.insert(0, '>');
+        seq.insert_batch(4, "XY".chars());
+        assert_eq!(seq.to_string(), ">helXYloworld");
+
+        let chunks: Vec<_> = seq.chunks().collect();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.index, c.text.as_ref()))
+                .collect::<Vec<_>>(),
+            [
+                (0, ">"),
+                (1, "h"),
+                (2, "el"),
+                (4, "X"),
+                (5, "Y"),
+                (6, "lo"),
+                (8, "world")
+            ]
+        );
+        for chunk in &chunks {
+            assert_eq!(seq.get(chunk.index).map(|(id, _)| id), Some(chunk.id));
+        }
+        // Anything in a run is borrowed, however the run is split up.
+        assert!(matches!(chunks[2].text, std::borrow::Cow::Borrowed("el")));
+        assert!(matches!(
+            chunks[6].text,
+            std::borrow::Cow::Borrowed("world")
+        ));
+
+        assert_eq!(HashSeq::default().chunks().count(), 0);
+    }
+
+    #[quickcheck]
+    fn prop_chunks_cover_the_text(edits: Vec<(bool, u8, char)>) -> bool {
+        let mut seq = HashSeq::default();
+        for (insert_or_remove, idx, ch) in edits {
+            let idx = idx as usize;
+            if insert_or_remove {
+                seq.insert(idx.min(seq.len()), ch);
+            } else if !seq.is_empty() {
+                seq.remove(idx.min(seq.len() - 1));
+            }
+        }
+
+        let mut text = String::new();
+        for chunk in seq.chunks() {
+            if chunk.text.is_empty()
+                || chunk.index != text.chars().count()
+                || seq.get(chunk.index).map(|(id, _)| id) != Some(chunk.id)
+            {
+                return false;
+            }
+            text.push_str(&chunk.text);
+        }
+        text == seq.iter().collect::<String>() && text == seq.to_string()
+    }
+
+    #[test]
+    fn test_splice() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "hello world".chars());
+        let mut bob = alice.clone();
+
+        let ops = alice.splice(0..5, "howdy".chars());
+        assert_eq!(alice.to_string(), "howdy world");
+        // The insert depends on the remove, so it can't land without it.
+        let [EncodableOp::Node(remove), insert @ ..] = &ops[..] else {
+            panic!("expected the remove first, got {ops:?}");
+        };
+        assert!(matches!(remove.op, Op::Remove(_)));
+        let first_insert = match &insert[0] {
+            EncodableOp::Node(node) => node.clone(),
+            EncodableOp::Run(run) => run.decompress().remove(0),
+        };
+        assert!(first_insert.extra_dependencies.contains(&remove.id()));
+
+        // Concurrent edits on either side of the replaced text stay there.
+        bob.insert_batch(5, "!".chars());
+        bob.insert_batch(0, "> ".chars());
+        for op in ops {
+            bob.apply_op(op);
+        }
+        assert_eq!(bob.to_string(), "> howdy! world");
+
+        assert_eq!(alice.splice(5..5, ",".chars()).len(), 1);
+        assert_eq!(alice.splice(5..6, []).len(), 1);
+        assert!(alice.splice(0..0, []).is_empty());
+        assert_eq!(alice.to_string(), "howdy world");
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_splice_out_of_bounds_panics() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        seq.splice(2..4, "x".chars());
+    }
+
+    #[quickcheck]
+    fn prop_splice_vec_model(instructions: Vec<(u8, u8, String)>) {
+        let mut model: Vec<char> = Vec::new();
+        let mut seq = HashSeq::default();
+        let mut replica = HashSeq::default();
+
+        for (start, len, text) in instructions {
+            let start = start as usize % (model.len() + 1);
+            let end = (start + len as usize % 4).min(model.len());
+            model.splice(start..end, text.chars());
+            for op in seq.splice(start..end, text.chars()) {
+                replica.apply_op(op);
+            }
+        }
+
+        assert_eq!(seq.iter().collect::<Vec<_>>(), model);
+        assert_eq!(replica, seq);
+    }
+
+    #[test]
+    fn test_id_addressed_edits() {
+        let mut alice = HashSeq::default();
+        let mut ops = alice.insert_batch(0, "abc".chars());
+        let id = |seq: &HashSeq, idx: usize| seq.get(idx).unwrap().0;
+        let (a, b, c) = (id(&alice, 0), id(&alice, 1), id(&alice, 2));
+
+        ops.extend(alice.insert_after_id(&b, "XY".chars()));
+        assert_eq!(alice.to_string(), "abXYc");
+        ops.extend(alice.insert_before_id(&b, "Z".chars()));
+        assert_eq!(alice.to_string(), "aZbXYc");
+        ops.extend(alice.insert_before_id(&b, "W".chars()));
+        assert_eq!(alice.to_string(), "aZWbXYc");
+        ops.extend(alice.insert_after_id(&a, "V".chars()));
+        assert_eq!(alice.to_string(), "aVZWbXYc");
+
+        // Removed anchors still mark the spot.
+        ops.extend(alice.remove_ids(&BTreeSet::from([b, c])));
+        assert_eq!(alice.to_string(), "aVZWXY");
+        ops.extend(alice.insert_after_id(&b, "1".chars()));
+        ops.extend(alice.insert_before_id(&b, "2".chars()));
+        ops.extend(alice.insert_after_id(&c, "3".chars()));
+        assert_eq!(alice.to_string(), "aVZW21XY3");
+
+        // Removing what's already gone, or was never there, is a no-op.
+        assert!(
+            alice
+                .remove_ids(&BTreeSet::from([b, Id::default()]))
+                .is_empty()
+        );
+        assert!(alice.insert_after_id(&a, []).is_empty());
+
+        let mut bob = HashSeq::default();
+        for op in ops {
+            bob.apply_op(op);
+        }
+        assert_eq!(bob, alice);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an insert")]
+    fn test_insert_after_unknown_id_panics() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        seq.insert_after_id(&Id::default(), "x".chars());
+    }
+
+    #[test]
+    fn test_id_addressed_edits_survive_concurrent_edits() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "hello world".chars());
+        let mut bob = alice.clone();
+        let space = alice.get(5).unwrap().0;
+
+        // Bob's edits shift every index, but not where the comma goes.
+        bob.insert_batch(0, ">> ".chars());
+        bob.remove_batch(3, 2);
+        alice.insert_before_id(&space, ",".chars());
+        alice.merge(&bob);
+        bob.merge(&alice);
+        assert_eq!(alice.to_string(), ">> llo, world");
+        assert_eq!(alice, bob);
+    }
+
+    #[quickcheck]
+    fn prop_id_addressed_edits_match_model(edits: Vec<(u8, u8, char)>) -> bool {
+        let mut seq = HashSeq::default();
+        let mut model = Vec::new();
+        let mut ops = Vec::new();
+        for (kind, idx, ch) in edits {
+            if model.is_empty() {
+                ops.extend(seq.insert(0, ch));
+                model.insert(0, ch);
+                continue;
+            }
+            let idx = idx as usize % model.len();
+            let (id, _) = seq.get(idx).unwrap();
+            match kind % 3 {
+                0 => {
+                    ops.extend(seq.insert_after_id(&id, [ch, ch]));
+                    model.splice(idx + 1..idx + 1, [ch, ch]);
+                }
+                1 => {
+                    ops.extend(seq.insert_before_id(&id, [ch]));
+                    model.insert(idx, ch);
+                }
+                _ => {
+                    ops.extend(seq.remove_ids(&BTreeSet::from([id])));
+                    model.remove(idx);
+                }
+            }
+        }
+
+        let mut replica = HashSeq::default();
+        for op in ops {
+            replica.apply_op(op);
+        }
+        seq.iter().eq(model.iter().copied()) && replica == seq
+    }
+
+    fn marks<const N: usize>(marks: [(&str, &str); N]) -> BTreeMap<String, String> {
+        marks
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_marks() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello world".chars());
+        seq.add_mark(0..5, "bold", "true", Expand::End);
+        seq.add_mark(6..11, "link", "https://example.com", Expand::None);
+        assert_eq!(
+            seq.marked_chunks().collect::<Vec<_>>(),
+            [
+                ("hello".to_string(), marks([("bold", "true")])),
+                (" ".to_string(), marks([])),
+                (
+                    "world".to_string(),
+                    marks([("link", "https://example.com")])
+                ),
+            ]
+        );
+
+        // Typing at the end of the bold text is bold, at the start of the link isn't linked.
+        seq.insert(5, '!');
+        seq.insert(7, '<');
+        assert_eq!(seq.to_string(), "hello! <world");
+        assert_eq!(
+            seq.mark_spans(),
+            [
+                (0..6, marks([("bold", "true")])),
+                (6..8, marks([])),
+                (8..13, marks([("link", "https://example.com")])),
+            ]
+        );
+
+        // The latest mark wins where they overlap.
+        seq.remove_mark(1..3, "bold", Expand::None);
+        seq.add_mark(10..13, "link", "https://example.org", Expand::None);
+        assert_eq!(
+            seq.marked_chunks().collect::<Vec<_>>(),
+            [
+                ("h".to_string(), marks([("bold", "true")])),
+                ("el".to_string(), marks([])),
+                ("lo!".to_string(), marks([("bold", "true")])),
+                (" <".to_string(), marks([])),
+                ("wo".to_string(), marks([("link", "https://example.com")])),
+                ("rld".to_string(), marks([("link", "https://example.org")])),
+            ]
+        );
+
+        // Marks have no position of their own.
+        let mark = seq.add_mark(0..1, "bold", "true", Expand::None)[0].first_id();
+        assert!(seq.iter_from_id(&mark).is_none());
+        for gravity in [Gravity::Left, Gravity::Right] {
+            let cursor = Cursor {
+                anchor: Some(mark),
+                gravity,
+            };
+            assert_eq!(seq.cursor_index(&cursor), None);
+        }
+
+        // Marks don't outlive their text.
+        seq.remove_batch(8, 5);
+        assert_eq!(seq.mark_spans().len(), 4);
+        assert!(seq.add_mark(2..2, "bold", "true", Expand::Both).is_empty());
+        assert!(HashSeq::<char>::default().mark_spans().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_mark_out_of_bounds_panics() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        seq.add_mark(1..4, "bold", "true", Expand::None);
+    }
+
+    #[test]
+    fn test_concurrent_marks() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "one two three".chars());
+        let mut bob = alice.clone();
+
+        alice.add_mark(0..7, "color", "red", Expand::None);
+        bob.add_mark(4..13, "color", "blue", Expand::None);
+        bob.insert_batch(7, " and a half".chars());
+        alice.merge(&bob);
+        bob.merge(&alice);
+        let spans = alice.mark_spans();
+        assert_eq!(spans, bob.mark_spans());
+        // Both marks have the same clock, so their ids decide: blue wins "two".
+        assert_eq!(
+            spans,
+            [
+                (0..4, marks([("color", "red")])),
+                (4..24, marks([("color", "blue")])),
+            ]
+        );
+
+        // A mark made after seeing both of them wins over both.
+        alice.add_mark(0..alice.len(), "color", "green", Expand::None);
+        bob.merge(&alice);
+        assert_eq!(
+            bob.marked_chunks().collect::<Vec<_>>(),
+            [(
+                "one two and a half three".to_string(),
+                marks([("color", "green")])
+            )]
+        );
+    }
+
+    #[test]
+    fn test_mark_clocks_are_bounded() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        let mark = |seq: &HashSeq, clock| HashNode {
+            extra_dependencies: seq.tips().clone(),
+            op: Op::AddMark {
+                start: seq.cursor_at(0, Gravity::Right),
+                end: seq.cursor_at(1, Gravity::Left),
+                clock,
+                name: "bold".to_string(),
+                value: "true".to_string(),
+            },
+        };
+
+        // A peer can't jump ahead of every mark it has seen, nor overflow ours.
+        for clock in [2, u64::MAX] {
+            assert_eq!(
+                seq.try_apply(mark(&seq, clock)),
+                ApplyOutcome::Rejected(RejectReason::ClockTooHigh { clock, max: 1 })
+            );
+        }
+        assert!(seq.mark_spans().iter().all(|(_, marks)| marks.is_empty()));
+
+        assert!(matches!(
+            seq.try_apply(mark(&seq, 1)),
+            ApplyOutcome::Applied { .. }
+        ));
+        let local = seq.add_mark(1..3, "bold", "false", Expand::None);
+        assert!(matches!(
+            &local[..],
+            [EncodableOp::Node(HashNode {
+                op: Op::AddMark { clock: 2, .. },
+                ..
+            })]
+        ));
+        assert_eq!(
+            seq.try_apply(mark(&seq, 4)),
+            ApplyOutcome::Rejected(RejectReason::ClockTooHigh { clock: 4, max: 3 })
+        );
+    }
+
+    #[quickcheck]
+    fn prop_marks_commutative(a: Vec<(u8, u8, u8, char)>, b: Vec<(u8, u8, u8, char)>) -> bool {
+        fn edit(seq: &mut HashSeq, edits: &[(u8, u8, u8, char)]) {
+            for &(kind, x, y, ch) in edits {
+                let (x, y) = (x as usize, y as usize);
+                let name = ["bold", "link"][x % 2];
+                let expand = [Expand::None, Expand::Start, Expand::End, Expand::Both][y % 4];
+                let range = x.min(y).min(seq.len())..x.max(y).min(seq.len());
+                match kind % 4 {
+                    0 => {
+                        seq.insert(x.min(seq.len()), ch);
+                    }
+                    1 if !seq.is_empty() => {
+                        seq.remove(x.min(seq.len() - 1));
+                    }
+                    2 => {
+                        seq.add_mark(range, name, &ch.to_string(), expand);
+                    }
+                    _ => {
+                        seq.remove_mark(range, name, expand);
+                    }
+                }
+            }
+        }
+
+        let mut seq_a = HashSeq::default();
+        let mut seq_b = HashSeq::default();
+        edit(&mut seq_a, &a);
+        edit(&mut seq_b, &b);
+
+        let mut merge_a_b = seq_a.clone();
+        merge_a_b.merge(&seq_b);
+        let mut merge_b_a = seq_b.clone();
+        merge_b_a.merge(&seq_a);
+
+        let spans = merge_a_b.mark_spans();
+        let covered = spans.iter().map(|(range, _)| range.len()).sum::<usize>();
+        merge_a_b == merge_b_a
+            && spans == merge_b_a.mark_spans()
+            && covered == merge_a_b.len()
+            && spans
+                .windows(2)
+                .all(|w| w[0].0.end == w[1].0.start && w[0].1 != w[1].1)
+    }
+
+    #[test]
+    fn test_move_range() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "one two three".chars());
+        let ids: Vec<Id> = seq.iter_ids().copied().collect();
+        let mut replica = seq.clone();
+
+        let ops = seq.move_range(4..8, 13);
+        assert_eq!(ops.len(), 4);
+        assert_eq!(seq.to_string(), "one threetwo ");
+        let ops = ops.into_iter().chain(seq.move_range(0..4, 9));
+        for op in ops {
+            replica.apply_op(op);
+        }
+        assert_eq!(seq.to_string(), "threeone two ");
+        assert_eq!(replica, seq);
+        assert_eq!(replica.to_string(), "threeone two ");
+        assert_eq!(
+            seq.iter_ids().collect::<Vec<_>>(),
+            seq.index.iter().map(|(id, _)| id).collect::<Vec<_>>()
+        );
+
+        // Moving a range onto itself does nothing.
+        assert!(seq.move_range(2..5, 2).is_empty());
+        assert!(seq.move_range(2..5, 5).is_empty());
+        assert!(seq.move_range(2..2, 0).is_empty());
+
+        // Edits by id follow the moved elements.
+        seq.remove_batch(0, 1);
+        assert_eq!(seq.to_string(), "hreeone two ");
+        seq.move_range(0..4, 12);
+        assert_eq!(seq.to_string(), "one two hree");
+        seq.insert_after_id(&ids[9], "H".chars());
+        assert_eq!(seq.to_string(), "one two hHree");
+        seq.remove_ids(&ids[..3].iter().copied().collect());
+        assert_eq!(seq.to_string(), " two hHree");
+
+        // So do cursors and marks.
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abcdef".chars());
+        let before_b = seq.cursor_at(1, Gravity::Right);
+        let after_b = seq.cursor_at(2, Gravity::Left);
+        seq.add_mark(1..2, "bold", "true", Expand::None);
+        seq.move_range(1..2, 6);
+        assert_eq!(seq.to_string(), "acdefb");
+        assert_eq!(seq.cursor_index(&before_b), Some(5));
+        assert_eq!(seq.cursor_index(&after_b), Some(6));
+        assert_eq!(
+            seq.mark_spans(),
+            [(0..5, marks([])), (5..6, marks([("bold", "true")]))]
+        );
+
+        // A cursor taken on a moved element keeps following it.
+        let on_b = seq.cursor_at(5, Gravity::Right);
+        seq.move_range(5..6, 0);
+        assert_eq!(seq.to_string(), "bacdef");
+        assert_eq!(seq.cursor_index(&on_b), Some(0));
+        assert_eq!(
+            seq.mark_spans(),
+            [(0..1, marks([("bold", "true")])), (1..6, marks([]))]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_move_out_of_bounds_panics() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        seq.move_range(0..1, 4);
+    }
+
+    #[test]
+    fn test_concurrent_moves() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "milk eggs bread ".chars());
+        let mut bob = alice.clone();
+
+        // Both move "eggs " but to different places: one of them wins, whole.
+        alice.move_range(5..10, 0);
+        bob.move_range(5..10, 16);
+        bob.insert(0, '>');
+        alice.merge(&bob);
+        bob.merge(&alice);
+        assert_eq!(alice, bob);
+        assert_eq!(alice.to_string(), bob.to_string());
+        assert!(["eggs >milk bread ", ">milk bread eggs "].contains(&alice.to_string().as_str()));
+
+        // A later move wins over both.
+        alice.move_range(0..alice.len(), 0);
+        alice.move_range(1..6, 17);
+        bob.merge(&alice);
+        assert_eq!(bob.to_string(), alice.to_string());
+        assert_eq!(bob.len(), 17);
+    }
+
+    #[test]
+    fn test_move_clocks_are_bounded() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        let ids: Vec<Id> = seq.iter_ids().copied().collect();
+        let move_a = |clock| HashNode {
+            extra_dependencies: BTreeSet::new(),
+            op: Op::Move {
+                anchor: ids[2],
+                before: false,
+                element: ids[0],
+                clock,
+            },
+        };
+
+        for clock in [2, u64::MAX] {
+            assert_eq!(
+                seq.try_apply(move_a(clock)),
+                ApplyOutcome::Rejected(RejectReason::ClockTooHigh { clock, max: 1 })
+            );
+        }
+        assert_eq!(seq.to_string(), "abc");
+
+        // Our own moves still go one past everything we've seen.
+        let local = seq.move_range(0..1, 3);
+        assert!(matches!(
+            &local[..],
+            [EncodableOp::Node(HashNode {
+                op: Op::Move { clock: 1, .. },
+                ..
+            })]
+        ));
+        assert_eq!(seq.to_string(), "bca");
+        assert!(matches!(
+            seq.try_apply(HashNode {
+                extra_dependencies: seq.tips().clone(),
+                ..move_a(2)
+            }),
+            ApplyOutcome::Applied { .. }
+        ));
+    }
+
+    #[test]
+    fn test_move_concurrent_with_edits() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "abcdef".chars());
+        let mut bob = alice.clone();
+
+        alice.move_range(0..3, 6);
+        bob.remove(1);
+        bob.insert(2, 'x');
+        bob.insert(6, 'y');
+        alice.merge(&bob);
+        bob.merge(&alice);
+        assert_eq!(alice, bob);
+        // The removed element stays removed, the insert in the range stays
+        // behind, and the ids decide the order of the moved text and the
+        // concurrent insert at the end.
+        assert_eq!(alice.to_string(), "xdefacy");
+        assert_eq!(bob.to_string(), "xdefacy");
+    }
+
+    #[quickcheck]
+    fn prop_move_vec_model(base: String, moves: Vec<(u8, u8, u8)>) {
+        let mut model: Vec<char> = base.chars().collect();
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, base.chars());
+        let mut replica = seq.clone();
+
+        for (start, len, to) in moves {
+            let start = start as usize % (model.len() + 1);
+            let end = (start + len as usize % 4).min(model.len());
+            let to = to as usize % (model.len() + 1);
+            for op in seq.move_range(start..end, to) {
+                replica.apply_op(op);
+            }
+            let moved: Vec<_> = model.drain(start..end).collect();
+            let to = if to > end {
+                to - moved.len()
+            } else {
+                to.min(start)
+            };
+            model.splice(to..to, moved);
+        }
+
+        assert_eq!(seq.iter().collect::<Vec<_>>(), model);
+        assert_eq!(replica, seq);
+        assert_eq!(replica.iter().collect::<Vec<_>>(), model);
+        assert!(seq.iter_ids().eq(seq.index.iter().map(|(id, _)| id)));
+    }
+
+    #[quickcheck]
+    fn prop_moves_commutative(
+        base: String,
+        a: Vec<(u8, u8, u8, char)>,
+        b: Vec<(u8, u8, u8, char)>,
+    ) -> bool {
+        fn edit(seq: &mut HashSeq, edits: &[(u8, u8, u8, char)]) {
+            for &(kind, x, y, ch) in edits {
+                let (x, y) = (x as usize, y as usize);
+                match kind % 3 {
+                    0 => {
+                        seq.insert(x.min(seq.len()), ch);
+                    }
+                    1 if !seq.is_empty() => {
+                        seq.remove(x.min(seq.len() - 1));
+                    }
+                    _ => {
+                        let start = x.min(seq.len());
+                        let end = (start + kind as usize % 5).min(seq.len());
+                        seq.move_range(start..end, y.min(seq.len()));
+                    }
+                }
+            }
+        }
+
+        let mut seq_a = HashSeq::default();
+        seq_a.insert_batch(0, base.chars());
+        let mut seq_b = seq_a.clone();
+        edit(&mut seq_a, &a);
+        edit(&mut seq_b, &b);
+
+        let mut merge_a_b = seq_a.clone();
+        merge_a_b.merge(&seq_b);
+        let mut merge_b_a = seq_b.clone();
+        merge_b_a.merge(&seq_a);
+
+        // Every element shows up once, wherever it ended up.
+        let elements: BTreeSet<Id> = merge_a_b
+            .iter_ids()
+            .map(|id| *merge_a_b.element_of(id))
+            .collect();
+        merge_a_b == merge_b_a
+            && merge_a_b.to_string() == merge_b_a.to_string()
+            && merge_a_b.iter_ids().eq(merge_b_a.iter_ids())
+            && merge_a_b
+                .iter_ids()
+                .eq(merge_a_b.index.iter().map(|(id, _)| id))
+            && elements.len() == merge_a_b.len()
+    }
+
+    #[test]
+    fn test_remove_range_intent() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello brave new world".chars());
+        let mut replica = seq.clone();
+
+        let ops = seq.remove_range_intent(6, 10);
+        assert_eq!(ops.len(), 1);
+        assert_eq!(seq.to_string(), "hello world");
+        // The range remove has no position of its own.
+        let range = ops[0].first_id();
+        assert!(seq.iter_from_id(&range).is_none());
+        let cursor = Cursor {
+            anchor: Some(range),
+            gravity: Gravity::Right,
+        };
+        assert_eq!(seq.cursor_index(&cursor), None);
+        for op in ops {
+            replica.apply_op(op);
+        }
+        assert_eq!(replica, seq);
+        assert_eq!(replica.to_string(), "hello world");
+        assert!(seq.remove_range_intent(11, 3).is_empty());
+        assert!(seq.remove_range_intent(0, 0).is_empty());
+
+        // Typing into the gap afterwards sticks.
+        seq.insert_batch(6, "big ".chars());
+        seq.remove_range_intent(4, 1);
+        assert_eq!(seq.to_string(), "hell big world");
+        assert!(seq.iter_ids().eq(seq.index.iter().map(|(id, _)| id)));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an insert")]
+    fn test_insert_after_range_remove_panics() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abcdef".chars());
+        let range = seq.remove_range_intent(1, 2)[0].first_id();
+        seq.insert_after_id(&range, "XY".chars());
+    }
+
+    #[test]
+    fn test_reversed_range_remove_removes_nothing() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abcdef".chars());
+        let ids: Vec<Id> = seq.iter_ids().copied().collect();
+        seq.remove_batch(2, 2);
+        let range = |start: usize, end: usize| HashNode {
+            extra_dependencies: BTreeSet::new(),
+            op: Op::RemoveRange {
+                start: ids[start],
+                end: ids[end],
+            },
+        };
+
+        // Across visible elements, and between two tombstones in the same gap.
+        seq.apply(range(4, 1));
+        seq.apply(range(3, 2));
+        assert_eq!(seq.to_string(), "abef");
+
+        seq.apply(range(1, 4));
+        assert_eq!(seq.to_string(), "af");
+    }
+
+    #[test]
+    fn test_range_remove_takes_concurrent_inserts() {
+        let mut alice = HashSeq::default();
+        alice.insert_batch(0, "Keep this. Drop this sentence. Keep that.".chars());
+        let mut bob = alice.clone();
+        let mut carol = alice.clone();
+
+        alice.remove_range_intent(11, 20);
+        assert_eq!(alice.to_string(), "Keep this. Keep that.");
+        // Bob types in the middle of the sentence, at its edges and outside it.
+        bob.insert_batch(31, ">".chars());
+        bob.insert_batch(15, " all of".chars());
+        bob.insert_batch(11, "<".chars());
+        bob.insert_batch(0, "> ".chars());
+        assert_eq!(
+            bob.to_string(),
+            "> Keep this. <Drop all of this sentence. >Keep that."
+        );
+        // Carol removes the sentence by id instead.
+        carol.remove_batch(11, 20);
+        carol.merge(&bob);
+        assert_eq!(carol.to_string(), "> Keep this. < all of>Keep that.");
+
+        alice.merge(&bob);
+        bob.merge(&alice);
+        assert_eq!(alice, bob);
+        assert_eq!(alice.to_string(), "> Keep this. <>Keep that.");
+        assert_eq!(bob.to_string(), alice.to_string());
+
+        // A concurrent move into the range is removed too.
+        let mut dave = HashSeq::default();
+        dave.insert_batch(0, "abcdef".chars());
+        let mut erin = dave.clone();
+        dave.remove_range_intent(1, 3);
+        erin.move_range(5..6, 2);
+        dave.merge(&erin);
+        erin.merge(&dave);
+        assert_eq!(dave.to_string(), "ae");
+        assert_eq!(erin.to_string(), "ae");
+    }
+
+    #[quickcheck]
+    fn prop_remove_range_intent_vec_model(edits: Vec<(bool, u8, u8, char)>) {
+        let mut model: Vec<char> = Vec::new();
+        let mut seq = HashSeq::default();
+        let mut replica = HashSeq::default();
+
+        for (insert_or_remove, idx, len, ch) in edits {
+            let idx = idx as usize % (model.len() + 1);
+            let ops = if insert_or_remove {
+                model.insert(idx, ch);
+                seq.insert(idx, ch)
+            } else {
+                let end = (idx + len as usize % 5).min(model.len());
+                model.drain(idx..end);
+                seq.remove_range_intent(idx, end - idx)
+            };
+            for op in ops {
+                replica.apply_op(op);
+            }
+        }
+
+        assert_eq!(seq.iter().collect::<Vec<_>>(), model);
+        assert_eq!(replica, seq);
+        assert_eq!(replica.iter().collect::<Vec<_>>(), model);
+    }
+
+    #[quickcheck]
+    fn prop_range_removes_commutative(
+        base: String,
+        a: Vec<(u8, u8, u8, char)>,
+        b: Vec<(u8, u8, u8, char)>,
+    ) -> bool {
+        fn edit(seq: &mut HashSeq, edits: &[(u8, u8, u8, char)]) {
+            for &(kind, x, y, ch) in edits {
+                let (x, y) = (x as usize, y as usize);
+                let start = x.min(seq.len());
+                let amount = y % 5;
+                match kind % 4 {
+                    0 => {
+                        seq.insert(start, ch);
+                    }
+                    1 => {
+                        seq.remove_batch(start, amount);
+                    }
+                    2 => {
+                        seq.remove_range_intent(start, amount);
+                    }
+                    _ => {
+                        let end = (start + amount).min(seq.len());
+                        seq.move_range(start..end, (kind as usize * 7).min(seq.len()));
+                    }
+                }
+            }
+        }
+
+        let mut seq_a = HashSeq::default();
+        seq_a.insert_batch(0, base.chars());
+        let mut seq_b = seq_a.clone();
+        edit(&mut seq_a, &a);
+        edit(&mut seq_b, &b);
+
+        let mut merge_a_b = seq_a.clone();
+        merge_a_b.merge(&seq_b);
+        let mut merge_b_a = seq_b.clone();
+        merge_b_a.merge(&seq_a);
+
+        merge_a_b == merge_b_a
+            && merge_a_b.to_string() == merge_b_a.to_string()
+            && merge_a_b.iter_ids().eq(merge_b_a.iter_ids())
+            && merge_a_b
+                .iter_ids()
+                .eq(merge_a_b.index.iter().map(|(id, _)| id))
+    }
+
+    #[quickcheck]
+    fn prop_rolled_back_transactions_leave_no_trace(
+        base: String,
+        a: Vec<(u8, u8, u8, char)>,
+        b: Vec<(u8, u8, u8, char)>,
+    ) -> bool {
+        fn edit(tx: &mut Transaction<'_>, edits: &[(u8, u8, u8, char)]) {
+            for &(kind, x, y, ch) in edits {
+                let (x, y) = (x as usize, y as usize);
+                let start = x.min(tx.len());
+                let end = (start + y % 5).min(tx.len());
+                match kind % 5 {
+                    0 => tx.insert_batch(start, [ch, ch]),
+                    1 => tx.remove_batch(start, end - start),
+                    2 => tx.remove_range_intent(start, end - start),
+                    3 => tx.move_range(start..end, (kind as usize * 7).min(tx.len())),
+                    _ => tx.add_mark(start..end, "b", &ch.to_string(), Expand::End),
+                }
+            }
+        }
+
+        let mut seq = HashSeq::default();
+        seq.set_record_changes(true);
+        seq.insert_batch(0, base.chars());
+        seq.transaction(|tx| {
+            edit(tx, &a);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        let before = seq.clone();
+        let result = seq.transaction(|tx| {
+            edit(tx, &b);
+            Err(())
+        });
+
+        result.is_err()
+            && seq.runs == before.runs
+            && seq.root_nodes == before.root_nodes
+            && seq.before_nodes == before.before_nodes
+            && seq.befores_by_anchor == before.befores_by_anchor
+            && seq.remove_nodes == before.remove_nodes
+            && seq.remove_range_nodes == before.remove_range_nodes
+            && seq.mark_nodes == before.mark_nodes
+            && seq.move_nodes == before.move_nodes
+            && seq.run_index == before.run_index
+            && seq.afters == before.afters
+            && seq.removed_inserts == before.removed_inserts
+            && seq.tips == before.tips
+            && seq.mark_clock == before.mark_clock
+            && seq.clocks == before.clocks
+            && seq.moves_by_element == before.moves_by_element
+            && seq.vacated == before.vacated
+            && seq.move_clock == before.move_clock
+            && seq.spanned_by == before.spanned_by
+            && seq.range_removed == before.range_removed
+            && seq.index.iter().eq(before.index.iter())
+            && seq.utf16_len() == before.utf16_len()
+            && seq.take_changes() == before.clone().take_changes()
+    }
 }
@@ -1,13 +1,16 @@
-use crate::{hashseq::HashSeq, Id};
+use std::borrow::Cow;
+use std::iter::Peekable;
+
+use crate::{hashseq::HashSeq, weighted_list, Element, Id};
 
 #[derive(Debug, Clone)]
-pub struct HashSeqIter<'a> {
-    seq: &'a HashSeq,
+pub struct HashSeqIter<'a, T: Element = char> {
+    seq: &'a HashSeq<T>,
     waiting_stack: Vec<(Id, Vec<Id>)>,
 }
 
-impl<'a> HashSeqIter<'a> {
-    pub(crate) fn new(seq: &'a HashSeq) -> Self {
+impl<'a, T: Element> HashSeqIter<'a, T> {
+    pub(crate) fn new(seq: &'a HashSeq<T>) -> Self {
         let mut iter = Self {
             seq,
             waiting_stack: Vec::new(),
@@ -29,7 +32,7 @@ impl<'a> HashSeqIter<'a> {
     }
 }
 
-impl<'a> Iterator for HashSeqIter<'a> {
+impl<'a, T: Element> Iterator for HashSeqIter<'a, T> {
     type Item = &'a Id;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -62,7 +65,7 @@ impl<'a> Iterator for HashSeqIter<'a> {
                     }
                 }
                 // Return reference from existing data structures
-                if !self.seq.removed_inserts.contains(&n)
+                if self.seq.is_visible(&n)
                     && let Some(id_ref) = self.seq.get_id_ref(&n)
                 {
                     return Some(id_ref);
@@ -71,3 +74,96 @@ impl<'a> Iterator for HashSeqIter<'a> {
         }
     }
 }
+
+/// A stretch of consecutive visible characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    /// The id of the first character.
+    pub id: Id,
+    /// The char index of the first character.
+    pub index: usize,
+    /// Borrowed from a run; owned for the odd root or before node, which
+    /// store their character on its own.
+    pub text: Cow<'a, str>,
+}
+
+/// Iterates over the visible text a run at a time, see [`HashSeq::chunks`].
+#[derive(Debug, Clone)]
+pub struct Chunks<'a> {
+    seq: &'a HashSeq,
+    entries: Peekable<weighted_list::Iter<'a, char>>,
+    index: usize,
+    // The last run we sliced, with a char position in it and its byte offset,
+    // so a run split up by tombstones isn't rescanned from the start for each piece.
+    cursor: Option<(Id, usize, usize)>,
+}
+
+impl<'a> Chunks<'a> {
+    pub(crate) fn new(seq: &'a HashSeq) -> Self {
+        Self {
+            seq,
+            entries: seq.index.iter().peekable(),
+            index: 0,
+            cursor: None,
+        }
+    }
+
+    fn byte_offset(&mut self, run_id: Id, run: &str, position: usize) -> usize {
+        let (from_char, from_byte) = match self.cursor {
+            Some((id, char_pos, byte_pos)) if id == run_id && char_pos <= position => {
+                (char_pos, byte_pos)
+            }
+            _ => (0, 0),
+        };
+        let byte_pos = run[from_byte..]
+            .char_indices()
+            .nth(position - from_char)
+            .map_or(run.len(), |(i, _)| from_byte + i);
+        self.cursor = Some((run_id, position, byte_pos));
+        byte_pos
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Chunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&id, &ch) = self.entries.next()?;
+        let index = self.index;
+
+        let Some(run_pos) = self.seq.run_index.get(&id) else {
+            self.index += 1;
+            return Some(Chunk {
+                id,
+                index,
+                text: Cow::Owned(ch.to_string()),
+            });
+        };
+
+        // Take elements of the run for as long as they're next in the text.
+        let run = &self.seq.runs[&run_pos.run_id];
+        let mut end = run_pos.position + 1;
+        let mut byte_len = ch.len_utf8();
+        while let Some(elem) = run.elements.get(end)
+            && let Some(&(next, &ch)) = self.entries.peek()
+            && next == elem
+        {
+            self.entries.next();
+            end += 1;
+            byte_len += ch.len_utf8();
+        }
+        self.index += end - run_pos.position;
+
+        let start = self.byte_offset(run_pos.run_id, &run.run, run_pos.position);
+        Some(Chunk {
+            id,
+            index,
+            text: Cow::Borrowed(&run.run[start..start + byte_len]),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, upper) = self.entries.size_hint();
+        (usize::from(upper != Some(0)), upper)
+    }
+}
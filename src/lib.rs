@@ -1,17 +1,42 @@
+pub mod annotations;
+pub mod bloom;
+pub mod changes;
+pub mod cursor;
+pub mod element;
 pub mod encoding;
 pub mod hash_node;
 pub mod hashseq;
 pub mod hashseq_iter;
+pub mod orphans;
+pub mod reconcile;
 pub mod run;
+pub mod sync;
+pub mod transaction;
+pub mod validation;
 pub mod wasm;
+pub mod weighted_list;
 
+pub use self::annotations::{Annotation, Annotations, Expand};
+pub use self::bloom::BloomFilter;
+pub use self::changes::Change;
+pub use self::cursor::{Cursor, Gravity};
+pub use self::element::{Element, RunStorage};
 pub use self::encoding::{
-    decode_batch, decode_hashseq, encode_batch, encode_hashseq, DecodeError, EncodableOp,
+    decode_batch, decode_bloom_filter, decode_cursor, decode_delta, decode_hashseq,
+    decode_reconcile_message, decode_sync_message, encode_batch, encode_bloom_filter,
+    encode_cursor, encode_delta, encode_hashseq, encode_reconcile_message, encode_sync_message,
+    DecodeError, EncodableOp, EncodeElement,
 };
 pub use self::hash_node::{HashNode, Op};
-pub use self::hashseq::{HashSeq, RunPosition};
-pub use self::hashseq_iter::HashSeqIter;
+pub use self::hashseq::{ApplyOutcome, HashSeq, RejectReason, RunPosition};
+pub use self::hashseq_iter::{Chunk, Chunks, HashSeqIter};
+pub use self::orphans::{DropReason, DroppedOrphan, Eviction, OrphanBuffer, OrphanPolicy};
+pub use self::reconcile::{RangeReconciler, ReconcileMessage};
 pub use self::run::Run;
+pub use self::sync::{SyncMessage, SyncSession};
+pub use self::transaction::Transaction;
+pub use self::validation::{DefaultPolicy, PermissivePolicy, ValidationPolicy};
+pub use self::weighted_list::{Weight, WeightedList};
 
 #[derive(
     Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
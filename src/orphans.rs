@@ -0,0 +1,510 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::hashseq::IdMap;
+use crate::{Element, HashNode, Id, Op};
+
+/// Which orphans to drop first when the buffer is over its limits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    /// Drop the orphans that have been waiting the longest.
+    #[default]
+    OldestFirst,
+    /// Drop the orphans missing the most dependencies, oldest first among equals.
+    /// These are the least likely to ever be applied.
+    LargestMissingFirst,
+}
+
+/// Limits on the orphan buffer. The default is unbounded; replicas talking to
+/// untrusted peers should set limits, since a peer can send any number of
+/// nodes whose dependencies never arrive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OrphanPolicy {
+    pub max_count: Option<usize>,
+    /// Limit on the approximate memory held by orphans.
+    pub max_bytes: Option<usize>,
+    /// Orphans with more extra dependencies than this are dropped on arrival.
+    pub max_extra_dependencies: Option<usize>,
+    pub eviction: Eviction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Evicted to keep the buffer within `max_count` or `max_bytes`.
+    Evicted,
+    /// Had more than `max_extra_dependencies` extra dependencies.
+    TooManyDependencies,
+}
+
+/// An orphan the policy dropped. It can still be applied if it's sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedOrphan {
+    pub id: Id,
+    pub reason: DropReason,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<T> {
+    node: HashNode<T>,
+    arrival: u64,
+    missing: usize,
+    bytes: usize,
+    waiting_on: Id,
+}
+
+/// Nodes that can't be applied until a dependency arrives.
+///
+/// Each orphan is indexed under one dependency it is still missing, so
+/// applying a node only wakes the orphans waiting on it. A woken orphan that is
+/// still missing something else is filed under that dependency instead.
+#[derive(Debug, Clone)]
+pub struct OrphanBuffer<T = char> {
+    policy: OrphanPolicy,
+    // Keyed by the orphan's own id, which we computed, so FxHash is safe here.
+    entries: IdMap<Entry<T>>,
+    // missing dependency -> orphans waiting on it, in arrival order. Keyed by
+    // ids a peer chose rather than ones we hashed, so keep std HashMap: the
+    // input is adversary-controllable and benefits from SipHash's HashDoS protection.
+    waiting_on: HashMap<Id, BTreeSet<(u64, Id)>>,
+    // Next orphan to evict first.
+    eviction_order: BTreeSet<(Reverse<usize>, u64, Id)>,
+    next_arrival: u64,
+    bytes: usize,
+    dropped: Vec<DroppedOrphan>,
+}
+
+impl<T> Default for OrphanBuffer<T> {
+    fn default() -> Self {
+        Self {
+            policy: OrphanPolicy::default(),
+            entries: IdMap::default(),
+            waiting_on: HashMap::new(),
+            eviction_order: BTreeSet::new(),
+            next_arrival: 0,
+            bytes: 0,
+            dropped: Vec::new(),
+        }
+    }
+}
+
+impl<T: Element> OrphanBuffer<T> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Approximate memory held by the buffered orphans.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn contains(&self, id: &Id) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    pub fn get(&self, id: &Id) -> Option<&HashNode<T>> {
+        self.entries.get(id).map(|entry| &entry.node)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HashNode<T>> + '_ {
+        self.entries.values().map(|entry| &entry.node)
+    }
+
+    pub fn policy(&self) -> &OrphanPolicy {
+        &self.policy
+    }
+
+    /// Orphans dropped by the policy since the last call to `take_dropped`.
+    pub fn dropped(&self) -> &[DroppedOrphan] {
+        &self.dropped
+    }
+
+    pub(crate) fn take_dropped(&mut self) -> Vec<DroppedOrphan> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Switch policies, dropping whatever the new one doesn't allow.
+    pub(crate) fn set_policy(&mut self, policy: OrphanPolicy) {
+        self.policy = policy;
+        self.eviction_order = self
+            .entries
+            .iter()
+            .map(|(id, entry)| self.eviction_key(*id, entry))
+            .collect();
+
+        if let Some(max) = policy.max_extra_dependencies {
+            let mut too_many: Vec<Id> = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.node.extra_dependencies.len() > max)
+                .map(|(id, _)| *id)
+                .collect();
+            too_many.sort_unstable();
+            for id in too_many {
+                self.remove(&id);
+                self.dropped.push(DroppedOrphan {
+                    id,
+                    reason: DropReason::TooManyDependencies,
+                });
+            }
+        }
+        self.enforce_limits();
+    }
+
+    /// Buffer `node` until its `missing` dependencies arrive.
+    pub(crate) fn insert(&mut self, id: Id, node: HashNode<T>, missing: &[Id]) {
+        if self.entries.contains_key(&id) {
+            return;
+        }
+        if self
+            .policy
+            .max_extra_dependencies
+            .is_some_and(|max| node.extra_dependencies.len() > max)
+        {
+            self.dropped.push(DroppedOrphan {
+                id,
+                reason: DropReason::TooManyDependencies,
+            });
+            return;
+        }
+
+        let entry = Entry {
+            bytes: approximate_size(&node),
+            node,
+            arrival: self.next_arrival,
+            missing: missing.len(),
+            waiting_on: missing[0],
+        };
+        self.next_arrival += 1;
+        self.bytes += entry.bytes;
+        self.waiting_on
+            .entry(entry.waiting_on)
+            .or_default()
+            .insert((entry.arrival, id));
+        self.eviction_order.insert(self.eviction_key(id, &entry));
+        self.entries.insert(id, entry);
+
+        self.enforce_limits();
+    }
+
+    /// File an orphan that is still `missing` dependencies under the first of them.
+    pub(crate) fn refile(&mut self, id: Id, missing: &[Id]) {
+        let Some(mut entry) = self.entries.remove(&id) else {
+            return;
+        };
+        self.unindex(id, &entry);
+        entry.missing = missing.len();
+        entry.waiting_on = missing[0];
+        self.waiting_on
+            .entry(entry.waiting_on)
+            .or_default()
+            .insert((entry.arrival, id));
+        self.eviction_order.insert(self.eviction_key(id, &entry));
+        self.entries.insert(id, entry);
+    }
+
+    pub(crate) fn remove(&mut self, id: &Id) -> Option<HashNode<T>> {
+        let entry = self.entries.remove(id)?;
+        self.unindex(*id, &entry);
+        self.bytes -= entry.bytes;
+        Some(entry.node)
+    }
+
+    /// The orphans waiting on `dep`, in the order they arrived. Each of them
+    /// must then be either `refile`d or `remove`d.
+    pub(crate) fn take_waiting_on(&mut self, dep: &Id) -> Vec<Id> {
+        self.waiting_on
+            .remove(dep)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    fn unindex(&mut self, id: Id, entry: &Entry<T>) {
+        if let Some(waiting) = self.waiting_on.get_mut(&entry.waiting_on) {
+            waiting.remove(&(entry.arrival, id));
+            if waiting.is_empty() {
+                self.waiting_on.remove(&entry.waiting_on);
+            }
+        }
+        self.eviction_order.remove(&self.eviction_key(id, entry));
+    }
+
+    fn eviction_key(&self, id: Id, entry: &Entry<T>) -> (Reverse<usize>, u64, Id) {
+        match self.policy.eviction {
+            Eviction::OldestFirst => (Reverse(0), entry.arrival, id),
+            Eviction::LargestMissingFirst => (Reverse(entry.missing), entry.arrival, id),
+        }
+    }
+
+    fn enforce_limits(&mut self) {
+        let over_limit = |buffer: &Self| {
+            buffer
+                .policy
+                .max_count
+                .is_some_and(|max| buffer.len() > max)
+                || buffer
+                    .policy
+                    .max_bytes
+                    .is_some_and(|max| buffer.bytes > max)
+        };
+        while over_limit(self) {
+            let Some(&(_, _, id)) = self.eviction_order.first() else {
+                break;
+            };
+            self.remove(&id);
+            self.dropped.push(DroppedOrphan {
+                id,
+                reason: DropReason::Evicted,
+            });
+        }
+    }
+}
+
+fn approximate_size<T>(node: &HashNode<T>) -> usize {
+    let (removed, strings) = match &node.op {
+        Op::Remove(ids) => (ids.len(), 0),
+        Op::AddMark { name, value, .. } => (0, name.len() + value.len()),
+        Op::RemoveMark { name, .. } => (0, name.len()),
+        _ => (0, 0),
+    };
+    std::mem::size_of::<Entry<T>>()
+        + (node.extra_dependencies.len() + removed) * std::mem::size_of::<Id>()
+        + strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApplyOutcome, EncodableOp, HashSeq, decode_hashseq, encode_hashseq};
+
+    fn nodes(seq: &HashSeq) -> Vec<HashNode> {
+        seq.ops_since(&BTreeSet::new())
+            .into_iter()
+            .flat_map(|op| match op {
+                EncodableOp::Run(run) => run.decompress(),
+                EncodableOp::Node(node) => vec![node],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_orphan_is_refiled_under_its_next_missing_dependency() {
+        // Two concurrent roots and a remove of both.
+        let mut source = HashSeq::default();
+        source.insert(0, 'a');
+        let mut other = HashSeq::default();
+        other.insert(0, 'b');
+        source.merge(&other);
+        let EncodableOp::Node(remove) = source.remove_batch(0, 2).remove(0) else {
+            panic!("expected a remove node");
+        };
+        let root = |id: Id| nodes(&source).into_iter().find(|n| n.id() == id).unwrap();
+
+        let mut seq = HashSeq::default();
+        let ApplyOutcome::Orphaned { missing } = seq.try_apply(remove.clone()) else {
+            panic!("both roots are missing");
+        };
+        assert_eq!(missing.len(), 2);
+
+        // Waking the remove on its first missing root files it under the second.
+        assert_eq!(
+            seq.try_apply(root(missing[0])),
+            ApplyOutcome::Applied {
+                unorphaned: vec![],
+                rejected: vec![]
+            }
+        );
+        assert_eq!(seq.orphans().len(), 1);
+        assert_eq!(
+            seq.try_apply(root(missing[1])),
+            ApplyOutcome::Applied {
+                unorphaned: vec![remove.id()],
+                rejected: vec![]
+            }
+        );
+        assert!(seq.orphans().is_empty());
+        assert_eq!(seq.len(), 0);
+    }
+
+    #[test]
+    fn test_reversed_delivery_cascades() {
+        let mut source = HashSeq::default();
+        for i in 0..500 {
+            source.insert(i / 3, 'x');
+            if i % 7 == 0 {
+                source.remove(i / 5);
+            }
+        }
+
+        let mut seq = HashSeq::default();
+        let nodes = nodes(&source);
+        let (last, rest) = nodes.split_first().unwrap();
+        for node in rest.iter().rev() {
+            assert!(matches!(
+                seq.try_apply(node.clone()),
+                ApplyOutcome::Orphaned { .. }
+            ));
+        }
+        assert_eq!(seq.orphans().len(), rest.len());
+
+        let ApplyOutcome::Applied { unorphaned, .. } = seq.try_apply(last.clone()) else {
+            panic!("the first node has no dependencies");
+        };
+        assert_eq!(unorphaned.len(), rest.len());
+        assert!(seq.orphans().is_empty());
+        assert_eq!(seq, source);
+        assert_eq!(
+            seq.iter().collect::<String>(),
+            source.iter().collect::<String>()
+        );
+    }
+
+    /// A node waiting on `missing` fake dependencies that will never arrive.
+    fn orphan(tag: u8, missing: u8) -> HashNode {
+        HashNode {
+            extra_dependencies: (1..missing).map(|i| Id([i; 32])).collect(),
+            op: Op::InsertAfter(Id([tag; 32]), char::from(tag)),
+        }
+    }
+
+    #[test]
+    fn test_oldest_orphans_are_evicted_first() {
+        let mut seq = HashSeq::default();
+        seq.set_orphan_policy(OrphanPolicy {
+            max_count: Some(2),
+            ..Default::default()
+        });
+
+        let nodes: Vec<HashNode> = (100..104).map(|tag| orphan(tag, 1)).collect();
+        for node in &nodes {
+            seq.apply(node.clone());
+        }
+
+        assert_eq!(seq.orphans().len(), 2);
+        assert!(seq.orphans().contains(&nodes[2].id()));
+        assert!(seq.orphans().contains(&nodes[3].id()));
+        assert_eq!(
+            seq.take_dropped_orphans(),
+            vec![
+                DroppedOrphan {
+                    id: nodes[0].id(),
+                    reason: DropReason::Evicted
+                },
+                DroppedOrphan {
+                    id: nodes[1].id(),
+                    reason: DropReason::Evicted
+                },
+            ]
+        );
+        assert!(seq.take_dropped_orphans().is_empty());
+    }
+
+    #[test]
+    fn test_largest_missing_sets_are_evicted_first() {
+        let mut seq = HashSeq::default();
+        for (tag, missing) in [(100, 3), (101, 1), (102, 5), (103, 1)] {
+            seq.apply(orphan(tag, missing));
+        }
+
+        // Tightening the policy evicts right away.
+        seq.set_orphan_policy(OrphanPolicy {
+            max_count: Some(2),
+            eviction: Eviction::LargestMissingFirst,
+            ..Default::default()
+        });
+        let dropped: Vec<Id> = seq.take_dropped_orphans().iter().map(|d| d.id).collect();
+        assert_eq!(dropped, vec![orphan(102, 5).id(), orphan(100, 3).id()]);
+        assert!(seq.orphans().contains(&orphan(101, 1).id()));
+        assert!(seq.orphans().contains(&orphan(103, 1).id()));
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let mut seq = HashSeq::default();
+        seq.set_orphan_policy(OrphanPolicy {
+            max_bytes: Some(4096),
+            ..Default::default()
+        });
+        for tag in 0..=255 {
+            seq.apply(orphan(tag, 3));
+        }
+        assert!(seq.orphans().bytes() <= 4096);
+        assert!(!seq.orphans().is_empty());
+        assert_eq!(seq.orphans().len() + seq.take_dropped_orphans().len(), 256);
+    }
+
+    #[test]
+    fn test_fan_out_limit() {
+        let mut seq = HashSeq::default();
+        seq.set_orphan_policy(OrphanPolicy {
+            max_extra_dependencies: Some(2),
+            ..Default::default()
+        });
+
+        let wide = orphan(100, 4);
+        assert!(matches!(
+            seq.try_apply(wide.clone()),
+            ApplyOutcome::Orphaned { .. }
+        ));
+        assert!(seq.orphans().is_empty());
+        assert_eq!(
+            seq.take_dropped_orphans(),
+            vec![DroppedOrphan {
+                id: wide.id(),
+                reason: DropReason::TooManyDependencies
+            }]
+        );
+
+        seq.apply(orphan(101, 3));
+        assert_eq!(seq.orphans().len(), 1);
+    }
+
+    #[test]
+    fn test_evicted_orphans_can_be_resent() {
+        let mut source = HashSeq::default();
+        source.insert_batch(0, "abc".chars());
+        let nodes = nodes(&source);
+
+        let mut seq = HashSeq::default();
+        seq.set_orphan_policy(OrphanPolicy {
+            max_count: Some(1),
+            ..Default::default()
+        });
+        seq.apply(nodes[2].clone());
+        seq.apply(nodes[1].clone());
+        assert_eq!(seq.take_dropped_orphans().len(), 1);
+
+        // 'c' was evicted and 'b' waits on 'a', which was never sent, so the
+        // seq asks for 'a'.
+        assert_eq!(seq.missing_dependencies(), BTreeSet::from([nodes[0].id()]));
+        seq.apply(nodes[0].clone());
+        assert_eq!(seq.iter().collect::<String>(), "ab");
+        seq.apply(nodes[2].clone());
+        assert_eq!(seq, source);
+        assert_eq!(seq.iter().collect::<String>(), "abc");
+    }
+
+    #[test]
+    fn test_encoding_persists_only_remaining_orphans() {
+        let mut seq = HashSeq::default();
+        seq.set_orphan_policy(OrphanPolicy {
+            max_count: Some(3),
+            ..Default::default()
+        });
+        for tag in 100..110 {
+            seq.apply(orphan(tag, 2));
+        }
+
+        let decoded: HashSeq = decode_hashseq(&encode_hashseq(&seq)).unwrap();
+        let mut remaining: Vec<Id> = decoded.orphans().iter().map(HashNode::id).collect();
+        remaining.sort();
+        let mut expected: Vec<Id> = (107..110).map(|tag| orphan(tag, 2).id()).collect();
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+}
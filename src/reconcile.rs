@@ -0,0 +1,306 @@
+// Range-based set reconciliation over the ids of a `HashSeq`.
+//
+// Each peer keeps its node ids sorted. A message splits the id space into
+// consecutive ranges, each carrying either a fingerprint of the ids in it or,
+// once a range is small enough, the ids themselves. Ranges whose fingerprints
+// match are dropped, mismatching ones are split further, so two peers find
+// their symmetric difference in a logarithmic number of round trips.
+//
+// Ids are uniform BLAKE3 hashes, so splitting at our own ids gives balanced
+// ranges and XOR makes a cheap fingerprint. XOR is linear though: a peer that
+// wants to hide ids from us can grind a set of nodes whose XOR cancels out.
+// That only stalls reconciliation with that peer, it never corrupts state.
+
+use std::collections::BTreeSet;
+
+use crate::{HashSeq, Id};
+
+/// Ranges with at most this many ids are sent as an id list.
+const ID_LIST_THRESHOLD: usize = 16;
+
+/// A mismatching range is split into this many sub-ranges.
+const BRANCHING_FACTOR: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub count: usize,
+    pub xor: [u8; 32],
+}
+
+impl Fingerprint {
+    pub fn of<'a>(ids: impl IntoIterator<Item = &'a Id>) -> Self {
+        let mut fp = Fingerprint::default();
+        for id in ids {
+            fp.count += 1;
+            for (acc, byte) in fp.xor.iter_mut().zip(id.0) {
+                *acc ^= byte;
+            }
+        }
+        fp
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeMode {
+    /// Both sides already agree on this range.
+    Skip,
+    Fingerprint(Fingerprint),
+    /// Every id the sender has in this range.
+    IdList(Vec<Id>),
+}
+
+/// A range of the id space. Its lower bound is the previous range's upper
+/// bound (or the smallest id for the first range).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    /// Exclusive upper bound, `None` meaning the end of the id space.
+    pub upper: Option<Id>,
+    pub mode: RangeMode,
+}
+
+/// One round of range reconciliation. The ranges cover the whole id space.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconcileMessage {
+    pub ranges: Vec<Range>,
+}
+
+/// Runs the reconciliation protocol against a snapshot of a `HashSeq`'s ids.
+///
+/// Either side may `initiate`; both then feed each other's messages to
+/// `reconcile` until it returns `None`. Whoever resolves a range learns the
+/// difference for it: `have` collects ids only we have (send them with
+/// `HashSeq::ops_for_ids`), `need` collects ids only the peer has (ask the
+/// peer for them).
+#[derive(Debug, Clone)]
+pub struct RangeReconciler {
+    ids: Vec<Id>,
+}
+
+impl RangeReconciler {
+    pub fn new(seq: &HashSeq) -> Self {
+        let mut ids: Vec<Id> = seq.node_ids().copied().collect();
+        ids.sort_unstable();
+        Self { ids }
+    }
+
+    pub fn initiate(&self) -> ReconcileMessage {
+        ReconcileMessage {
+            ranges: self.split(0, self.ids.len(), None),
+        }
+    }
+
+    /// Handle a message from the peer, returning the reply or `None` once
+    /// every range has been resolved.
+    pub fn reconcile(
+        &self,
+        msg: &ReconcileMessage,
+        have: &mut BTreeSet<Id>,
+        need: &mut BTreeSet<Id>,
+    ) -> Option<ReconcileMessage> {
+        let mut ranges: Vec<Range> = Vec::new();
+        let mut lower = 0;
+        for range in &msg.ranges {
+            let upper = match range.upper {
+                Some(bound) => lower + self.ids[lower..].partition_point(|id| *id < bound),
+                None => self.ids.len(),
+            };
+            let ours = &self.ids[lower..upper];
+
+            let reply = match &range.mode {
+                RangeMode::Skip => vec![],
+                RangeMode::Fingerprint(fp) if *fp == Fingerprint::of(ours) => vec![],
+                RangeMode::Fingerprint(fp) if fp.count == 0 || ours.len() <= ID_LIST_THRESHOLD => {
+                    vec![Range {
+                        upper: range.upper,
+                        mode: RangeMode::IdList(ours.to_vec()),
+                    }]
+                }
+                RangeMode::Fingerprint(_) => self.split(lower, upper, range.upper),
+                RangeMode::IdList(theirs) => {
+                    let theirs: BTreeSet<&Id> = theirs.iter().collect();
+                    have.extend(ours.iter().filter(|id| !theirs.contains(id)));
+                    need.extend(
+                        theirs
+                            .into_iter()
+                            .filter(|id| ours.binary_search(id).is_err()),
+                    );
+                    vec![]
+                }
+            };
+
+            if reply.is_empty() {
+                match ranges.last_mut() {
+                    Some(prev) if prev.mode == RangeMode::Skip => prev.upper = range.upper,
+                    _ => ranges.push(Range {
+                        upper: range.upper,
+                        mode: RangeMode::Skip,
+                    }),
+                }
+            } else {
+                ranges.extend(reply);
+            }
+            lower = upper;
+        }
+
+        if ranges.iter().all(|r| r.mode == RangeMode::Skip) {
+            None
+        } else {
+            Some(ReconcileMessage { ranges })
+        }
+    }
+
+    /// Fingerprint `ids[lower..upper]` (ending at `bound`) as up to
+    /// `BRANCHING_FACTOR` sub-ranges, or list the ids if there are few.
+    fn split(&self, lower: usize, upper: usize, bound: Option<Id>) -> Vec<Range> {
+        let ids = &self.ids[lower..upper];
+        if ids.len() <= ID_LIST_THRESHOLD {
+            return vec![Range {
+                upper: bound,
+                mode: RangeMode::IdList(ids.to_vec()),
+            }];
+        }
+
+        let chunk_size = ids.len().div_ceil(BRANCHING_FACTOR);
+        let chunks: Vec<&[Id]> = ids.chunks(chunk_size).collect();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| Range {
+                upper: chunks.get(i + 1).map(|next| next[0]).or(bound),
+                mode: RangeMode::Fingerprint(Fingerprint::of(*chunk)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_reconcile_message, encode_reconcile_message};
+    use quickcheck_macros::quickcheck;
+
+    struct Outcome {
+        rounds: usize,
+        bytes: usize,
+        a_have: BTreeSet<Id>,
+        a_need: BTreeSet<Id>,
+        b_have: BTreeSet<Id>,
+        b_need: BTreeSet<Id>,
+    }
+
+    fn run(a: &HashSeq, b: &HashSeq) -> Outcome {
+        let rec_a = RangeReconciler::new(a);
+        let rec_b = RangeReconciler::new(b);
+        let mut out = Outcome {
+            rounds: 0,
+            bytes: 0,
+            a_have: BTreeSet::new(),
+            a_need: BTreeSet::new(),
+            b_have: BTreeSet::new(),
+            b_need: BTreeSet::new(),
+        };
+
+        let mut msg = Some(rec_a.initiate());
+        let mut a_turn = false;
+        while let Some(m) = msg {
+            let bytes = encode_reconcile_message(&m);
+            out.bytes += bytes.len();
+            out.rounds += 1;
+            let m = decode_reconcile_message(&bytes).unwrap();
+            msg = if a_turn {
+                rec_a.reconcile(&m, &mut out.a_have, &mut out.a_need)
+            } else {
+                rec_b.reconcile(&m, &mut out.b_have, &mut out.b_need)
+            };
+            a_turn = !a_turn;
+        }
+        out
+    }
+
+    fn edit(seq: &mut HashSeq, ops: &[(bool, u8, char)]) {
+        for &(insert_or_remove, idx, elem) in ops {
+            let idx = idx as usize;
+            if insert_or_remove {
+                seq.insert(idx.min(seq.len()), elem);
+            } else if !seq.is_empty() {
+                seq.remove(idx.min(seq.len() - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_identical_replicas_need_one_round() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "identical".chars());
+
+        let out = run(&seq, &seq.clone());
+        assert_eq!(out.rounds, 1);
+        assert!(out.a_have.is_empty() && out.b_have.is_empty());
+    }
+
+    #[test]
+    fn test_small_difference_in_large_replicas() {
+        let mut a = HashSeq::default();
+        for i in 0..2000 {
+            a.insert(i / 2, char::from(b'a' + (i % 26) as u8));
+        }
+        let mut b = a.clone();
+        a.insert(10, 'X');
+        b.remove(1500);
+
+        let out = run(&a, &b);
+        // Initiate, two rounds of splitting and an id list, each way at most.
+        assert!(out.rounds <= 5, "took {} rounds", out.rounds);
+        assert!(out.bytes < 2000 * 32 / 4, "sent {} bytes", out.bytes);
+
+        let a_ids: BTreeSet<Id> = a.node_ids().copied().collect();
+        let b_ids: BTreeSet<Id> = b.node_ids().copied().collect();
+        let only_a: BTreeSet<Id> = a_ids.difference(&b_ids).copied().collect();
+        let only_b: BTreeSet<Id> = b_ids.difference(&a_ids).copied().collect();
+        assert_eq!(only_a.len(), 1);
+        assert_eq!(only_b.len(), 1);
+        assert_eq!(&out.a_have | &out.b_need, only_a);
+        assert_eq!(&out.b_have | &out.a_need, only_b);
+    }
+
+    #[quickcheck]
+    fn prop_reconcile_converges(
+        base: Vec<(bool, u8, char)>,
+        ops_a: Vec<(bool, u8, char)>,
+        ops_b: Vec<(bool, u8, char)>,
+    ) {
+        let mut a = HashSeq::default();
+        edit(&mut a, &base);
+        let mut b = a.clone();
+        edit(&mut a, &ops_a);
+        edit(&mut b, &ops_b);
+
+        let out = run(&a, &b);
+
+        let a_ids: BTreeSet<Id> = a.node_ids().copied().collect();
+        let b_ids: BTreeSet<Id> = b.node_ids().copied().collect();
+        assert_eq!(
+            &out.a_have | &out.b_need,
+            a_ids.difference(&b_ids).copied().collect()
+        );
+        assert_eq!(
+            &out.b_have | &out.a_need,
+            b_ids.difference(&a_ids).copied().collect()
+        );
+
+        // Swap exactly the missing nodes.
+        let to_b = a.ops_for_ids(&(&out.a_have | &out.b_need));
+        let to_a = b.ops_for_ids(&(&out.b_have | &out.a_need));
+        for op in to_a {
+            a.apply_op(op);
+        }
+        for op in to_b {
+            b.apply_op(op);
+        }
+
+        assert!(a.orphans().is_empty());
+        assert!(b.orphans().is_empty());
+        assert_eq!(a, b);
+        assert_eq!(a.iter().collect::<String>(), b.iter().collect::<String>());
+    }
+}
@@ -1,6 +1,8 @@
-use crate::{HashNode, Id, Op};
+use crate::element::RunStorage;
+use crate::{Element, HashNode, Id, Op};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
+use std::ops::Range;
 
 /// A run represents a sequence of consecutive characters that can be compressed
 /// together instead of storing each as an individual HashNode.
@@ -13,30 +15,30 @@ use std::collections::BTreeSet;
 /// - Subsequent elements are InsertAfter(previous_element, char)
 /// - Runs can never start with InsertRoot or InsertBefore
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Run {
+pub struct Run<T: Element = char> {
     /// The node that comes before this run (the anchor for the first character)
     pub insert_after: Id,
     /// Extra dependencies for the first element of the run
     /// This is needed to correctly reconstruct the node's hash when decompressing
     pub first_extra_deps: BTreeSet<Id>,
-    /// The string content of this run
-    pub run: String,
+    /// The content of this run, a `String` for runs of `char`
+    pub run: T::Run,
     /// Cached element IDs for O(1) lookup (avoids recomputing hashes)
     pub elements: Vec<Id>,
 }
 
-impl Run {
-    /// Create a new run from a string
-    pub fn new(insert_after: Id, first_extra_deps: BTreeSet<Id>, first: char) -> Self {
+impl<T: Element> Run<T> {
+    /// Create a new run holding a single element
+    pub fn new(insert_after: Id, first_extra_deps: BTreeSet<Id>, first: T) -> Self {
         let first_node = HashNode {
             extra_dependencies: first_extra_deps.clone(),
-            op: Op::InsertAfter(insert_after, first),
+            op: Op::InsertAfter(insert_after, first.clone()),
         };
         let first_id = first_node.id();
         Self {
             insert_after,
             first_extra_deps,
-            run: first.to_string(),
+            run: std::iter::once(first).collect(),
             elements: vec![first_id],
         }
     }
@@ -53,10 +55,10 @@ impl Run {
 
     /// Decompress the run into individual HashNodes
     /// This reconstructs the full node information for each character
-    pub fn decompress(&self) -> Vec<HashNode> {
-        let mut nodes = Vec::with_capacity(self.run.len());
+    pub fn decompress(&self) -> Vec<HashNode<T>> {
+        let mut nodes = Vec::with_capacity(self.len());
 
-        let mut chars = self.run.chars();
+        let mut chars = self.run.elems();
 
         let first = chars.next().unwrap(); // we always have at least one char in the run
         nodes.push(HashNode {
@@ -74,8 +76,30 @@ impl Run {
         nodes
     }
 
-    pub fn first_node(&self) -> HashNode {
-        let mut chars = self.run.chars();
+    /// Recompute the id of every element and check it against `elements`.
+    /// Costs a hash per character, which is what caching the ids avoids.
+    pub fn verify_ids(&self) -> bool {
+        let mut prev = None;
+        let mut ids = self.elements.iter();
+        for ch in self.run.elems() {
+            let node = match prev {
+                None => self.first_node(),
+                Some(prev) => HashNode {
+                    extra_dependencies: BTreeSet::new(),
+                    op: Op::InsertAfter(prev, ch),
+                },
+            };
+            let id = node.id();
+            if ids.next() != Some(&id) {
+                return false;
+            }
+            prev = Some(id);
+        }
+        prev.is_some() && ids.next().is_none()
+    }
+
+    pub fn first_node(&self) -> HashNode<T> {
+        let mut chars = self.run.elems();
         let first = chars.next().unwrap(); // we always have at least one char in the run
         HashNode {
             extra_dependencies: self.first_extra_deps.clone(),
@@ -105,11 +129,11 @@ impl Run {
 
     /// Extend this run by appending a character and return the new element's ID
     /// The new character will be InsertAfter(current_last_character, ch)
-    pub fn extend(&mut self, ch: char) -> Id {
+    pub fn extend(&mut self, ch: T) -> Id {
         let prev_id = *self.elements.last().unwrap();
         let new_node = HashNode {
             extra_dependencies: BTreeSet::new(),
-            op: Op::InsertAfter(prev_id, ch),
+            op: Op::InsertAfter(prev_id, ch.clone()),
         };
         let new_id = new_node.id();
         self.extend_with_id(new_id, ch);
@@ -117,17 +141,40 @@ impl Run {
     }
 
     /// Extend this run with a pre-computed ID (avoids hash computation)
-    pub fn extend_with_id(&mut self, id: Id, ch: char) {
+    pub fn extend_with_id(&mut self, id: Id, ch: T) {
         self.run.push(ch);
         self.elements.push(id);
     }
 
+    /// Copy elements `range` out into a standalone run.
+    ///
+    /// A slice starting past the first element is anchored on the element before it
+    /// and, like the right half of `split_at`, has no extra dependencies.
+    pub fn slice(&self, range: Range<usize>) -> Run<T> {
+        assert!(
+            range.start < range.end && range.end <= self.len(),
+            "Invalid slice range"
+        );
+
+        let (insert_after, first_extra_deps) = match range.start {
+            0 => (self.insert_after, self.first_extra_deps.clone()),
+            start => (self.elements[start - 1], BTreeSet::new()),
+        };
+
+        Run {
+            insert_after,
+            first_extra_deps,
+            run: self.run.slice(range.clone()),
+            elements: self.elements[range].to_vec(),
+        }
+    }
+
     /// Split this run at the given position, returning the right portion
     /// The left portion remains in self, the right portion is returned
     ///
     /// Example: run "abc" split at position 1 becomes "a" and "bc"
     /// The right run's insert_after becomes the ID of the last element of the left run
-    pub fn split_at(&mut self, position: usize) -> Run {
+    pub fn split_at(&mut self, position: usize) -> Run<T> {
         assert!(
             position > 0 && position < self.len(),
             "Invalid split position"
@@ -137,9 +184,7 @@ impl Run {
         let right_elements = self.elements.split_off(position);
         let right_insert_after = *self.elements.last().unwrap();
 
-        // Split the string - need to find byte position for char position
-        let byte_pos = self.run.char_indices().nth(position).unwrap().0;
-        let right_str = self.run.split_off(byte_pos);
+        let right_str = self.run.split_off(position);
 
         // Create the right run with pre-computed elements
         Run {
@@ -149,6 +194,21 @@ impl Run {
             elements: right_elements,
         }
     }
+
+    /// Drop every element past the first `len`, undoing extensions.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.elements.truncate(len);
+        self.run.split_off(len);
+    }
+
+    /// Join `right` back on, undoing `split_at`.
+    pub(crate) fn join(&mut self, right: Run<T>) {
+        debug_assert_eq!(right.insert_after, self.last_id());
+        for elem in right.run.elems() {
+            self.run.push(elem);
+        }
+        self.elements.extend(right.elements);
+    }
 }
 
 #[cfg(test)]
@@ -282,6 +342,43 @@ mod tests {
         assert_eq!(run.find_position(&test_id(99)), None);
     }
 
+    #[test]
+    fn test_verify_ids() {
+        let mut run = Run::new(test_id(0), BTreeSet::from([test_id(1)]), 'a');
+        run.extend('b');
+        run.extend('c');
+        assert!(run.verify_ids());
+        assert!(run.split_at(1).verify_ids());
+
+        run.elements[0] = test_id(2);
+        assert!(!run.verify_ids());
+
+        let mut short = Run::new(test_id(0), BTreeSet::new(), 'a');
+        short.run.push('b');
+        assert!(!short.verify_ids());
+    }
+
+    #[test]
+    fn test_slice() {
+        let mut deps = BTreeSet::new();
+        deps.insert(test_id(1));
+        let mut run = Run::new(test_id(0), deps.clone(), 'a');
+        run.extend('b');
+        run.extend('c');
+        let nodes = run.decompress();
+
+        let head = run.slice(0..2);
+        assert_eq!(head.run, "ab");
+        assert_eq!(head.first_extra_deps, deps);
+        assert_eq!(head.decompress(), nodes[..2]);
+
+        let tail = run.slice(1..3);
+        assert_eq!(tail.run, "bc");
+        assert_eq!(tail.insert_after, nodes[0].id());
+        assert!(tail.first_extra_deps.is_empty());
+        assert_eq!(tail.decompress(), nodes[1..]);
+    }
+
     #[quickcheck]
     fn prop_split_preserves_decompress(run: Run, idx: usize) -> bool {
         // split_at requires: 0 < position < len
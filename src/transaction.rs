@@ -0,0 +1,340 @@
+use std::collections::BTreeSet;
+use std::ops::{Deref, Range};
+
+use crate::changes::ChangeLogMark;
+use crate::hashseq::IdSet;
+use crate::{Element, EncodableOp, Expand, HashSeq, Id, Weight};
+
+/// Local edits grouped by `HashSeq::transaction`.
+///
+/// Edits apply as they're made, so reads through the transaction see them.
+/// Every op depends on the ones made before it, so a peer that is missing
+/// anything the first op needs holds the whole batch in its orphan buffer
+/// and applies it in one go.
+#[derive(Debug)]
+pub struct Transaction<'a, T: Element = char> {
+    seq: &'a mut HashSeq<T>,
+    ops: Vec<EncodableOp<T>>,
+}
+
+impl<'a, T: Element> Transaction<'a, T> {
+    pub(crate) fn new(seq: &'a mut HashSeq<T>) -> Self {
+        Self {
+            seq,
+            ops: Vec::new(),
+        }
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<EncodableOp<T>> {
+        self.ops
+    }
+
+    /// The ops made so far.
+    pub fn ops(&self) -> &[EncodableOp<T>] {
+        &self.ops
+    }
+
+    pub fn insert(&mut self, idx: usize, value: T) {
+        let ops = self.seq.insert(idx, value);
+        self.ops.extend(ops);
+    }
+
+    pub fn insert_batch(&mut self, idx: usize, batch: impl IntoIterator<Item = T>) {
+        let ops = self.seq.insert_batch(idx, batch);
+        self.ops.extend(ops);
+    }
+
+    pub fn insert_after_id(&mut self, anchor: &Id, batch: impl IntoIterator<Item = T>) {
+        let ops = self.seq.insert_after_id(anchor, batch);
+        self.ops.extend(ops);
+    }
+
+    pub fn insert_before_id(&mut self, anchor: &Id, batch: impl IntoIterator<Item = T>) {
+        let ops = self.seq.insert_before_id(anchor, batch);
+        self.ops.extend(ops);
+    }
+
+    pub fn remove(&mut self, idx: usize) {
+        let ops = self.seq.remove(idx);
+        self.ops.extend(ops);
+    }
+
+    pub fn remove_batch(&mut self, idx: usize, amount: usize) {
+        let ops = self.seq.remove_batch(idx, amount);
+        self.ops.extend(ops);
+    }
+
+    pub fn remove_range_intent(&mut self, idx: usize, amount: usize) {
+        let ops = self.seq.remove_range_intent(idx, amount);
+        self.ops.extend(ops);
+    }
+
+    pub fn remove_ids(&mut self, ids: &BTreeSet<Id>) {
+        let ops = self.seq.remove_ids(ids);
+        self.ops.extend(ops);
+    }
+
+    pub fn splice(&mut self, range: Range<usize>, batch: impl IntoIterator<Item = T>) {
+        let ops = self.seq.splice(range, batch);
+        self.ops.extend(ops);
+    }
+
+    pub fn move_range(&mut self, range: Range<usize>, to: usize) {
+        let ops = self.seq.move_range(range, to);
+        self.ops.extend(ops);
+    }
+
+    pub fn add_mark(&mut self, range: Range<usize>, name: &str, value: &str, expand: Expand) {
+        let ops = self.seq.add_mark(range, name, value, expand);
+        self.ops.extend(ops);
+    }
+
+    pub fn remove_mark(&mut self, range: Range<usize>, name: &str, expand: Expand) {
+        let ops = self.seq.remove_mark(range, name, expand);
+        self.ops.extend(ops);
+    }
+}
+
+/// The journal of the open transaction, if there is one. A copy of the
+/// sequence isn't part of the transaction, so it starts without one.
+#[derive(Debug)]
+pub(crate) struct OpenJournal<T: Element>(pub(crate) Option<Journal<T>>);
+
+impl<T: Element> Default for OpenJournal<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T: Element> Clone for OpenJournal<T> {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+/// What a transaction changed, kept to put the sequence back if it fails.
+/// Each applied node adds the few undo entries it needs, so rolling back
+/// costs as much as the edits did, whatever the size of the sequence.
+#[derive(Debug)]
+pub(crate) struct Journal<T: Element> {
+    pub(crate) tips: BTreeSet<Id>,
+    pub(crate) mark_clock: u64,
+    pub(crate) move_clock: u64,
+    pub(crate) changes: Option<ChangeLogMark<T>>,
+    // Runs extended so far, which only need truncating back to their length
+    // before the first extension.
+    pub(crate) extended: IdSet,
+    pub(crate) undo: Vec<Undo<T>>,
+}
+
+/// One step to undo, in the order they were made; they're undone last first.
+#[derive(Debug)]
+pub(crate) enum Undo<T> {
+    /// A node was applied: drop everything keyed by its id.
+    Applied(Id),
+    /// `id` was added to the afters of `anchor`.
+    After { anchor: Id, id: Id },
+    /// `id` was added to the befores of `anchor`.
+    Before { anchor: Id, id: Id },
+    /// A run was extended past `len` elements.
+    Extended { run_id: Id, len: usize },
+    /// The run `left` was split, starting the run `right`.
+    Split { left: Id, right: Id },
+    /// An insert was removed.
+    Removed(Id),
+    /// A slot stopped holding its element.
+    Vacated(Id),
+    /// A slot was hidden by a range remove.
+    RangeRemoved(Id),
+    /// A slot was spanned by a range remove.
+    Spanned { slot: Id, range: Id },
+    /// An element was moved.
+    Moved { element: Id, entry: (u64, Id, Id) },
+    /// An element was inserted into the index at this position.
+    Indexed(usize),
+    /// An element was removed from the index at this position.
+    Unindexed {
+        position: usize,
+        id: Id,
+        value: T,
+        weight: Weight,
+    },
+}
+
+impl<T: Element> Deref for Transaction<'_, T> {
+    type Target = HashSeq<T>;
+
+    fn deref(&self) -> &HashSeq<T> {
+        self.seq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HashSeq, decode_batch, encode_batch};
+    use quickcheck_macros::quickcheck;
+
+    #[test]
+    fn test_commit_returns_one_batch() {
+        let mut alice = HashSeq::default();
+        let base = alice.insert_batch(0, "hello world".chars());
+        let mut bob = HashSeq::default();
+        for op in base {
+            bob.apply_op(op);
+        }
+
+        let ops = alice
+            .transaction(|tx| {
+                tx.splice(0..5, "howdy".chars());
+                let end = tx.len();
+                tx.insert_batch(end, "!".chars());
+                tx.remove(5);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(alice.to_string(), "howdyworld!");
+
+        for op in decode_batch(&encode_batch(&ops)).unwrap() {
+            bob.apply_op(op);
+        }
+        assert_eq!(bob, alice);
+        assert_eq!(bob.to_string(), "howdyworld!");
+    }
+
+    #[test]
+    fn test_error_rolls_back() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello".chars());
+        seq.set_record_changes(true);
+        let before = seq.clone();
+
+        let result = seq.transaction(|tx| {
+            tx.insert_batch(5, " world".chars());
+            tx.remove_batch(0, 2);
+            assert_eq!(tx.to_string(), "llo world");
+            Err("changed my mind")
+        });
+        assert_eq!(result, Err("changed my mind"));
+        assert_eq!(seq.tips(), before.tips());
+        assert_eq!(seq.runs, before.runs);
+        assert_eq!(seq.to_string(), "hello");
+        assert!(seq.take_changes().is_empty());
+        assert_eq!(seq.node_ids().count(), before.node_ids().count());
+    }
+
+    #[test]
+    fn test_panic_rolls_back() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "hello".chars());
+        let before = seq.clone();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            seq.transaction(|tx| -> Result<(), ()> {
+                tx.insert_batch(5, " world".chars());
+                tx.remove(0);
+                panic!("oops")
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(seq.tips(), before.tips());
+        assert_eq!(seq.to_string(), "hello");
+        assert_eq!(seq.node_ids().count(), before.node_ids().count());
+    }
+
+    #[test]
+    fn test_peer_applies_the_batch_as_a_unit() {
+        let mut alice = HashSeq::default();
+        let base = alice.insert_batch(0, "abc".chars());
+        let ops = alice
+            .transaction(|tx| {
+                tx.insert_batch(3, "def".chars());
+                tx.remove(0);
+                tx.insert(0, 'A');
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+
+        // Everything in the batch waits on what came before it...
+        let mut bob = HashSeq::default();
+        for op in ops {
+            bob.apply_op(op);
+        }
+        assert!(bob.is_empty());
+
+        // ...and lands with it.
+        for op in base {
+            bob.apply_op(op);
+        }
+        assert!(bob.orphans().is_empty());
+        assert_eq!(bob.to_string(), "Abcdef");
+    }
+
+    #[test]
+    fn test_orphans_wait_for_the_commit() {
+        // Bob only has the 'b' of Alice's "ab", so it waits on her 'a'...
+        let mut alice = HashSeq::default();
+        let ops = alice.insert_batch(0, "ab".chars());
+        let mut bob = HashSeq::default();
+        bob.apply_op(ops[1].clone());
+
+        // ...which is the very node Bob makes typing 'a' into his empty sequence.
+        let mut attempt = bob.clone();
+        let _ = attempt.transaction(|tx| {
+            tx.insert(0, 'a');
+            assert_eq!(tx.to_string(), "a");
+            Err(())
+        });
+        assert_eq!(attempt.to_string(), "");
+        assert_eq!(attempt.orphans().len(), 1);
+
+        bob.transaction(|tx| {
+            tx.insert(0, 'a');
+            assert_eq!(tx.to_string(), "a");
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert!(bob.orphans().is_empty());
+        assert_eq!(bob, alice);
+        assert_eq!(bob.to_string(), "ab");
+    }
+
+    #[quickcheck]
+    fn prop_rollback_leaves_no_trace(base: Vec<(bool, u8, char)>, edits: Vec<(bool, u8, char)>) {
+        fn edit(seq: &mut HashSeq, insert_or_remove: bool, idx: u8, ch: char) {
+            let idx = idx as usize;
+            if insert_or_remove {
+                seq.insert(idx.min(seq.len()), ch);
+            } else if !seq.is_empty() {
+                seq.remove(idx.min(seq.len() - 1));
+            }
+        }
+
+        let mut seq = HashSeq::default();
+        for (insert_or_remove, idx, ch) in base {
+            edit(&mut seq, insert_or_remove, idx, ch);
+        }
+        let before = seq.clone();
+        let result = seq.transaction(|tx| {
+            for &(insert_or_remove, idx, ch) in &edits {
+                let idx = idx as usize;
+                if insert_or_remove {
+                    tx.insert(idx.min(tx.len()), ch);
+                } else if !tx.is_empty() {
+                    tx.remove(idx.min(tx.len() - 1));
+                }
+            }
+            Err(())
+        });
+        assert!(result.is_err());
+
+        // Editing afterwards behaves as if the transaction never happened.
+        let mut expected = before.clone();
+        for &(insert_or_remove, idx, ch) in edits.iter().rev() {
+            edit(&mut seq, insert_or_remove, idx, ch);
+            edit(&mut expected, insert_or_remove, idx, ch);
+        }
+        assert_eq!(seq, expected);
+        assert_eq!(seq.to_string(), expected.to_string());
+        assert_eq!(seq.runs, expected.runs);
+    }
+}
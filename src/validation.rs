@@ -0,0 +1,587 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::hashseq::IdSet;
+use crate::{Element, HashNode, HashSeq, Id, Op, RejectReason};
+
+/// Decides which nodes a `HashSeq` accepts.
+///
+/// `validate` is only called once all of the node's dependencies have been
+/// applied. Rules must be deterministic functions of the node and its
+/// dependencies: every honest replica then rejects the same nodes, whatever
+/// order they arrive in, and replicas still converge. Don't look at anything
+/// else, like concurrent nodes, the visible text or the time of day.
+///
+/// Nodes that depend on a rejected node are rejected too.
+pub trait ValidationPolicy<T: Element = char>: std::fmt::Debug + Send + Sync {
+    fn validate(&self, seq: &HashSeq<T>, node: &HashNode<T>) -> Result<(), RejectReason>;
+
+    /// The most `extra_dependencies` the policy accepts, if it bounds them.
+    /// Local edits depend on at most this many of the tips, so that they
+    /// aren't rejected, here or by peers using the same policy.
+    fn max_extra_dependencies(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The policy a `HashSeq` starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DefaultPolicy {
+    /// Upper bound on the size of `extra_dependencies`. Honest replicas only
+    /// depend on their current tips, which stay small in practice.
+    pub max_extra_dependencies: usize,
+}
+
+impl Default for DefaultPolicy {
+    fn default() -> Self {
+        Self {
+            max_extra_dependencies: 256,
+        }
+    }
+}
+
+impl<T: Element> ValidationPolicy<T> for DefaultPolicy {
+    fn validate(&self, seq: &HashSeq<T>, node: &HashNode<T>) -> Result<(), RejectReason> {
+        if node.extra_dependencies.len() > self.max_extra_dependencies {
+            return Err(RejectReason::TooManyDependencies {
+                count: node.extra_dependencies.len(),
+                max: self.max_extra_dependencies,
+            });
+        }
+        if let Op::Remove(targets) = &node.op {
+            if targets.is_empty() {
+                return Err(RejectReason::EmptyRemove);
+            }
+            if let Some(target) = targets.iter().find(|id| {
+                seq.remove_nodes.contains_key(id) || seq.remove_range_nodes.contains_key(id)
+            }) {
+                return Err(RejectReason::RemovesRemove(*target));
+            }
+        }
+        Ok(())
+    }
+
+    fn max_extra_dependencies(&self) -> Option<usize> {
+        Some(self.max_extra_dependencies)
+    }
+}
+
+/// Accepts everything that is structurally sound.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PermissivePolicy;
+
+impl<T: Element> ValidationPolicy<T> for PermissivePolicy {
+    fn validate(&self, _seq: &HashSeq<T>, _node: &HashNode<T>) -> Result<(), RejectReason> {
+        Ok(())
+    }
+}
+
+/// Shared handle to a policy, so `HashSeq` stays `Clone` and `Default`.
+#[derive(Debug, Clone)]
+pub(crate) struct Validator<T: Element>(Arc<dyn ValidationPolicy<T>>);
+
+impl<T: Element> Validator<T> {
+    pub(crate) fn new(policy: impl ValidationPolicy<T> + 'static) -> Self {
+        Self(Arc::new(policy))
+    }
+
+    pub(crate) fn validate(
+        &self,
+        seq: &HashSeq<T>,
+        node: &HashNode<T>,
+    ) -> Result<(), RejectReason> {
+        self.0.validate(seq, node)
+    }
+
+    pub(crate) fn max_extra_dependencies(&self) -> Option<usize> {
+        self.0.max_extra_dependencies()
+    }
+}
+
+impl<T: Element> Default for Validator<T> {
+    fn default() -> Self {
+        Self::new(DefaultPolicy::default())
+    }
+}
+
+/// Ids of rejected nodes, kept so that nodes built on them are rejected too.
+///
+/// Past `max_count` the oldest are forgotten. That's safe: a node built on a
+/// forgotten one waits as an orphan instead, and if the forgotten node is sent
+/// again it is rejected again, taking its dependents with it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RejectedSet {
+    // Keyed by ids we hashed ourselves, so FxHash is safe here.
+    ids: IdSet,
+    order: VecDeque<Id>,
+    max_count: Option<usize>,
+}
+
+impl RejectedSet {
+    pub(crate) fn contains(&self, id: &Id) -> bool {
+        self.ids.contains(id)
+    }
+
+    pub(crate) fn insert(&mut self, id: Id) {
+        if self.ids.insert(id) {
+            self.order.push_back(id);
+            self.evict();
+        }
+    }
+
+    pub(crate) fn set_max_count(&mut self, max_count: Option<usize>) {
+        self.max_count = max_count;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        let Some(max) = self.max_count else {
+            return;
+        };
+        while self.order.len() > max {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApplyOutcome, EncodableOp, Expand, Id};
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeSet;
+
+    fn node(op: Op) -> HashNode {
+        HashNode {
+            extra_dependencies: BTreeSet::new(),
+            op,
+        }
+    }
+
+    fn only_node(ops: Vec<EncodableOp>) -> HashNode {
+        match ops.into_iter().next() {
+            Some(EncodableOp::Node(node)) => node,
+            op => panic!("expected a single node, got {op:?}"),
+        }
+    }
+
+    fn fake_id(i: u64) -> Id {
+        Id(*blake3::hash(&i.to_le_bytes()).as_bytes())
+    }
+
+    #[test]
+    fn test_empty_remove_is_rejected() {
+        let mut seq = HashSeq::default();
+        seq.insert(0, 'a');
+        assert_eq!(
+            seq.try_apply(node(Op::Remove(BTreeSet::new()))),
+            ApplyOutcome::Rejected(RejectReason::EmptyRemove)
+        );
+        assert_eq!(seq.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_of_a_remove_is_rejected() {
+        let mut seq = HashSeq::default();
+        seq.insert(0, 'a');
+        let remove = only_node(seq.remove(0));
+        let undo = node(Op::Remove(BTreeSet::from([remove.id()])));
+        assert_eq!(
+            seq.try_apply(undo.clone()),
+            ApplyOutcome::Rejected(RejectReason::RemovesRemove(remove.id()))
+        );
+        assert!(seq.is_rejected(&undo.id()));
+        assert_eq!(seq.len(), 0);
+    }
+
+    #[test]
+    fn test_marks_have_no_position() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "ab".chars());
+        let mark = only_node(seq.add_mark(0..1, "bold", "true", Expand::None));
+        assert_eq!(
+            seq.try_apply(node(Op::InsertAfter(mark.id(), 'x'))),
+            ApplyOutcome::Rejected(RejectReason::AnchorIsMark(mark.id()))
+        );
+        assert_eq!(
+            seq.try_apply(node(Op::Remove(BTreeSet::from([mark.id()])))),
+            ApplyOutcome::Rejected(RejectReason::RemovesMark(mark.id()))
+        );
+        assert_eq!(seq.mark_spans().len(), 2);
+    }
+
+    #[test]
+    fn test_moves_only_move_inserts() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        let mv = only_node(seq.move_range(0..1, 3));
+        let remove = only_node(seq.remove(0));
+        let a = *seq.iter_ids().last().unwrap();
+        assert_eq!(
+            seq.try_apply(node(Op::Remove(BTreeSet::from([mv.id()])))),
+            ApplyOutcome::Rejected(RejectReason::RemovesMove(mv.id()))
+        );
+        let moves = |element| Op::Move {
+            anchor: a,
+            before: false,
+            element,
+            clock: 2,
+        };
+        assert_eq!(
+            seq.try_apply(node(moves(remove.id()))),
+            ApplyOutcome::Rejected(RejectReason::MovesNonInsert(remove.id()))
+        );
+        assert_eq!(
+            seq.try_apply(node(moves(mv.id()))),
+            ApplyOutcome::Rejected(RejectReason::MovesNonInsert(mv.id()))
+        );
+        assert_eq!(seq.to_string(), "ca");
+    }
+
+    #[test]
+    fn test_range_removes_have_no_position() {
+        let mut seq = HashSeq::default();
+        seq.insert_batch(0, "abc".chars());
+        let range = only_node(seq.remove_range_intent(0, 2));
+        assert_eq!(
+            seq.try_apply(node(Op::InsertBefore(range.id(), 'x'))),
+            ApplyOutcome::Rejected(RejectReason::AnchorIsRemove(range.id()))
+        );
+        assert_eq!(
+            seq.try_apply(node(Op::Remove(BTreeSet::from([range.id()])))),
+            ApplyOutcome::Rejected(RejectReason::RemovesRemove(range.id()))
+        );
+        assert_eq!(seq.to_string(), "c");
+    }
+
+    #[test]
+    fn test_too_many_dependencies_is_rejected() {
+        let mut seq = HashSeq::default();
+        seq.insert(0, 'a');
+        let mut spam = node(Op::InsertRoot('x'));
+        spam.extra_dependencies = seq.tips().clone();
+        for i in 0..300 {
+            spam.extra_dependencies.insert(fake_id(i));
+        }
+
+        // The missing dependencies never show up: the node sits in the orphan buffer.
+        assert!(matches!(
+            seq.try_apply(spam.clone()),
+            ApplyOutcome::Orphaned { .. }
+        ));
+
+        // Once they do, it's rejected.
+        let mut seq = HashSeq::default();
+        seq.set_validation_policy(DefaultPolicy {
+            max_extra_dependencies: 2,
+        });
+        let mut deps = BTreeSet::new();
+        for ch in "abc".chars() {
+            let root = node(Op::InsertRoot(ch));
+            deps.insert(root.id());
+            seq.apply(root);
+        }
+        let mut wide = node(Op::InsertRoot('x'));
+        wide.extra_dependencies = deps;
+        assert_eq!(
+            seq.try_apply(wide),
+            ApplyOutcome::Rejected(RejectReason::TooManyDependencies { count: 3, max: 2 })
+        );
+    }
+
+    #[test]
+    fn test_dependents_of_rejected_nodes_are_rejected() {
+        let mut seq = HashSeq::default();
+        seq.insert(0, 'a');
+        let remove = only_node(seq.remove(0));
+        let bad = node(Op::InsertAfter(remove.id(), 'x'));
+        let child = node(Op::InsertAfter(bad.id(), 'y'));
+        let grandchild = node(Op::Remove(BTreeSet::from([child.id()])));
+
+        // Delivered out of order, the dependents are rejected once `bad` is.
+        let mut fresh = HashSeq::default();
+        assert!(matches!(
+            fresh.try_apply(grandchild.clone()),
+            ApplyOutcome::Orphaned { .. }
+        ));
+        assert!(matches!(
+            fresh.try_apply(child.clone()),
+            ApplyOutcome::Orphaned { .. }
+        ));
+        for op in seq.ops_since(&BTreeSet::new()) {
+            fresh.apply_op(op);
+        }
+        assert_eq!(
+            fresh.try_apply(bad.clone()),
+            ApplyOutcome::Rejected(RejectReason::AnchorIsRemove(remove.id()))
+        );
+        assert!(fresh.orphans().is_empty());
+        assert!(fresh.is_rejected(&child.id()));
+        assert!(fresh.is_rejected(&grandchild.id()));
+
+        // Delivered in order, each one is rejected as it arrives.
+        seq.try_apply(bad.clone());
+        assert_eq!(
+            seq.try_apply(child),
+            ApplyOutcome::Rejected(RejectReason::DependsOnRejected(bad.id()))
+        );
+        assert!(matches!(
+            seq.try_apply(grandchild),
+            ApplyOutcome::Rejected(RejectReason::DependsOnRejected(_))
+        ));
+        assert_eq!(seq, fresh);
+    }
+
+    #[test]
+    fn test_orphans_rejected_on_release_are_reported() {
+        let mut seq = HashSeq::default();
+        let root = only_node(seq.insert(0, 'a'));
+        let remove = only_node(seq.remove(0));
+        let bad = node(Op::InsertAfter(remove.id(), 'x'));
+        let child = node(Op::InsertAfter(bad.id(), 'y'));
+
+        let mut fresh = HashSeq::default();
+        fresh.try_apply(root);
+        fresh.try_apply(child.clone());
+        fresh.try_apply(bad.clone());
+        assert_eq!(
+            fresh.try_apply(remove.clone()),
+            ApplyOutcome::Applied {
+                unorphaned: vec![],
+                rejected: vec![
+                    (bad.id(), RejectReason::AnchorIsRemove(remove.id())),
+                    (child.id(), RejectReason::DependsOnRejected(bad.id())),
+                ],
+            }
+        );
+        assert!(fresh.orphans().is_empty());
+    }
+
+    #[test]
+    fn test_oldest_rejections_are_forgotten() {
+        let mut seq = HashSeq::default();
+        seq.insert(0, 'a');
+        let remove = only_node(seq.remove(0));
+        seq.set_orphan_policy(crate::OrphanPolicy {
+            max_count: Some(2),
+            ..Default::default()
+        });
+
+        let bad: Vec<HashNode> = "xyz"
+            .chars()
+            .map(|c| node(Op::InsertAfter(remove.id(), c)))
+            .collect();
+        for n in &bad {
+            assert!(matches!(
+                seq.try_apply(n.clone()),
+                ApplyOutcome::Rejected(_)
+            ));
+        }
+        assert!(!seq.is_rejected(&bad[0].id()));
+        assert!(seq.is_rejected(&bad[1].id()));
+        assert!(seq.is_rejected(&bad[2].id()));
+
+        // Built on a forgotten rejection, a node waits as an orphan until the
+        // rejected node is sent again.
+        let child = node(Op::InsertAfter(bad[0].id(), 'w'));
+        assert_eq!(
+            seq.try_apply(child.clone()),
+            ApplyOutcome::Orphaned {
+                missing: vec![bad[0].id()]
+            }
+        );
+        assert!(matches!(
+            seq.try_apply(bad[0].clone()),
+            ApplyOutcome::Rejected(RejectReason::AnchorIsRemove(_))
+        ));
+        assert!(seq.is_rejected(&child.id()));
+        assert!(seq.orphans().is_empty());
+    }
+
+    /// A sequence holding `n` roots from as many concurrent peers, so it has `n` tips.
+    fn concurrent_peers(n: u32) -> HashSeq {
+        let mut seq = HashSeq::default();
+        for i in 0..n {
+            let mut peer = HashSeq::default();
+            for op in peer.insert(0, char::from_u32(0x100 + i).unwrap()) {
+                seq.apply_op(op);
+            }
+        }
+        seq
+    }
+
+    #[test]
+    fn test_local_edits_stay_within_the_dependency_bound() {
+        let mut seq = concurrent_peers(300);
+        assert_eq!(seq.tips().len(), 300);
+        let mut replica = seq.clone();
+
+        let mut ops = seq.insert(0, 'x');
+        assert_eq!(seq.len(), 301);
+        assert!(seq.tips().len() > 1);
+        ops.extend(seq.remove(1));
+        assert_eq!(seq.len(), 300);
+        // The tips the insert left out were folded into the remove.
+        assert_eq!(seq.tips().len(), 1);
+
+        // A batch folds them into the nodes after its first one.
+        let mut batch = concurrent_peers(300);
+        let mut batch_replica = batch.clone();
+        let batch_ops = batch.insert_batch(0, "xyz".chars());
+        assert_eq!(batch.len(), 303);
+        assert_eq!(batch.tips().len(), 1);
+
+        for op in ops {
+            for outcome in replica.try_apply_op(op) {
+                assert!(matches!(outcome, ApplyOutcome::Applied { .. }));
+            }
+        }
+        assert_eq!(replica, seq);
+        assert_eq!(replica.to_string(), seq.to_string());
+        for op in batch_ops {
+            for outcome in batch_replica.try_apply_op(op) {
+                assert!(matches!(outcome, ApplyOutcome::Applied { .. }));
+            }
+        }
+        assert_eq!(batch_replica, batch);
+        assert_eq!(batch_replica.to_string(), batch.to_string());
+    }
+
+    #[test]
+    fn test_rejected_local_edits_return_no_ops() {
+        #[derive(Debug)]
+        struct NoRemoves;
+
+        impl ValidationPolicy for NoRemoves {
+            fn validate(&self, _seq: &HashSeq, node: &HashNode) -> Result<(), RejectReason> {
+                match node.op {
+                    Op::Remove(_) => Err(RejectReason::Policy("no removes".into())),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let mut seq = HashSeq::default();
+        seq.set_validation_policy(NoRemoves);
+        assert_eq!(seq.insert_batch(0, "ab".chars()).len(), 2);
+        assert!(seq.remove(0).is_empty());
+        assert_eq!(seq.to_string(), "ab");
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        #[derive(Debug)]
+        struct NoShouting;
+
+        impl ValidationPolicy for NoShouting {
+            fn validate(&self, seq: &HashSeq, node: &HashNode) -> Result<(), RejectReason> {
+                match node.op {
+                    Op::InsertRoot(c) | Op::InsertAfter(_, c) | Op::InsertBefore(_, c)
+                        if c.is_uppercase() =>
+                    {
+                        Err(RejectReason::Policy(format!("no shouting: {c:?}")))
+                    }
+                    _ => DefaultPolicy::default().validate(seq, node),
+                }
+            }
+        }
+
+        let mut seq = HashSeq::default();
+        seq.set_validation_policy(NoShouting);
+        seq.insert_batch(0, "hello".chars());
+        assert_eq!(
+            seq.try_apply(node(Op::InsertRoot('H'))),
+            ApplyOutcome::Rejected(RejectReason::Policy("no shouting: 'H'".into()))
+        );
+        assert_eq!(seq.iter().collect::<String>(), "hello");
+
+        let mut permissive = HashSeq::default();
+        permissive.set_validation_policy(PermissivePolicy);
+        assert!(matches!(
+            permissive.try_apply(node(Op::Remove(BTreeSet::new()))),
+            ApplyOutcome::Applied { .. }
+        ));
+    }
+
+    #[quickcheck]
+    fn prop_honest_replicas_converge_under_hostile_ops(
+        edits: Vec<(bool, u8, char)>,
+        hostile: Vec<(u8, u8, char)>,
+        seed: u64,
+    ) {
+        use rand::{SeedableRng, seq::SliceRandom};
+
+        let mut honest = HashSeq::default();
+        let mut nodes: Vec<HashNode> = Vec::new();
+        let mut removes: Vec<Id> = Vec::new();
+        for (insert_or_remove, idx, ch) in edits {
+            let idx = idx as usize;
+            let ops = if insert_or_remove {
+                honest.insert(idx.min(honest.len()), ch)
+            } else if !honest.is_empty() {
+                let ops = honest.remove(idx.min(honest.len() - 1));
+                if let Some(EncodableOp::Node(remove)) = ops.first() {
+                    removes.push(remove.id());
+                }
+                ops
+            } else {
+                continue;
+            };
+            for op in ops {
+                match op {
+                    EncodableOp::Node(node) => nodes.push(node),
+                    EncodableOp::Run(run) => nodes.extend(run.decompress()),
+                }
+            }
+        }
+
+        // Hostile nodes built on honest ones and on each other.
+        let mut ids: Vec<Id> = nodes.iter().map(|n| n.id()).collect();
+        for (kind, target, ch) in hostile {
+            let pick = |pool: &[Id]| pool.get(target as usize % pool.len().max(1)).copied();
+            let op = match kind % 5 {
+                0 => Op::Remove(BTreeSet::new()),
+                1 => match pick(&removes) {
+                    Some(r) => Op::Remove(BTreeSet::from([r])),
+                    None => continue,
+                },
+                2 => match pick(&removes) {
+                    Some(r) => Op::InsertAfter(r, ch),
+                    None => continue,
+                },
+                3 => match pick(&ids) {
+                    Some(id) => Op::InsertBefore(id, ch),
+                    None => Op::InsertRoot(ch),
+                },
+                _ => match pick(&ids) {
+                    Some(id) => Op::Remove(BTreeSet::from([id])),
+                    None => continue,
+                },
+            };
+            let hostile_node = node(op);
+            ids.push(hostile_node.id());
+            nodes.push(hostile_node);
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut in_order = HashSeq::default();
+        for n in nodes.iter().cloned() {
+            in_order.apply(n);
+        }
+        nodes.shuffle(&mut rng);
+        let mut shuffled = HashSeq::default();
+        for n in nodes {
+            shuffled.apply(n);
+        }
+
+        assert!(in_order.orphans().is_empty());
+        assert!(shuffled.orphans().is_empty());
+        assert_eq!(in_order, shuffled);
+        assert_eq!(
+            in_order.iter().collect::<String>(),
+            shuffled.iter().collect::<String>()
+        );
+    }
+}
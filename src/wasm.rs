@@ -2,18 +2,55 @@ use wasm_bindgen::prelude::*;
 
 use crate::encoding::{decode_hashseq, encode_hashseq};
 use crate::hashseq::HashSeq;
+use crate::Change;
 
 #[wasm_bindgen]
-#[derive(Default)]
 pub struct WasmHashSeq {
     inner: HashSeq,
 }
 
+/// A change to the visible text, see `Change`. Offsets are in UTF-16 code
+/// units, like JavaScript string indices.
+#[wasm_bindgen(getter_with_clone)]
+pub struct WasmChange {
+    pub index: usize,
+    pub removed_len: usize,
+    pub inserted: String,
+}
+
+impl From<Change> for WasmChange {
+    fn from(change: Change) -> Self {
+        Self {
+            index: change.utf16_index,
+            removed_len: change.utf16_removed_len,
+            inserted: change.inserted,
+        }
+    }
+}
+
+fn invalid_offset(offset: usize) -> JsValue {
+    JsValue::from_str(&format!("offset {offset} is out of bounds or splits a character"))
+}
+
+fn invalid_range(offset: usize, len: usize) -> JsValue {
+    JsValue::from_str(&format!(
+        "range of {len} at offset {offset} is out of bounds or splits a character"
+    ))
+}
+
 #[wasm_bindgen]
 impl WasmHashSeq {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            inner: HashSeq::default(),
+        }
+    }
+
+    /// Start or stop recording changes for `take_changes`. Off by default:
+    /// once on, changes pile up until they're taken.
+    pub fn record_changes(&mut self, record: bool) {
+        self.inner.set_record_changes(record);
     }
 
     pub fn insert(&mut self, idx: usize, text: &str) {
@@ -24,14 +61,51 @@ impl WasmHashSeq {
         self.inner.remove_batch(idx, len);
     }
 
+    /// Insert at a UTF-16 offset, e.g. a CodeMirror position.
+    pub fn insert_utf16(&mut self, offset: usize, text: &str) -> Result<(), JsValue> {
+        self.inner
+            .insert_utf16(offset, text)
+            .ok_or_else(|| invalid_offset(offset))?;
+        Ok(())
+    }
+
+    /// Remove `len` UTF-16 code units starting at `offset`.
+    pub fn remove_utf16(&mut self, offset: usize, len: usize) -> Result<(), JsValue> {
+        self.inner
+            .remove_utf16(offset, len)
+            .ok_or_else(|| invalid_range(offset, len))?;
+        Ok(())
+    }
+
+    /// Insert at a UTF-8 byte offset.
+    pub fn insert_utf8(&mut self, offset: usize, text: &str) -> Result<(), JsValue> {
+        self.inner
+            .insert_utf8(offset, text)
+            .ok_or_else(|| invalid_offset(offset))?;
+        Ok(())
+    }
+
+    pub fn utf16_to_index(&self, offset: usize) -> Option<usize> {
+        self.inner.utf16_to_index(offset)
+    }
+
+    pub fn index_to_utf16(&self, idx: usize) -> usize {
+        self.inner.index_to_utf16(idx)
+    }
+
     pub fn text(&self) -> String {
-        self.inner.iter().collect()
+        self.inner.to_string()
     }
 
     pub fn len(&self) -> usize {
         self.inner.len()
     }
 
+    /// Length in UTF-16 code units, i.e. the `length` of `text()` in JavaScript.
+    pub fn utf16_len(&self) -> usize {
+        self.inner.utf16_len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
@@ -43,7 +117,19 @@ impl WasmHashSeq {
     pub fn merge_encoded(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
         let other = decode_hashseq(bytes)
             .map_err(|e| JsValue::from_str(&format!("decode error: {e}")))?;
-        self.inner.merge(other);
+        self.inner.merge(&other);
         Ok(())
     }
+
+    /// Changes to the text since the last call, including local edits.
+    /// Empty unless `record_changes` was turned on.
+    pub fn take_changes(&mut self) -> Vec<WasmChange> {
+        self.inner.take_changes().into_iter().map(WasmChange::from).collect()
+    }
+}
+
+impl Default for WasmHashSeq {
+    fn default() -> Self {
+        Self::new()
+    }
 }
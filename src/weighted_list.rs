@@ -0,0 +1,664 @@
+use std::ops::{Add, AddAssign, Range, Sub, SubAssign};
+
+use crate::Id;
+use crate::hashseq::IdMap;
+
+/// How much an element counts for in each of the ways the sequence can be
+/// indexed. Summed over a range, it gives the length of that range.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Weight {
+    /// Elements, i.e. `char`s for text.
+    pub len: usize,
+    /// UTF-16 code units.
+    pub utf16: usize,
+    /// UTF-8 bytes.
+    pub utf8: usize,
+    /// Newlines, i.e. line breaks.
+    pub lines: usize,
+}
+
+impl Weight {
+    /// An element that is one unit long in every encoding and isn't a newline.
+    pub const UNIT: Weight = Weight {
+        len: 1,
+        utf16: 1,
+        utf8: 1,
+        lines: 0,
+    };
+}
+
+impl Add for Weight {
+    type Output = Weight;
+
+    fn add(self, other: Weight) -> Weight {
+        Weight {
+            len: self.len + other.len,
+            utf16: self.utf16 + other.utf16,
+            utf8: self.utf8 + other.utf8,
+            lines: self.lines + other.lines,
+        }
+    }
+}
+
+impl AddAssign for Weight {
+    fn add_assign(&mut self, other: Weight) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Weight {
+    type Output = Weight;
+
+    fn sub(self, other: Weight) -> Weight {
+        Weight {
+            len: self.len - other.len,
+            utf16: self.utf16 - other.utf16,
+            utf8: self.utf8 - other.utf8,
+            lines: self.lines - other.lines,
+        }
+    }
+}
+
+impl SubAssign for Weight {
+    fn sub_assign(&mut self, other: Weight) {
+        *self = *self - other;
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    id: Id,
+    value: T,
+    weight: Weight,
+    // Sum of the weights in the subtree rooted here.
+    total: Weight,
+    priority: u64,
+    left: usize,
+    right: usize,
+    parent: usize,
+}
+
+/// The visible ids and their values in order, each with a weight, so positions
+/// can be looked up by element, UTF-16 or UTF-8 offset, or line.
+///
+/// A treap keyed by position, with parent links so `find` can walk up from an
+/// id to the root. Every operation is O(log n).
+///
+/// Priorities mix the ids' leading bytes with a key drawn at random for each
+/// list. Peers choose ids, and could grind them to unbalance the tree if the
+/// ids were used as priorities directly. The mix is a couple of multiplies
+/// rather than a hash, since it runs for every element that's inserted.
+#[derive(Debug, Clone)]
+pub struct WeightedList<T> {
+    nodes: Vec<Node<T>>,
+    // Slots in `nodes` that were freed by `remove`.
+    free: Vec<usize>,
+    root: usize,
+    slots: IdMap<usize>,
+    priority_key: [u64; 2],
+}
+
+impl<T> Default for WeightedList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> WeightedList<T> {
+    pub fn new() -> Self {
+        let mut key = [0; 16];
+        getrandom::getrandom(&mut key).expect("no source of randomness");
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: NIL,
+            slots: IdMap::default(),
+            priority_key: [word(&key, 0), word(&key, 8)],
+        }
+    }
+
+    /// The keyed priority of `id`: murmur3's finalizer over each of its
+    /// first two words, chained.
+    fn priority(&self, id: &Id) -> u64 {
+        let fmix = |mut x: u64| {
+            x ^= x >> 33;
+            x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+            x ^= x >> 33;
+            x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+            x ^ (x >> 33)
+        };
+        let [k0, k1] = self.priority_key;
+        fmix(fmix(word(&id.0, 0) ^ k0) ^ word(&id.0, 8) ^ k1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.total().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root == NIL
+    }
+
+    /// The summed weight of every element.
+    pub fn total(&self) -> Weight {
+        self.subtotal(self.root)
+    }
+
+    pub fn get(&self, position: usize) -> Option<&Id> {
+        self.entry(position).map(|(id, _)| id)
+    }
+
+    /// The id and value at `position`.
+    pub fn entry(&self, position: usize) -> Option<(&Id, &T)> {
+        let node = &self.nodes[self.node_at(position)?];
+        Some((&node.id, &node.value))
+    }
+
+    /// The position of `id`, if it's in the list.
+    pub fn find(&self, id: &Id) -> Option<usize> {
+        let mut n = *self.slots.get(id)?;
+        let mut position = self.subtotal(self.nodes[n].left).len;
+        while self.nodes[n].parent != NIL {
+            let parent = &self.nodes[self.nodes[n].parent];
+            if parent.right == n {
+                position += self.subtotal(parent.left).len + 1;
+            }
+            n = self.nodes[n].parent;
+        }
+        Some(position)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.range(0..self.len())
+    }
+
+    /// The ids and values from `position` on.
+    pub fn iter_from(&self, position: usize) -> Iter<'_, T> {
+        self.range(position..self.len())
+    }
+
+    /// The ids and values in `range`, clamped to the length of the list.
+    pub fn range(&self, range: Range<usize>) -> Iter<'_, T> {
+        let end = range.end.min(self.len());
+        let remaining = end.saturating_sub(range.start);
+        let (front, back) = match remaining {
+            0 => (NIL, NIL),
+            _ => (
+                self.node_at(range.start).unwrap(),
+                self.node_at(end - 1).unwrap(),
+            ),
+        };
+        Iter {
+            list: self,
+            front,
+            back,
+            remaining,
+        }
+    }
+
+    /// The summed weight of the elements before `position`.
+    pub fn prefix(&self, mut position: usize) -> Weight {
+        let mut sum = Weight::default();
+        let mut n = self.root;
+        while n != NIL && position > 0 {
+            let node = &self.nodes[n];
+            let left = self.subtotal(node.left);
+            if position <= left.len {
+                n = node.left;
+            } else {
+                sum += left + node.weight;
+                position -= left.len + 1;
+                n = node.right;
+            }
+        }
+        sum
+    }
+
+    /// The weight of the shortest prefix that is at least `offset` long as
+    /// measured by `metric`, or `None` if the whole list is shorter than that.
+    /// The prefix is exactly `offset` long unless `offset` falls inside an element.
+    pub fn seek(&self, offset: usize, metric: impl Fn(&Weight) -> usize) -> Option<Weight> {
+        if metric(&self.total()) < offset {
+            return None;
+        }
+        let mut sum = Weight::default();
+        let mut remaining = offset;
+        let mut n = self.root;
+        while remaining > 0 {
+            let node = &self.nodes[n];
+            let left = self.subtotal(node.left);
+            if metric(&left) >= remaining {
+                n = node.left;
+                continue;
+            }
+            let through = left + node.weight;
+            sum += through;
+            if metric(&through) >= remaining {
+                break;
+            }
+            remaining -= metric(&through);
+            n = node.right;
+        }
+        Some(sum)
+    }
+
+    /// Insert `id` so that it ends up at `position`. Ids must be unique.
+    pub fn insert(&mut self, position: usize, id: Id, value: T, weight: Weight) {
+        debug_assert!(!self.slots.contains_key(&id));
+        let node = Node {
+            id,
+            value,
+            weight,
+            total: weight,
+            priority: self.priority(&id),
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.slots.insert(id, slot);
+
+        if self.root == NIL {
+            self.set_root(slot);
+            return;
+        }
+        // Attach as a leaf, then rotate up until the heap order is restored.
+        let mut position = position.min(self.len());
+        let mut n = self.root;
+        loop {
+            self.nodes[n].total += weight;
+            let (left, right) = (self.nodes[n].left, self.nodes[n].right);
+            let left_len = self.subtotal(left).len;
+            if position <= left_len {
+                if left == NIL {
+                    self.set_left(n, slot);
+                    break;
+                }
+                n = left;
+            } else {
+                position -= left_len + 1;
+                if right == NIL {
+                    self.set_right(n, slot);
+                    break;
+                }
+                n = right;
+            }
+        }
+        loop {
+            let parent = self.nodes[slot].parent;
+            if parent == NIL || self.nodes[parent].priority >= self.nodes[slot].priority {
+                break;
+            }
+            self.rotate_up(slot);
+        }
+    }
+
+    /// Remove the element at `position`, returning its weight.
+    pub fn remove(&mut self, position: usize) -> Option<Weight> {
+        let n = self.node_at(position)?;
+
+        let node = &self.nodes[n];
+        let (weight, parent) = (node.weight, node.parent);
+        let child = self.merge(node.left, node.right);
+        if parent == NIL {
+            self.set_root(child);
+        } else {
+            if self.nodes[parent].left == n {
+                self.set_left(parent, child);
+            } else {
+                self.set_right(parent, child);
+            }
+            let mut ancestor = parent;
+            while ancestor != NIL {
+                self.nodes[ancestor].total -= weight;
+                ancestor = self.nodes[ancestor].parent;
+            }
+        }
+
+        self.slots.remove(&self.nodes[n].id);
+        self.free.push(n);
+        Some(weight)
+    }
+
+    fn node_at(&self, mut position: usize) -> Option<usize> {
+        let mut n = self.root;
+        while n != NIL {
+            let node = &self.nodes[n];
+            let left = self.subtotal(node.left).len;
+            match position.cmp(&left) {
+                std::cmp::Ordering::Less => n = node.left,
+                std::cmp::Ordering::Equal => return Some(n),
+                std::cmp::Ordering::Greater => {
+                    position -= left + 1;
+                    n = node.right;
+                }
+            }
+        }
+        None
+    }
+
+    fn subtotal(&self, n: usize) -> Weight {
+        if n == NIL {
+            Weight::default()
+        } else {
+            self.nodes[n].total
+        }
+    }
+
+    fn leftmost(&self, mut n: usize) -> usize {
+        while self.nodes[n].left != NIL {
+            n = self.nodes[n].left;
+        }
+        n
+    }
+
+    fn rightmost(&self, mut n: usize) -> usize {
+        while self.nodes[n].right != NIL {
+            n = self.nodes[n].right;
+        }
+        n
+    }
+
+    /// The node before `n` in order.
+    fn predecessor(&self, mut n: usize) -> Option<usize> {
+        if self.nodes[n].left != NIL {
+            return Some(self.rightmost(self.nodes[n].left));
+        }
+        loop {
+            let parent = self.nodes[n].parent;
+            if parent == NIL {
+                return None;
+            }
+            if self.nodes[parent].right == n {
+                return Some(parent);
+            }
+            n = parent;
+        }
+    }
+
+    /// The node after `n` in order.
+    fn successor(&self, mut n: usize) -> Option<usize> {
+        if self.nodes[n].right != NIL {
+            return Some(self.leftmost(self.nodes[n].right));
+        }
+        loop {
+            let parent = self.nodes[n].parent;
+            if parent == NIL {
+                return None;
+            }
+            if self.nodes[parent].left == n {
+                return Some(parent);
+            }
+            n = parent;
+        }
+    }
+
+    fn set_root(&mut self, n: usize) {
+        self.root = n;
+        if n != NIL {
+            self.nodes[n].parent = NIL;
+        }
+    }
+
+    fn set_left(&mut self, n: usize, child: usize) {
+        self.nodes[n].left = child;
+        if child != NIL {
+            self.nodes[child].parent = n;
+        }
+    }
+
+    fn set_right(&mut self, n: usize, child: usize) {
+        self.nodes[n].right = child;
+        if child != NIL {
+            self.nodes[child].parent = n;
+        }
+    }
+
+    fn update_total(&mut self, n: usize) {
+        let node = &self.nodes[n];
+        self.nodes[n].total = self.subtotal(node.left) + node.weight + self.subtotal(node.right);
+    }
+
+    /// Swap `n` with its parent, keeping the order of the elements.
+    fn rotate_up(&mut self, n: usize) {
+        let parent = self.nodes[n].parent;
+        let grandparent = self.nodes[parent].parent;
+        if self.nodes[parent].left == n {
+            self.set_left(parent, self.nodes[n].right);
+            self.set_right(n, parent);
+        } else {
+            self.set_right(parent, self.nodes[n].left);
+            self.set_left(n, parent);
+        }
+        if grandparent == NIL {
+            self.set_root(n);
+        } else if self.nodes[grandparent].left == parent {
+            self.set_left(grandparent, n);
+        } else {
+            self.set_right(grandparent, n);
+        }
+        self.update_total(parent);
+        self.update_total(n);
+    }
+
+    /// Concatenate the subtrees at `a` and `b`.
+    ///
+    /// Walks down the right spine of `a` and the left spine of `b`, hanging
+    /// the higher priority node off of the last one taken. Iterative, since
+    /// the spines can be long.
+    fn merge(&mut self, mut a: usize, mut b: usize) -> usize {
+        let mut root = NIL;
+        // The last node taken, and whether the next one goes on its right.
+        let mut last: Option<(usize, bool)> = None;
+        let mut taken = Vec::new();
+        loop {
+            let next = if a == NIL {
+                b
+            } else if b == NIL || self.nodes[a].priority > self.nodes[b].priority {
+                a
+            } else {
+                b
+            };
+            match last {
+                None => root = next,
+                Some((parent, true)) => self.set_right(parent, next),
+                Some((parent, false)) => self.set_left(parent, next),
+            }
+            if a == NIL || b == NIL {
+                break;
+            }
+            taken.push(next);
+            if next == a {
+                a = self.nodes[a].right;
+                last = Some((next, true));
+            } else {
+                b = self.nodes[b].left;
+                last = Some((next, false));
+            }
+        }
+        for n in taken.into_iter().rev() {
+            self.update_total(n);
+        }
+        root
+    }
+}
+
+/// Iterator over a range of a `WeightedList`, from either end.
+#[derive(Debug, Clone)]
+pub struct Iter<'a, T> {
+    list: &'a WeightedList<T>,
+    front: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a Id, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front];
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.front = self.list.successor(self.front).unwrap();
+        }
+        Some((&node.id, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back];
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.back = self.list.predecessor(self.back).unwrap();
+        }
+        Some((&node.id, &node.value))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+/// The little-endian `u64` at `offset` in `bytes`.
+fn word(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Element;
+    use quickcheck_macros::quickcheck;
+
+    fn id(i: usize) -> Id {
+        Id(*blake3::hash(&i.to_le_bytes()).as_bytes())
+    }
+
+    #[test]
+    fn test_offsets() {
+        let mut list = WeightedList::new();
+        for (i, ch) in "aé🦀\nb".chars().enumerate() {
+            list.insert(i, id(i), ch, ch.weight());
+        }
+        assert_eq!(
+            list.total(),
+            Weight {
+                len: 5,
+                utf16: 6,
+                utf8: 9,
+                lines: 1,
+            }
+        );
+        assert_eq!(list.prefix(3).utf16, 4);
+        assert_eq!(list.prefix(3).utf8, 7);
+        assert_eq!(list.seek(4, |w| w.utf16).map(|w| w.len), Some(3));
+        // Inside the crab.
+        assert_eq!(list.seek(3, |w| w.utf16).map(|w| w.utf16), Some(4));
+        assert_eq!(list.seek(7, |w| w.utf16), None);
+        assert_eq!(list.seek(1, |w| w.lines).map(|w| w.len), Some(4));
+        assert_eq!(list.entry(4), Some((&id(4), &'b')));
+
+        assert_eq!(list.remove(2), Some('🦀'.weight()));
+        assert_eq!(list.find(&id(3)), Some(2));
+        assert_eq!(list.find(&id(2)), None);
+        assert_eq!(list.total().utf16, 4);
+        assert_eq!(
+            list.iter_from(1).map(|(_, ch)| *ch).collect::<String>(),
+            "é\nb"
+        );
+    }
+
+    #[test]
+    fn test_ground_ids_stay_balanced() {
+        // Ids whose leading bytes grow, as if ground so that every new node
+        // outranks the ones before it.
+        let ground = |i: u64| {
+            let mut id = [0; 32];
+            id[..8].copy_from_slice(&i.to_le_bytes());
+            Id(id)
+        };
+        let mut list = WeightedList::new();
+        for i in 0..10_000 {
+            list.insert(i as usize, ground(i), 'x', Weight::UNIT);
+        }
+
+        let mut depth = 0;
+        let mut stack = vec![(list.root, 1)];
+        while let Some((n, d)) = stack.pop() {
+            if n != NIL {
+                depth = depth.max(d);
+                stack.extend([(list.nodes[n].left, d + 1), (list.nodes[n].right, d + 1)]);
+            }
+        }
+        assert!(depth < 100, "depth {depth}");
+
+        while !list.is_empty() {
+            list.remove(list.len() / 2);
+        }
+    }
+
+    #[quickcheck]
+    fn prop_matches_vec_model(ops: Vec<(bool, u8, char)>) {
+        let mut list = WeightedList::new();
+        let mut model: Vec<(Id, char)> = Vec::new();
+        for (i, (insert_or_remove, idx, ch)) in ops.into_iter().enumerate() {
+            let idx = idx as usize;
+            if insert_or_remove {
+                let idx = idx.min(model.len());
+                list.insert(idx, id(i), ch, ch.weight());
+                model.insert(idx, (id(i), ch));
+            } else if !model.is_empty() {
+                let idx = idx.min(model.len() - 1);
+                assert_eq!(list.remove(idx), Some(model[idx].1.weight()));
+                model.remove(idx);
+            }
+        }
+
+        assert_eq!(list.len(), model.len());
+        let mut prefix = Weight::default();
+        for (position, (id, ch)) in model.iter().enumerate() {
+            assert_eq!(list.entry(position), Some((id, ch)));
+            assert_eq!(list.find(id), Some(position));
+            assert_eq!(list.prefix(position), prefix);
+            assert_eq!(list.seek(prefix.utf16, |w| w.utf16), Some(prefix));
+            assert_eq!(list.seek(prefix.utf8, |w| w.utf8), Some(prefix));
+            prefix += ch.weight();
+            if *ch == '\n' {
+                assert_eq!(list.seek(prefix.lines, |w| w.lines), Some(prefix));
+            }
+        }
+        assert_eq!(list.total(), prefix);
+        assert_eq!(list.get(model.len()), None);
+        assert!(list.iter().eq(model.iter().map(|(id, ch)| (id, ch))));
+        assert!(
+            list.iter()
+                .rev()
+                .eq(model.iter().rev().map(|(id, ch)| (id, ch)))
+        );
+        let (start, end) = (model.len() / 3, model.len() * 2 / 3);
+        assert!(
+            list.range(start..end)
+                .eq(model[start..end].iter().map(|(id, ch)| (id, ch)))
+        );
+    }
+}
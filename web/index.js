@@ -11,6 +11,9 @@ await init();
 // Two independent CRDT peers
 const peerA = new WasmHashSeq();
 const peerB = new WasmHashSeq();
+// Remote edits reach the editors through `take_changes`.
+peerA.record_changes(true);
+peerB.record_changes(true);
 
 const lenA = document.getElementById('len-a');
 const lenB = document.getElementById('len-b');
@@ -27,16 +30,17 @@ let updatingB = false;
 function makeUpdateListener(peer, lenEl, flagGetter, flagSetter) {
   return EditorView.updateListener.of((update) => {
     if (!update.docChanged || flagGetter()) return;
+    // CodeMirror positions are UTF-16 offsets, like JS string indices.
     let offset = 0;
     update.changes.iterChanges((fromA, toA, fromB, toB, inserted) => {
       const adjustedFrom = fromA + offset;
       const removedLen = toA - fromA;
       if (removedLen > 0) {
-        peer.remove(adjustedFrom, removedLen);
+        peer.remove_utf16(adjustedFrom, removedLen);
       }
       const text = inserted.toString();
       if (text.length > 0) {
-        peer.insert(adjustedFrom, text);
+        peer.insert_utf16(adjustedFrom, text);
       }
       offset += text.length - removedLen;
     });
@@ -76,13 +80,21 @@ const editorB = new EditorView({
   parent: document.getElementById('editor-b'),
 });
 
-function setEditorContent(editor, text, flag) {
-  const current = editor.state.doc.toString();
-  if (current === text) return;
+function applyChanges(editor, peer, flag) {
+  const changes = peer.take_changes();
+  if (changes.length === 0) return;
   flag(true);
-  editor.dispatch({
-    changes: { from: 0, to: current.length, insert: text },
-  });
+  // Each change is relative to the text after the previous ones, so they are
+  // dispatched one at a time.
+  for (const change of changes) {
+    editor.dispatch({
+      changes: {
+        from: change.index,
+        to: change.index + change.removed_len,
+        insert: change.inserted,
+      },
+    });
+  }
   flag(false);
 }
 
@@ -91,6 +103,10 @@ document.getElementById('sync-btn').addEventListener('click', () => {
   const bytesA = peerA.encode();
   const bytesB = peerB.encode();
 
+  // Local edits are already in the editors.
+  peerA.take_changes();
+  peerB.take_changes();
+
   // Cross-merge
   try {
     peerA.merge_encoded(bytesB);
@@ -113,9 +129,9 @@ document.getElementById('sync-btn').addEventListener('click', () => {
   const textA = peerA.text();
   const textB = peerB.text();
 
-  // Update editors
-  setEditorContent(editorA, textA, (v) => (updatingA = v));
-  setEditorContent(editorB, textB, (v) => (updatingB = v));
+  // Update editors with what the merge changed
+  applyChanges(editorA, peerA, (v) => (updatingA = v));
+  applyChanges(editorB, peerB, (v) => (updatingB = v));
 
   updateLen(peerA, lenA);
   updateLen(peerB, lenB);
@@ -38,10 +38,10 @@ function applyChanges(seq, changes) {
     const adjustedFrom = fromA + offset;
     const removedLen = toA - fromA;
     if (removedLen > 0) {
-      seq.remove(adjustedFrom, removedLen);
+      seq.remove_utf16(adjustedFrom, removedLen);
     }
     if (text.length > 0) {
-      seq.insert(adjustedFrom, text);
+      seq.insert_utf16(adjustedFrom, text);
     }
     offset += text.length - removedLen;
   }
@@ -314,6 +314,41 @@ section('Multi-change: delete + insert (different positions)');
   assert(seq.text(), 'bcdefZ', 'delete at start, insert at end');
 }
 
+section('Non-BMP characters use UTF-16 offsets');
+{
+  const seq = new WasmHashSeq();
+  applyChanges(seq, [[0, 0, 'a🦀b']]);
+  assert(seq.utf16_len(), 4, 'crab is two code units');
+  applyChanges(seq, [[3, 3, 'X']]);
+  assert(seq.text(), 'a🦀Xb', 'insert after the crab');
+  applyChanges(seq, [[1, 3, '']]);
+  assert(seq.text(), 'aXb', 'delete the crab');
+  let threw = false;
+  try {
+    seq.insert_utf16(2, '🦀');
+    seq.insert_utf16(3, 'Y');
+  } catch (e) {
+    threw = true;
+  }
+  assert(threw, true, 'offset inside a surrogate pair is rejected');
+}
+
+section('Sync: changes are reported in UTF-16 offsets');
+{
+  const a = new WasmHashSeq();
+  const b = new WasmHashSeq();
+  b.record_changes(true);
+  a.insert_utf16(0, '🦀🦀');
+  b.merge_encoded(a.encode());
+  b.take_changes();
+  a.insert_utf16(4, '!');
+  b.merge_encoded(a.encode());
+  const changes = b.take_changes();
+  assert(changes.length, 1, 'one change');
+  assert(changes[0].index, 4, 'change index counts code units');
+  assert(a.take_changes().length, 0, 'changes are only recorded when asked');
+}
+
 // ===== Sync / merge tests =====
 
 section('Sync: independent edits merge');